@@ -1,9 +1,12 @@
 use esp_idf_svc::io::EspIOError;
 use esp_idf_svc::sys::EspError;
 
+use ruwm::mqtt::MqttTlsError;
+
 #[derive(Debug)]
 pub enum InitError {
     EspError(EspError),
+    MqttTlsError(MqttTlsError<EspError>),
 }
 
 impl From<EspError> for InitError {
@@ -12,6 +15,12 @@ impl From<EspError> for InitError {
     }
 }
 
+impl From<MqttTlsError<EspError>> for InitError {
+    fn from(e: MqttTlsError<EspError>) -> Self {
+        Self::MqttTlsError(e)
+    }
+}
+
 impl From<EspIOError> for InitError {
     fn from(e: EspIOError) -> Self {
         Self::EspError(e.0)
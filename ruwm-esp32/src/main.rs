@@ -14,6 +14,8 @@ use embassy_time::Duration;
 
 #[cfg(feature = "nvs")]
 use embedded_svc::storage::Storage;
+#[cfg(feature = "nvs")]
+use embedded_svc::wifi::Configuration;
 
 use esp_idf_svc::hal::adc::attenuation;
 use esp_idf_svc::hal::gpio::*;
@@ -25,13 +27,17 @@ use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_svc::sys::esp;
 use esp_idf_svc::timer::EspTaskTimerService;
-use esp_idf_svc::wifi::AuthMethod;
 
+use ruwm::audit::BootReason;
+#[cfg(feature = "nvs")]
+use ruwm::audit::{AuditLog, AUDIT_LOG_SIZE};
+#[cfg(feature = "nvs")]
+use ruwm::mqtt::MqttConfiguration;
 use ruwm::quit;
 use ruwm::spawn;
+use ruwm::users::{Users, USERS_MAX};
 use ruwm::wifi;
 use ruwm::wm::WaterMeterState;
-use ruwm::ws;
 
 use crate::errors::*;
 use crate::peripherals::{ButtonsPeripherals, PulseCounterPeripherals};
@@ -45,11 +51,9 @@ mod ulp_pulse_counter;
 #[cfg(all(feature = "ulp", not(any(esp32, esp32s2, esp32s3))))]
 compile_error!("Feature `ulp` is supported only on esp32, esp32s2 and esp32s3");
 
-const SSID: &str = env!("RUWM_WIFI_SSID");
-const PASS: &str = env!("RUWM_WIFI_PASS");
-
 const SLEEP_TIME: Duration = Duration::from_secs(30);
-const MQTT_MAX_TOPIC_LEN: usize = 64;
+const MQTT_MAX_TOPIC_LEN: usize = 128;
+const MQTT_MAX_PAYLOAD_LEN: usize = 256;
 
 // Make sure that the firmware will contain
 // up-to-date build time and package info coming from the binary crate
@@ -65,23 +69,16 @@ fn main() -> Result<(), InitError> {
 
     log::info!("Wakeup reason: {:?}", wakeup_reason);
 
-    // TODO: Persist the Wifi configuration in NVS, and start with an open AP,
-    // or whatever configuration the user has set via the UI
-    wifi::COMMAND.signal(wifi::WifiCommand::SetConfiguration(
-        embedded_svc::wifi::Configuration::Client(embedded_svc::wifi::ClientConfiguration {
-            ssid: SSID.try_into().unwrap(),
-            password: PASS.try_into().unwrap(),
-            auth_method: if PASS.is_empty() {
-                AuthMethod::None
-            } else {
-                Default::default()
-            },
-            ..Default::default()
-        }),
-    ));
-
     std::thread::scope(|scope| run(scope, wakeup_reason))?;
 
+    // The RAM does not survive the deep sleep, so the pulse trace is kept in RTC memory
+    unsafe {
+        services::RTC_MEMORY.tracing = ruwm::wm::TRACING.get();
+        services::RTC_MEMORY.trace = ruwm::wm::trace();
+        services::RTC_MEMORY.trace_time_ms = ruwm::wm::trace_time_ms();
+        services::RTC_MEMORY.trace_saved_at_ms = services::rtc_time_ms();
+    }
+
     log::info!("Going to sleep now");
 
     sleep()?;
@@ -151,14 +148,114 @@ fn run<'s>(scope: &'s Scope<'s, '_>, wakeup_reason: WakeupReason) -> Result<(),
     #[cfg(not(feature = "nvs"))]
     let wm_state: WaterMeterState = Default::default();
 
+    #[cfg(feature = "nvs")]
+    let mqtt_configuration = storage
+        .lock(|storage| storage.borrow().get::<MqttConfiguration>("mqtt-conf"))
+        .unwrap();
+
+    #[cfg(not(feature = "nvs"))]
+    let mqtt_configuration = unsafe { services::RTC_MEMORY.mqtt_configuration.clone() };
+
+    #[cfg(feature = "nvs")]
+    let wifi_configuration = storage
+        .lock(|storage| storage.borrow().get::<Configuration>("wifi-conf"))
+        .unwrap();
+
+    #[cfg(not(feature = "nvs"))]
+    let wifi_configuration = unsafe { services::RTC_MEMORY.wifi_configuration.clone() };
+
+    // Without a configuration, start an open access point so that the device can be set up via the UI
+    wifi::CONFIGURATION.set(Some(wifi_configuration.unwrap_or_else(|| {
+        log::warn!("No WiFi configuration found, starting an open access point");
+
+        wifi::access_point()
+    })));
+
+    if mqtt_configuration.is_none() {
+        log::warn!("No MQTT configuration found, MQTT is disabled until configured");
+    }
+
+    ruwm::mqtt::CONFIGURATION.set(mqtt_configuration);
+
+    #[cfg(feature = "nvs")]
+    let users = storage
+        .lock(|storage| storage.borrow().get::<Users<USERS_MAX>>("users"))
+        .unwrap();
+
+    #[cfg(not(feature = "nvs"))]
+    let users = unsafe { Some(services::RTC_MEMORY.users.clone()) };
+
+    let users = users.unwrap_or_default();
+
+    if users.is_empty() {
+        log::warn!("No user accounts found, the first web login will create the admin account");
+    }
+
+    ruwm::users::STATE.set(users);
+
+    #[cfg(feature = "nvs")]
+    let audit_log = storage
+        .lock(|storage| {
+            storage
+                .borrow()
+                .get::<AuditLog<AUDIT_LOG_SIZE>>("audit-log")
+        })
+        .unwrap();
+
+    #[cfg(not(feature = "nvs"))]
+    let audit_log = unsafe { Some(services::RTC_MEMORY.audit_log.clone()) };
+
+    ruwm::audit::LOG.lock(|log| *log.borrow_mut() = audit_log.unwrap_or_default());
+
+    let mut session_key = [0_u8; 32];
+
+    unsafe {
+        esp_idf_svc::sys::esp_fill_random(
+            session_key.as_mut_ptr() as *mut _,
+            session_key.len() as _,
+        );
+    }
+
+    ruwm::users::set_session_key(session_key);
+
+    ruwm::metrics::set_free_heap_hook(
+        || unsafe { esp_idf_svc::sys::esp_get_free_heap_size() } as usize
+    );
+
+    ruwm::mqtt::set_clock_hook(|| services::rtc_time_ms() / 1000);
+
     unsafe {
         services::RTC_MEMORY.wm = wm_state;
 
         ruwm::valve::STATE.set(services::RTC_MEMORY.valve);
+        ruwm::emergency::STATE.set(services::RTC_MEMORY.lockout);
         ruwm::wm::STATE.set(services::RTC_MEMORY.wm);
         ruwm::wm_stats::STATE.set(services::RTC_MEMORY.wm_stats);
+
+        let slept_ms =
+            services::rtc_time_ms().saturating_sub(services::RTC_MEMORY.trace_saved_at_ms);
+
+        ruwm::wm::restore_trace(
+            services::RTC_MEMORY.tracing,
+            services::RTC_MEMORY.trace.clone(),
+            services::RTC_MEMORY
+                .trace_time_ms
+                .wrapping_add(slept_ms as u32),
+        );
+
+        ruwm::mqtt::OUTBOX
+            .lock(|outbox| *outbox.borrow_mut() = services::RTC_MEMORY.mqtt_outbox.clone());
     }
 
+    // Recorded only now, so that the event is queued in the restored MQTT outbox
+    ruwm::audit::boot(match wakeup_reason {
+        WakeupReason::Unknown => BootReason::PowerOn,
+        WakeupReason::Timer => BootReason::Timer,
+        WakeupReason::ULP => BootReason::Pulse,
+        WakeupReason::Button => BootReason::Button,
+        _ => BootReason::Other,
+    });
+
     // Pulse counter
 
     #[cfg(feature = "ulp")]
@@ -192,6 +289,9 @@ fn run<'s>(scope: &'s Scope<'s, '_>, wakeup_reason: WakeupReason) -> Result<(),
                 |state| unsafe {
                     services::RTC_MEMORY.valve = state;
                 },
+                |state| unsafe {
+                    services::RTC_MEMORY.lockout = state;
+                },
                 pulse_counter,
                 pulse_wakeup,
                 |state| unsafe {
@@ -233,7 +333,7 @@ fn run<'s>(scope: &'s Scope<'s, '_>, wakeup_reason: WakeupReason) -> Result<(),
     let mid_prio_execution = std::thread::Builder::new()
         .stack_size(60000)
         .spawn_scoped(scope, move || {
-            let executor = LocalExecutor::<8>::new();
+            let executor = LocalExecutor::<16>::new();
 
             // Wifi
 
@@ -244,20 +344,55 @@ fn run<'s>(scope: &'s Scope<'s, '_>, wakeup_reason: WakeupReason) -> Result<(),
                 Some(nvs_default_partition.clone()),
             )?;
 
-            spawn::wifi(&executor, &mut wifi);
-
-            // Mqtt
+            spawn::wifi(&executor, &mut wifi, move |_configuration| {
+                unsafe {
+                    services::RTC_MEMORY.wifi_configuration = _configuration.clone();
+                }
 
-            let (mqtt_topic_prefix, mut mqtt_client, mut mqtt_conn) = services::mqtt()?;
+                #[cfg(feature = "nvs")]
+                flash(storage, "wifi-conf", _configuration);
+            });
 
-            spawn::mqtt_receive(&executor, &mut mqtt_conn);
+            // Mqtt
 
-            spawn::mqtt_send::<MQTT_MAX_TOPIC_LEN, 8>(
+            spawn::mqtt::<MQTT_MAX_TOPIC_LEN, MQTT_MAX_PAYLOAD_LEN, 8, _, _, _, _>(
                 &executor,
-                mqtt_topic_prefix,
-                &mut mqtt_client,
+                services::mqtt(nvs_default_partition.clone())?,
+                move |_configuration| {
+                    unsafe {
+                        services::RTC_MEMORY.mqtt_configuration = _configuration.clone();
+                    }
+
+                    #[cfg(feature = "nvs")]
+                    flash(storage, "mqtt-conf", _configuration);
+                },
+                |outbox| unsafe {
+                    services::RTC_MEMORY.mqtt_outbox = outbox;
+                },
             );
 
+            // Users
+
+            spawn::users(&executor, move |_users| {
+                unsafe {
+                    services::RTC_MEMORY.users = _users.clone();
+                }
+
+                #[cfg(feature = "nvs")]
+                flash(storage, "users", Some(_users));
+            });
+
+            // Audit log
+
+            spawn::audit(&executor, move |_log| {
+                unsafe {
+                    services::RTC_MEMORY.audit_log = _log.clone();
+                }
+
+                #[cfg(feature = "nvs")]
+                flash(storage, "audit-log", Some(_log));
+            });
+
             // Httpd
 
             let mut httpd = services::httpd()?;
@@ -267,10 +402,6 @@ fn run<'s>(scope: &'s Scope<'s, '_>, wakeup_reason: WakeupReason) -> Result<(),
 
             executor.spawn(httpd).detach();
 
-            // WS
-
-            executor.spawn(ws::broadcast()).detach();
-
             block_on(executor.run(quit::QUIT[1].wait()));
 
             Ok(())
@@ -298,7 +429,7 @@ fn run<'s>(scope: &'s Scope<'s, '_>, wakeup_reason: WakeupReason) -> Result<(),
 
             spawn::low_prio(&executor, &mut display, move |_new_state| {
                 #[cfg(feature = "nvs")]
-                flash_wm_state(storage, _new_state);
+                flash(storage, "wm-state", Some(_new_state));
             });
 
             block_on(executor.run(quit::QUIT[2].wait()));
@@ -325,19 +456,25 @@ fn run<'s>(scope: &'s Scope<'s, '_>, wakeup_reason: WakeupReason) -> Result<(),
 }
 
 #[cfg(feature = "nvs")]
-fn flash_wm_state<S>(
+fn flash<S, T>(
     storage: &'static Mutex<
         impl embassy_sync::blocking_mutex::raw::RawMutex,
         core::cell::RefCell<S>,
     >,
-    new_state: WaterMeterState,
+    name: &str,
+    new_state: Option<T>,
 ) where
     S: Storage,
+    T: serde::Serialize + serde::de::DeserializeOwned + PartialEq,
 {
     ruwm::log_err!(storage.lock(|storage| {
-        let old_state = storage.borrow().get("wm-state")?;
-        if old_state != Some(new_state) {
-            storage.borrow_mut().set("wm-state", &new_state)?;
+        let old_state = storage.borrow().get::<T>(name)?;
+        if old_state != new_state {
+            if let Some(new_state) = new_state.as_ref() {
+                storage.borrow_mut().set(name, new_state)?;
+            } else {
+                storage.borrow_mut().remove(name)?;
+            }
         }
 
         Ok::<_, S::Error>(())
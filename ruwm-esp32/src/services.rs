@@ -2,6 +2,7 @@ use core::fmt::Debug;
 use core::mem;
 use std::cell::UnsafeCell;
 use std::mem::MaybeUninit;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 extern crate alloc;
 
@@ -24,8 +25,10 @@ use embedded_hal::digital::OutputPin as EHOutputPin;
 
 use embedded_io_async::{Read, Write};
 use embedded_svc::http::server::asynch::Request;
-use embedded_svc::mqtt::client::asynch::{Client, Connection, Publish};
+use embedded_svc::mqtt::client::asynch::{Client, ErrorType, MessageId, Publish, QoS};
+use embedded_svc::storage::RawStorage;
 use embedded_svc::wifi::asynch::Wifi;
+use embedded_svc::wifi::Configuration;
 
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::adc::{Adc, AdcChannelDriver, AdcConfig, AdcDriver};
@@ -38,21 +41,30 @@ use esp_idf_svc::hal::prelude::*;
 use esp_idf_svc::hal::reset::WakeupReason;
 use esp_idf_svc::hal::spi::*;
 
-use esp_idf_svc::mqtt::client::{EspAsyncMqttClient, MqttClientConfiguration};
-use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::mqtt::client::{
+    EspAsyncMqttClient, EspAsyncMqttConnection, MqttClientConfiguration, MqttProtocolVersion,
+};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
 use esp_idf_svc::timer::EspTaskTimerService;
+use esp_idf_svc::tls::X509;
 use esp_idf_svc::wifi::{AsyncWifi, EspWifi};
 
 use esp_idf_svc::sys::{adc_atten_t, EspError};
 
 use gfx_xtra::draw_target::{Flushable, OwnedDrawTargetExt};
 
+use ruwm::audit::{AuditLog, AUDIT_LOG_SIZE};
 use ruwm::button::PressedLevel;
+use ruwm::emergency::LockoutState;
+use ruwm::mqtt::{
+    MqttCertificate, MqttCertificates, MqttConfiguration, MqttOutbox, OverflowPolicy,
+};
 use ruwm::pulse_counter::PulseCounter;
 use ruwm::pulse_counter::PulseWakeup;
 use ruwm::screen::Color;
+use ruwm::users::{Users, USERS_MAX};
 use ruwm::valve::{self, ValveState};
-use ruwm::wm::WaterMeterState;
+use ruwm::wm::{PulseTrace, WaterMeterState, PULSE_TRACE_SIZE};
 use ruwm::wm_stats::WaterMeterStatsState;
 use ruwm::ws::{WS_MAX_CONNECTIONS, WS_MAX_FRAME_LEN};
 
@@ -64,16 +76,38 @@ const ASSETS: assets::serve::Assets = edge_frame::assets!("RUWM_WEB");
 #[derive(Default)]
 pub struct RtcMemory {
     pub valve: Option<ValveState>,
+    pub lockout: Option<LockoutState>,
     pub wm: WaterMeterState,
+    pub tracing: bool,
+    pub trace: PulseTrace<PULSE_TRACE_SIZE>,
+    /// `wm::trace_time_ms` when the trace was saved
+    pub trace_time_ms: u32,
+    /// `rtc_time_ms` when the trace was saved
+    pub trace_saved_at_ms: u64,
     pub wm_stats: WaterMeterStatsState,
+    pub mqtt_configuration: Option<MqttConfiguration>,
+    pub mqtt_outbox: MqttOutbox,
+    pub users: Users<USERS_MAX>,
+    pub wifi_configuration: Option<Configuration>,
+    pub audit_log: AuditLog<AUDIT_LOG_SIZE>,
 }
 
 impl RtcMemory {
     pub const fn new() -> Self {
         Self {
             valve: None,
+            lockout: None,
             wm: WaterMeterState::new(),
+            tracing: false,
+            trace: PulseTrace::new(),
+            trace_time_ms: 0,
+            trace_saved_at_ms: 0,
             wm_stats: WaterMeterStatsState::new(),
+            mqtt_configuration: None,
+            mqtt_outbox: MqttOutbox::new(OverflowPolicy::DropOldest),
+            users: Users::new(),
+            wifi_configuration: None,
+            audit_log: AuditLog::new(),
         }
     }
 }
@@ -81,6 +115,14 @@ impl RtcMemory {
 #[cfg_attr(feature = "rtc-mem", link_section = ".rtc.data.rtc_memory")]
 pub static mut RTC_MEMORY: RtcMemory = RtcMemory::new();
 
+/// Milliseconds on the RTC clock, which - unlike `Instant` - keeps counting during deep sleep
+pub fn rtc_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 pub fn valve_pins(
     peripherals: ValvePeripherals,
     wakeup_reason: WakeupReason,
@@ -111,7 +153,7 @@ pub fn storage(
     >,
     InitError,
 > {
-    const POSTCARD_BUF_SIZE: usize = 500;
+    const POSTCARD_BUF_SIZE: usize = 1024;
 
     struct PostcardSerDe;
 
@@ -359,8 +401,22 @@ impl<'a> HttpdHandler<'a> {
     where
         T: Read + Write + TcpSplittableConnection,
     {
-        if matches!(con.headers().unwrap().method, Some(Method::Get)) {
-            if matches!(con.headers()?.path, Some("/ws")) {
+        if con
+            .headers()?
+            .path
+            .is_some_and(|path| path.starts_with(ruwm::api::API_PREFIX))
+        {
+            ruwm::api::handle(Request::wrap(con)).await?;
+        } else if matches!(con.headers()?.method, Some(Method::Get)) {
+            if con.headers()?.path == Some(ruwm::metrics::METRICS_PATH) {
+                ruwm::metrics::handle(Request::wrap(con)).await?;
+            } else if con
+                .headers()?
+                .path
+                .is_some_and(|path| path.split('?').next() == Some(ruwm::sse::EVENTS_PATH))
+            {
+                ruwm::sse::handle(Request::wrap(con)).await?;
+            } else if matches!(con.headers()?.path, Some("/ws")) {
                 let send_buf = &mut unsafe {
                     self.send_bufs.get().as_mut().unwrap().assume_init_mut()[task_id]
                 };
@@ -368,7 +424,7 @@ impl<'a> HttpdHandler<'a> {
                     self.recv_bufs.get().as_mut().unwrap().assume_init_mut()[task_id]
                 };
 
-                self.handle_ws(task_id, send_buf, recv_buf, con).await?;
+                self.handle_ws(send_buf, recv_buf, con).await?;
             } else {
                 self.handle_assets(con).await?;
             }
@@ -381,7 +437,6 @@ impl<'a> HttpdHandler<'a> {
 
     async fn handle_ws<'b, T, const N: usize>(
         &self,
-        task_id: usize,
         send_buf: &mut [u8],
         recv_buf: &mut [u8],
         con: &mut io::server::Connection<'b, T, N>,
@@ -404,7 +459,7 @@ impl<'a> HttpdHandler<'a> {
             let sender = WsConnection::new(write, || None);
             let receiver = WsConnection::new(read, || Option::<()>::None);
 
-            ruwm::ws::handle(sender, send_buf, receiver, recv_buf, task_id).await?;
+            ruwm::ws::handle(sender, send_buf, receiver, recv_buf).await?;
         } else {
             con.initiate_response(200, None, &[("Content-Length", "0")])
                 .await?;
@@ -482,26 +537,105 @@ where
     Ok(())
 }
 
-#[inline(always)]
-pub fn mqtt() -> Result<
-    (
-        &'static str,
-        impl Client + Publish,
-        impl Connection + 'static,
-    ),
+const MQTT_CERTIFICATE_BUF_SIZE: usize = 4096;
+
+pub struct MqttClient {
+    client: EspAsyncMqttClient,
+    // The ESP-IDF MQTT client does not copy the certificates, hence they need to outlive it
+    // Declared after `client` so that they are dropped after it
+    _certificates: Vec<u8>,
+}
+
+impl ErrorType for MqttClient {
+    type Error = EspError;
+}
+
+impl Client for MqttClient {
+    async fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<MessageId, Self::Error> {
+        self.client.subscribe(topic, qos).await
+    }
+
+    async fn unsubscribe(&mut self, topic: &str) -> Result<MessageId, Self::Error> {
+        self.client.unsubscribe(topic).await
+    }
+}
+
+impl Publish for MqttClient {
+    async fn publish(
+        &mut self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<MessageId, Self::Error> {
+        self.client.publish(topic, qos, retain, payload).await
+    }
+}
+
+pub fn mqtt(
+    partition: EspDefaultNvsPartition,
+) -> Result<
+    impl FnMut(&MqttConfiguration) -> Result<(MqttClient, EspAsyncMqttConnection), InitError>,
     InitError,
 > {
-    // TODO: Persist the MQTT configuration in NVS, and start with disabled MQTT,
-    // or whatever configuration the user has set via the UI
+    let certificates = EspNvs::new(partition, "certs", true)?;
+
+    Ok(move |configuration: &MqttConfiguration| mqtt_client(configuration, &certificates))
+}
+
+fn mqtt_client(
+    configuration: &MqttConfiguration,
+    certificates_storage: &impl RawStorage<Error = EspError>,
+) -> Result<(MqttClient, EspAsyncMqttConnection), InitError> {
+    let buf_size = if configuration.is_tls() {
+        MQTT_CERTIFICATE_BUF_SIZE
+    } else {
+        0
+    };
 
-    let client_id = "water-meter-demo";
-    let (mqtt_client, mqtt_conn) = EspAsyncMqttClient::new(
-        "mqtt://broker.emqx.io:1883",
+    let mut certificates_buf = vec![0; buf_size * 3];
+
+    let (ca_certificate_buf, buf) = certificates_buf.split_at_mut(buf_size);
+    let (client_certificate_buf, client_key_buf) = buf.split_at_mut(buf_size);
+
+    let certificates = MqttCertificates::load(
+        configuration,
+        certificates_storage,
+        ca_certificate_buf,
+        client_certificate_buf,
+        client_key_buf,
+    )?;
+
+    let (client, connection) = EspAsyncMqttClient::new(
+        &configuration.url,
         &MqttClientConfiguration {
-            client_id: Some(client_id),
+            protocol_version: configuration
+                .protocol_311
+                .then_some(MqttProtocolVersion::V3_1_1),
+            client_id: Some(&configuration.client_id),
+            username: (!configuration.username.is_empty())
+                .then_some(configuration.username.as_str()),
+            password: (!configuration.password.is_empty())
+                .then_some(configuration.password.as_str()),
+            server_certificate: certificates.ca_certificate.map(x509),
+            client_certificate: certificates.client_certificate.map(x509),
+            private_key: certificates.client_key.map(x509),
             ..Default::default()
         },
     )?;
 
-    Ok((client_id, mqtt_client, mqtt_conn))
+    Ok((
+        MqttClient {
+            client,
+            _certificates: certificates_buf,
+        },
+        connection,
+    ))
+}
+
+fn x509(certificate: MqttCertificate<'_>) -> X509<'_> {
+    match certificate {
+        MqttCertificate::Pem(pem) => X509::pem_until_nul(pem),
+        MqttCertificate::Der(der) => X509::der(der),
+    }
 }
@@ -0,0 +1,72 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use embassy_time::{Duration, Instant};
+
+use ruwm::battery::Adc;
+
+struct AdcState {
+    value: u16,
+    script: VecDeque<(Instant, u16)>,
+}
+
+/// A battery ADC returning the voltage (in millivolts) set by the test, either directly or by a script
+#[derive(Clone)]
+pub struct MockAdc(Rc<RefCell<AdcState>>);
+
+impl MockAdc {
+    pub fn new(value: u16) -> Self {
+        Self(Rc::new(RefCell::new(AdcState {
+            value,
+            script: VecDeque::new(),
+        })))
+    }
+
+    pub fn value(&self) -> u16 {
+        let mut state = self.0.borrow_mut();
+
+        let now = Instant::now();
+
+        while let Some((at, value)) = state.script.front().copied() {
+            if at > now {
+                break;
+            }
+
+            state.script.pop_front();
+            state.value = value;
+        }
+
+        state.value
+    }
+
+    pub fn set_value(&self, value: u16) {
+        let mut state = self.0.borrow_mut();
+
+        state.script.clear();
+        state.value = value;
+    }
+
+    /// Schedules value changes, each one relative to the previous one, the first one relative to now
+    pub fn script(&self, changes: impl IntoIterator<Item = (Duration, u16)>) {
+        let mut at = Instant::now();
+
+        self.0.borrow_mut().script = changes
+            .into_iter()
+            .map(|(after, value)| {
+                at += after;
+                (at, value)
+            })
+            .collect();
+    }
+}
+
+impl Adc for MockAdc {
+    type Error = Infallible;
+
+    async fn read(&mut self) -> Result<u16, Self::Error> {
+        Ok(self.value())
+    }
+}
@@ -0,0 +1,183 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use std::rc::Rc;
+
+use embedded_graphics::pixelcolor::PixelColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::Pixel;
+
+use gfx_xtra::draw_target::Flushable;
+
+use ruwm::screen::Color;
+
+/// The pixels of a display at some point in time
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<C> {
+    size: Size,
+    pixels: Vec<C>,
+}
+
+impl<C> Snapshot<C>
+where
+    C: Copy + PartialEq,
+{
+    pub fn new(size: Size, background: C) -> Self {
+        Self {
+            size,
+            pixels: vec![background; size.width as usize * size.height as usize],
+        }
+    }
+
+    pub fn from_pixels(size: Size, pixels: Vec<C>) -> Option<Self> {
+        (pixels.len() == size.width as usize * size.height as usize)
+            .then_some(Self { size, pixels })
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn pixel(&self, point: Point) -> Option<C> {
+        self.index(point).map(|index| self.pixels[index])
+    }
+
+    /// The pixels, row by row
+    pub fn pixels(&self) -> &[C] {
+        &self.pixels
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[C]> {
+        self.pixels.chunks(self.size.width as usize)
+    }
+
+    /// The points where the two snapshots differ, or `None` if they are of different sizes
+    pub fn diff(&self, other: &Self) -> Option<Vec<Point>> {
+        (self.size == other.size).then(|| {
+            self.pixels
+                .iter()
+                .zip(other.pixels.iter())
+                .enumerate()
+                .filter(|(_, (pixel, other))| pixel != other)
+                .map(|(index, _)| self.point(index))
+                .collect()
+        })
+    }
+
+    /// The smallest rectangle containing all the differences, if the snapshots are of the same size and do differ
+    pub fn diff_bounds(&self, other: &Self) -> Option<Rectangle> {
+        let diff = self.diff(other)?;
+
+        let top_left = Point::new(
+            diff.iter().map(|point| point.x).min()?,
+            diff.iter().map(|point| point.y).min()?,
+        );
+
+        let bottom_right = Point::new(
+            diff.iter().map(|point| point.x).max()?,
+            diff.iter().map(|point| point.y).max()?,
+        );
+
+        Some(Rectangle::with_corners(top_left, bottom_right))
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        (point.x >= 0
+            && point.y >= 0
+            && (point.x as u32) < self.size.width
+            && (point.y as u32) < self.size.height)
+            .then(|| point.y as usize * self.size.width as usize + point.x as usize)
+    }
+
+    fn point(&self, index: usize) -> Point {
+        Point::new(
+            (index % self.size.width as usize) as _,
+            (index / self.size.width as usize) as _,
+        )
+    }
+}
+
+struct Frame<C> {
+    drawn: Snapshot<C>,
+    flushed: Snapshot<C>,
+    flushes: usize,
+}
+
+/// An in-memory display.
+///
+/// Keeps both what was drawn so far, and what was drawn as of the last flush, i.e. what a real display would show.
+#[derive(Clone)]
+pub struct MockDisplay<C = Color>(Rc<RefCell<Frame<C>>>);
+
+impl<C> MockDisplay<C>
+where
+    C: Copy + PartialEq,
+{
+    pub fn new(size: Size, background: C) -> Self {
+        let snapshot = Snapshot::new(size, background);
+
+        Self(Rc::new(RefCell::new(Frame {
+            drawn: snapshot.clone(),
+            flushed: snapshot,
+            flushes: 0,
+        })))
+    }
+
+    pub fn drawn(&self) -> Snapshot<C> {
+        self.0.borrow().drawn.clone()
+    }
+
+    pub fn flushed(&self) -> Snapshot<C> {
+        self.0.borrow().flushed.clone()
+    }
+
+    /// How many times the display was flushed so far
+    pub fn flushes(&self) -> usize {
+        self.0.borrow().flushes
+    }
+}
+
+impl<C> OriginDimensions for MockDisplay<C> {
+    fn size(&self) -> Size {
+        self.0.borrow().drawn.size
+    }
+}
+
+impl<C> DrawTarget for MockDisplay<C>
+where
+    C: PixelColor,
+{
+    type Color = C;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let mut frame = self.0.borrow_mut();
+        let drawn = &mut frame.drawn;
+
+        for Pixel(point, color) in pixels {
+            if let Some(index) = drawn.index(point) {
+                drawn.pixels[index] = color;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<C> Flushable for MockDisplay<C>
+where
+    C: PixelColor,
+{
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let mut frame = self.0.borrow_mut();
+
+        frame.flushed = frame.drawn.clone();
+        frame.flushes += 1;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+
+use embassy_time::{Duration, Instant};
+
+use crate::signal::Changes;
+
+struct InputState {
+    high: bool,
+    edges: u64,
+    script: VecDeque<(Instant, bool)>,
+}
+
+impl InputState {
+    /// Applies the scripted levels which are due
+    fn sync(&mut self, now: Instant) {
+        while let Some((at, high)) = self.script.front().copied() {
+            if at > now {
+                break;
+            }
+
+            self.script.pop_front();
+            self.set(high);
+        }
+    }
+
+    fn set(&mut self, high: bool) {
+        if self.high != high {
+            self.high = high;
+            self.edges += 1;
+        }
+    }
+}
+
+/// An input pin driven by the test, either directly or by a script of timed level changes
+#[derive(Clone)]
+pub struct MockInputPin(Rc<(RefCell<InputState>, Changes)>);
+
+impl MockInputPin {
+    pub fn new(high: bool) -> Self {
+        Self(Rc::new((
+            RefCell::new(InputState {
+                high,
+                edges: 0,
+                script: VecDeque::new(),
+            }),
+            Changes::default(),
+        )))
+    }
+
+    pub fn level(&self) -> bool {
+        let mut state = self.0 .0.borrow_mut();
+
+        state.sync(Instant::now());
+        state.high
+    }
+
+    pub fn set_level(&self, high: bool) {
+        self.0 .0.borrow_mut().set(high);
+        self.0 .1.notify();
+    }
+
+    /// Schedules level changes, each one relative to the previous one, the first one relative to now.
+    /// Replaces any changes still pending from a previous script.
+    pub fn script(&self, changes: impl IntoIterator<Item = (Duration, bool)>) {
+        let mut at = Instant::now();
+
+        self.0 .0.borrow_mut().script = changes
+            .into_iter()
+            .map(|(after, high)| {
+                at += after;
+                (at, high)
+            })
+            .collect();
+
+        self.0 .1.notify();
+    }
+
+    /// Schedules a press of a button wired as active low, lasting `duration`
+    pub fn click(&self, after: Duration, duration: Duration) {
+        self.script([(after, false), (duration, true)]);
+    }
+
+    async fn wait_until(&self, condition: impl Fn(bool, bool) -> bool) {
+        let edges = self.0 .0.borrow().edges;
+
+        loop {
+            let next = {
+                let mut state = self.0 .0.borrow_mut();
+
+                state.sync(Instant::now());
+
+                if condition(state.high, state.edges != edges) {
+                    break;
+                }
+
+                state.script.front().map(|(at, _)| *at)
+            };
+
+            self.0 .1.wait(next).await;
+        }
+    }
+}
+
+impl ErrorType for MockInputPin {
+    type Error = Infallible;
+}
+
+impl InputPin for MockInputPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.level())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.level())
+    }
+}
+
+impl Wait for MockInputPin {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait_until(|high, _| high).await;
+
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait_until(|high, _| !high).await;
+
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_until(|high, edged| high && edged).await;
+
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_until(|high, edged| !high && edged).await;
+
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_until(|_, edged| edged).await;
+
+        Ok(())
+    }
+}
+
+/// An output pin which records every level it is set to, with the time it was set
+#[derive(Clone, Default)]
+pub struct MockOutputPin(Rc<RefCell<Vec<(Instant, bool)>>>);
+
+impl MockOutputPin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current level; low if the pin was never set
+    pub fn level(&self) -> bool {
+        self.0
+            .borrow()
+            .last()
+            .map(|(_, high)| *high)
+            .unwrap_or(false)
+    }
+
+    pub fn history(&self) -> Vec<(Instant, bool)> {
+        self.0.borrow().clone()
+    }
+
+    /// The levels the pin was set to, without the repetitions
+    pub fn transitions(&self) -> Vec<bool> {
+        let mut transitions: Vec<bool> = Vec::new();
+
+        for (_, high) in self.0.borrow().iter() {
+            if transitions.last() != Some(high) {
+                transitions.push(*high);
+            }
+        }
+
+        transitions
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    fn set(&self, high: bool) {
+        self.0.borrow_mut().push((Instant::now(), high));
+    }
+}
+
+impl ErrorType for MockOutputPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for MockOutputPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false);
+
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true);
+
+        Ok(())
+    }
+}
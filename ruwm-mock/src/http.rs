@@ -0,0 +1,224 @@
+use core::cell::RefCell;
+
+use std::rc::Rc;
+
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+use embedded_svc::http::server::asynch::Connection;
+use embedded_svc::http::{Headers, Method, Query};
+
+/// The errors of `MockConnection`; only the raw connection is not supported
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MockHttpError {
+    Unsupported,
+}
+
+impl embedded_io_async::Error for MockHttpError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Unsupported
+    }
+}
+
+/// The request line and the headers of a `MockConnection`
+pub struct MockRequest {
+    method: Method,
+    uri: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Query for MockRequest {
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn method(&self) -> Method {
+        self.method
+    }
+}
+
+impl Headers for MockRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The body of a `MockConnection`, read in chunks of at most `chunk_len` bytes
+pub struct MockBody {
+    data: Vec<u8>,
+    offset: usize,
+    chunk_len: usize,
+}
+
+impl ErrorType for MockBody {
+    type Error = MockHttpError;
+}
+
+impl Read for MockBody {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = buf
+            .len()
+            .min(self.chunk_len)
+            .min(self.data.len() - self.offset);
+
+        buf[..len].copy_from_slice(&self.data[self.offset..self.offset + len]);
+        self.offset += len;
+
+        Ok(len)
+    }
+}
+
+/// What a request handler responded with
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The response recorded by a `MockConnection`, shared with its clones.
+///
+/// Lets a test inspect a response as it is being streamed, by a handler which owns the connection.
+#[derive(Clone, Default)]
+pub struct MockResponseHandle(Rc<RefCell<Option<MockResponse>>>);
+
+impl MockResponseHandle {
+    /// The response so far; `None` until the handler has initiated it
+    pub fn get(&self) -> Option<MockResponse> {
+        self.0.borrow().clone()
+    }
+}
+
+/// An HTTP server connection carrying a single request, so that request handlers can be called directly.
+///
+/// The body is handed out in small chunks, like a socket would do, and the response is recorded.
+pub struct MockConnection {
+    request: MockRequest,
+    body: MockBody,
+    response: MockResponseHandle,
+}
+
+impl MockConnection {
+    pub fn new(method: Method, uri: &str, headers: &[(&str, &str)], body: &[u8]) -> Self {
+        Self {
+            request: MockRequest {
+                method,
+                uri: uri.to_string(),
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            },
+            body: MockBody {
+                data: body.to_vec(),
+                offset: 0,
+                chunk_len: 16,
+            },
+            response: MockResponseHandle::default(),
+        }
+    }
+
+    /// The response, once the handler has initiated it
+    pub fn response(&self) -> Option<MockResponse> {
+        self.response.get()
+    }
+
+    pub fn response_handle(&self) -> MockResponseHandle {
+        self.response.clone()
+    }
+}
+
+impl Query for MockConnection {
+    fn uri(&self) -> &str {
+        self.request.uri()
+    }
+
+    fn method(&self) -> Method {
+        self.request.method()
+    }
+}
+
+impl Headers for MockConnection {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.request.header(name)
+    }
+}
+
+impl ErrorType for MockConnection {
+    type Error = MockHttpError;
+}
+
+impl Read for MockConnection {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.body.read(buf).await
+    }
+}
+
+impl Write for MockConnection {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut response = self.response.0.borrow_mut();
+
+        response
+            .as_mut()
+            .expect("Response not initiated yet")
+            .body
+            .extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+}
+
+impl Connection for MockConnection {
+    type Headers = MockRequest;
+
+    type Read = MockBody;
+
+    type RawConnectionError = MockHttpError;
+
+    type RawConnection = Self;
+
+    fn split(&mut self) -> (&Self::Headers, &mut Self::Read) {
+        (&self.request, &mut self.body)
+    }
+
+    async fn initiate_response(
+        &mut self,
+        status: u16,
+        _message: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> Result<(), Self::Error> {
+        let mut response = self.response.0.borrow_mut();
+
+        assert!(response.is_none(), "Response already initiated");
+
+        *response = Some(MockResponse {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    fn is_response_initiated(&self) -> bool {
+        self.response.0.borrow().is_some()
+    }
+
+    fn raw_connection(&mut self) -> Result<&mut Self::RawConnection, Self::Error> {
+        Err(MockHttpError::Unsupported)
+    }
+}
@@ -0,0 +1,23 @@
+//! Mock peripherals for running the Rust Water Meter on the host - in tests, or in a native simulator -
+//! as well as a mock HTTP connection for calling the request handlers directly.
+//!
+//! All mocks are single-threaded, and cheaply cloneable: the clones share their state, so that
+//! one clone can be handed over to the system, while another is kept to drive or inspect it.
+//!
+//! Scripted changes are scheduled with `embassy-time`, so they play out in real time with
+//! the `std` time driver, and deterministically with the mock one.
+
+#![allow(async_fn_in_trait)]
+
+pub use adc::*;
+pub use display::*;
+pub use gpio::*;
+pub use http::*;
+pub use pulse::*;
+
+mod adc;
+mod display;
+mod gpio;
+mod http;
+mod pulse;
+mod signal;
@@ -0,0 +1,118 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use embassy_time::{Duration, Instant};
+
+use ruwm::pulse_counter::{PulseCounter, PulseWakeup};
+
+use crate::signal::Changes;
+
+struct PulseState {
+    pending: u64,
+    trace: VecDeque<(Instant, u64)>,
+    wakeup: Option<bool>,
+}
+
+impl PulseState {
+    fn sync(&mut self, now: Instant) {
+        while let Some((at, pulses)) = self.trace.front().copied() {
+            if at > now {
+                break;
+            }
+
+            self.trace.pop_front();
+            self.pending += pulses;
+        }
+    }
+}
+
+/// A pulse counter fed by the test, either directly or by replaying a trace of timestamped pulses.
+/// Also records what `PulseWakeup::set_enabled` was last called with.
+#[derive(Clone)]
+pub struct MockPulseCounter(Rc<(RefCell<PulseState>, Changes)>);
+
+impl MockPulseCounter {
+    pub fn new() -> Self {
+        Self(Rc::new((
+            RefCell::new(PulseState {
+                pending: 0,
+                trace: VecDeque::new(),
+                wakeup: None,
+            }),
+            Changes::default(),
+        )))
+    }
+
+    pub fn add_pulses(&self, pulses: u64) {
+        self.0 .0.borrow_mut().pending += pulses;
+        self.0 .1.notify();
+    }
+
+    /// Replays a trace of pulses, each one timestamped relative to now.
+    /// Replaces any pulses still pending from a previous trace.
+    pub fn replay(&self, trace: impl IntoIterator<Item = (Duration, u64)>) {
+        let start = Instant::now();
+
+        let mut trace = trace
+            .into_iter()
+            .map(|(offset, pulses)| (start + offset, pulses))
+            .collect::<Vec<_>>();
+
+        trace.sort_by_key(|(at, _)| *at);
+
+        self.0 .0.borrow_mut().trace = trace.into();
+        self.0 .1.notify();
+    }
+
+    /// Whether all pulses of the replayed trace were taken
+    pub fn is_replayed(&self) -> bool {
+        let state = self.0 .0.borrow();
+
+        state.trace.is_empty() && state.pending == 0
+    }
+
+    pub fn wakeup(&self) -> Option<bool> {
+        self.0 .0.borrow().wakeup
+    }
+}
+
+impl Default for MockPulseCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PulseCounter for MockPulseCounter {
+    type Error = Infallible;
+
+    async fn take_pulses(&mut self) -> Result<u64, Self::Error> {
+        loop {
+            let next = {
+                let mut state = self.0 .0.borrow_mut();
+
+                state.sync(Instant::now());
+
+                if state.pending > 0 {
+                    break Ok(core::mem::replace(&mut state.pending, 0));
+                }
+
+                state.trace.front().map(|(at, _)| *at)
+            };
+
+            self.0 .1.wait(next).await;
+        }
+    }
+}
+
+impl PulseWakeup for MockPulseCounter {
+    type Error = Infallible;
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Self::Error> {
+        self.0 .0.borrow_mut().wakeup = Some(enabled);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_futures::select::select;
+use embassy_time::{Instant, Timer};
+
+/// Wakes the tasks waiting on a mock whenever the test changes it
+#[derive(Default)]
+pub(crate) struct Changes {
+    generation: Cell<u64>,
+    wakers: RefCell<Vec<Waker>>,
+}
+
+impl Changes {
+    pub fn notify(&self) {
+        self.generation.set(self.generation.get().wrapping_add(1));
+
+        for waker in self.wakers.borrow_mut().drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Waits for the next `notify` call, or until `deadline` - whichever comes first
+    pub async fn wait(&self, deadline: Option<Instant>) {
+        let generation = self.generation.get();
+
+        let changed = poll_fn(|cx| {
+            if self.generation.get() != generation {
+                Poll::Ready(())
+            } else {
+                let mut wakers = self.wakers.borrow_mut();
+
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+
+                Poll::Pending
+            }
+        });
+
+        if let Some(deadline) = deadline {
+            select(changed, Timer::at(deadline)).await;
+        } else {
+            changed.await;
+        }
+    }
+}
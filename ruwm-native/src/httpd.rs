@@ -0,0 +1,171 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+
+use channel_bridge::asynch::ws::{WsError, DEFAULT_BUF_SIZE};
+
+use edge_http::io::{self, server::Server};
+use edge_http::ws::MAX_BASE64_KEY_RESPONSE_LEN;
+use edge_http::{Method, DEFAULT_MAX_HEADERS_COUNT};
+use edge_std_nal_async::StdTcpConnection;
+use edge_ws::io::WsConnection;
+
+use embedded_nal_async::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use embedded_nal_async_xtra::{TcpListen, TcpSplittableConnection};
+
+use embedded_io_async::{Read, Write};
+use embedded_svc::http::server::asynch::Request;
+
+use ruwm::ws::{WS_MAX_CONNECTIONS, WS_MAX_FRAME_LEN};
+
+#[derive(Debug)]
+pub enum HttpdError<T> {
+    Http(io::Error<T>),
+    Ws(WsError<edge_ws::io::Error<T>>),
+}
+
+impl<T> From<io::Error<T>> for HttpdError<T> {
+    fn from(err: io::Error<T>) -> Self {
+        Self::Http(err)
+    }
+}
+
+impl<T> From<WsError<edge_ws::io::Error<T>>> for HttpdError<T> {
+    fn from(err: WsError<edge_ws::io::Error<T>>) -> Self {
+        Self::Ws(err)
+    }
+}
+
+/// Serves the same backend as the firmware - the WebSocket, the REST API, the Server-Sent Events
+/// and the metrics - but not the Web UI assets, which are served by Trunk instead
+pub struct HttpdHandler {
+    send_bufs: UnsafeCell<MaybeUninit<[[u8; WS_MAX_FRAME_LEN]; WS_MAX_CONNECTIONS]>>,
+    recv_bufs: UnsafeCell<MaybeUninit<[[u8; WS_MAX_FRAME_LEN]; WS_MAX_CONNECTIONS]>>,
+}
+
+impl HttpdHandler {
+    pub fn new() -> Self {
+        Self {
+            send_bufs: UnsafeCell::new(MaybeUninit::uninit()),
+            recv_bufs: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    async fn handle<'b, T, const N: usize>(
+        &self,
+        task_id: usize,
+        con: &mut io::server::Connection<'b, T, N>,
+    ) -> Result<(), HttpdError<T::Error>>
+    where
+        T: Read + Write + TcpSplittableConnection,
+    {
+        if con
+            .headers()?
+            .path
+            .is_some_and(|path| path.starts_with(ruwm::api::API_PREFIX))
+        {
+            ruwm::api::handle(Request::wrap(con)).await?;
+        } else if matches!(con.headers()?.method, Some(Method::Get)) {
+            if con.headers()?.path == Some(ruwm::metrics::METRICS_PATH) {
+                ruwm::metrics::handle(Request::wrap(con)).await?;
+            } else if con
+                .headers()?
+                .path
+                .is_some_and(|path| path.split('?').next() == Some(ruwm::sse::EVENTS_PATH))
+            {
+                ruwm::sse::handle(Request::wrap(con)).await?;
+            } else if matches!(con.headers()?.path, Some("/ws")) {
+                let send_buf = &mut unsafe {
+                    self.send_bufs.get().as_mut().unwrap().assume_init_mut()[task_id]
+                };
+                let recv_buf = &mut unsafe {
+                    self.recv_bufs.get().as_mut().unwrap().assume_init_mut()[task_id]
+                };
+
+                self.handle_ws(send_buf, recv_buf, con).await?;
+            } else {
+                con.initiate_response(404, None, &[]).await?;
+            }
+        } else {
+            con.initiate_response(405, None, &[]).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_ws<'b, T, const N: usize>(
+        &self,
+        send_buf: &mut [u8],
+        recv_buf: &mut [u8],
+        con: &mut io::server::Connection<'b, T, N>,
+    ) -> Result<(), HttpdError<T::Error>>
+    where
+        T: Read + Write + TcpSplittableConnection,
+    {
+        if con.is_ws_upgrade_request()? {
+            let mut buf = send_buf[..MAX_BASE64_KEY_RESPONSE_LEN].try_into().unwrap();
+
+            con.initiate_ws_upgrade_response(&mut buf).await?;
+            con.complete().await?;
+
+            let socket = con.unbind()?;
+
+            let (read, write) = socket.split().map_err(io::Error::Io)?;
+
+            log::info!("Starting WS connection");
+
+            let sender = WsConnection::new(write, || None);
+            let receiver = WsConnection::new(read, || Option::<()>::None);
+
+            ruwm::ws::handle(sender, send_buf, receiver, recv_buf).await?;
+        } else {
+            con.initiate_response(200, None, &[("Content-Length", "0")])
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for HttpdHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T, const N: usize> io::server::TaskHandler<'a, T, N> for HttpdHandler
+where
+    T: Read + Write + TcpSplittableConnection,
+{
+    type Error = HttpdError<T::Error>;
+
+    async fn handle(
+        &self,
+        task_id: usize,
+        con: &mut io::server::Connection<'a, T, N>,
+    ) -> Result<(), Self::Error> {
+        HttpdHandler::handle(self, task_id, con).await
+    }
+}
+
+pub type HttpdServer =
+    Server<{ WS_MAX_CONNECTIONS }, { DEFAULT_BUF_SIZE }, { DEFAULT_MAX_HEADERS_COUNT }>;
+
+pub async fn run<H>(
+    server: &mut HttpdServer,
+    handler: H,
+    port: u16,
+) -> Result<(), io::Error<std::io::Error>>
+where
+    H: for<'b> io::server::TaskHandler<'b, &'b mut StdTcpConnection, { DEFAULT_MAX_HEADERS_COUNT }>,
+{
+    let stack = edge_std_nal_async::Stack::new();
+
+    let acceptor = stack
+        .listen(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)))
+        .await
+        .map_err(io::Error::Io)?;
+
+    server.run_with_task_id(acceptor, handler, None).await?;
+
+    Ok(())
+}
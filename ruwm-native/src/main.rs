@@ -0,0 +1,199 @@
+#![allow(async_fn_in_trait)]
+#![recursion_limit = "1024"]
+
+use std::fs::File;
+
+use anyhow::{anyhow, bail};
+
+use log::{error, info};
+
+use edge_executor::LocalExecutor;
+
+use embedded_graphics::prelude::Size;
+
+use ruwm::battery::BatteryState;
+use ruwm::mqtt::{MqttConfiguration, MQTT_CERTIFICATE_NAME_MAX_LEN};
+use ruwm::screen::Color;
+use ruwm::{audit, spawn};
+
+use ruwm_mock::{MockAdc, MockDisplay, MockInputPin, MockOutputPin, MockPulseCounter};
+
+mod httpd;
+mod mqtt;
+mod tui;
+
+const DISPLAY_SIZE: Size = Size::new(128, 128);
+const MQTT_MAX_TOPIC_LEN: usize = 128;
+const MQTT_MAX_PAYLOAD_LEN: usize = 256;
+
+const USAGE: &str = "Usage: ruwm-native [--port <port>] [--mqtt <mqtt[s]://host[:port]>] [--client-id <id>] [--certs <dir>] [--ca <file>] [--cert <file> --key <file>] [--ascii] [--log <file>]";
+
+struct Args {
+    port: u16,
+    mqtt_url: Option<String>,
+    mqtt_client_id: String,
+    mqtt_certs: String,
+    mqtt_ca_certificate: Option<String>,
+    mqtt_client_certificate: Option<String>,
+    mqtt_client_key: Option<String>,
+    ascii: bool,
+    log: String,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = Self {
+            port: 8080,
+            mqtt_url: None,
+            mqtt_client_id: "ruwm-native".into(),
+            mqtt_certs: ".".into(),
+            mqtt_ca_certificate: None,
+            mqtt_client_certificate: None,
+            mqtt_client_key: None,
+            ascii: false,
+            log: "ruwm-native.log".into(),
+        };
+
+        let mut iter = std::env::args().skip(1);
+
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .ok_or_else(|| anyhow!("{arg}: missing value\n{USAGE}"))
+            };
+
+            match arg.as_str() {
+                "--port" => args.port = value()?.parse()?,
+                "--mqtt" => args.mqtt_url = Some(value()?),
+                "--client-id" => args.mqtt_client_id = value()?,
+                "--certs" => args.mqtt_certs = value()?,
+                "--ca" => args.mqtt_ca_certificate = Some(value()?),
+                "--cert" => args.mqtt_client_certificate = Some(value()?),
+                "--key" => args.mqtt_client_key = Some(value()?),
+                "--ascii" => args.ascii = true,
+                "--log" => args.log = value()?,
+                _ => bail!("{arg}: unknown argument\n{USAGE}"),
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse()?;
+
+    // The terminal belongs to the UI, hence the log goes to a file
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .target(env_logger::Target::Pipe(Box::new(File::create(&args.log)?)))
+        .init();
+
+    info!("Initializing services & peripherals");
+
+    let valve_power_pin = MockOutputPin::new();
+    let valve_open_pin = MockOutputPin::new();
+    let valve_close_pin = MockOutputPin::new();
+
+    let peripherals = tui::Peripherals {
+        buttons: [
+            MockInputPin::new(true),
+            MockInputPin::new(true),
+            MockInputPin::new(true),
+        ],
+        pulses: MockPulseCounter::new(),
+        battery_voltage: MockAdc::new(BatteryState::MAX_VOLTAGE),
+        power: MockInputPin::new(true),
+        display: MockDisplay::new(DISPLAY_SIZE, Color::Black),
+    };
+
+    if let Some(url) = &args.mqtt_url {
+        let mut configuration = MqttConfiguration::new();
+
+        configuration.url = url
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow!("MQTT URL too long"))?;
+        configuration.client_id = args
+            .mqtt_client_id
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow!("MQTT client ID too long"))?;
+        configuration.ca_certificate = certificate_name(&args.mqtt_ca_certificate)?;
+        configuration.client_certificate = certificate_name(&args.mqtt_client_certificate)?;
+        configuration.client_key = certificate_name(&args.mqtt_client_key)?;
+
+        ruwm::mqtt::CONFIGURATION.set(Some(configuration));
+    }
+
+    let executor = LocalExecutor::<64>::new();
+
+    // Nothing is persisted, so every run starts afresh
+
+    spawn::high_prio(
+        &executor,
+        valve_power_pin,
+        valve_open_pin,
+        valve_close_pin,
+        |_| (),
+        |_| (),
+        peripherals.pulses.clone(),
+        peripherals.pulses.clone(),
+        |_| (),
+        |_| (),
+        peripherals.battery_voltage.clone(),
+        peripherals.power.clone(),
+        false,
+        peripherals.buttons[0].clone(),
+        peripherals.buttons[1].clone(),
+        peripherals.buttons[2].clone(),
+    );
+
+    spawn::mqtt::<MQTT_MAX_TOPIC_LEN, MQTT_MAX_PAYLOAD_LEN, 64, _, _, _, _>(
+        &executor,
+        {
+            let certificates = mqtt::CertificatesDir::new(&args.mqtt_certs);
+
+            move |configuration: &MqttConfiguration| mqtt::client(configuration, &certificates)
+        },
+        |_| (),
+        |_| (),
+    );
+
+    spawn::users(&executor, |_| ());
+
+    audit::record(audit::AuditEvent::Boot(audit::BootReason::PowerOn));
+
+    spawn::audit(&executor, |_| ());
+
+    spawn::low_prio_owned(&executor, peripherals.display.clone(), |_| ());
+
+    let port = args.port;
+
+    executor
+        .spawn(async move {
+            let mut httpd = httpd::HttpdServer::new();
+            let handler = httpd::HttpdHandler::new();
+
+            if let Err(err) = httpd::run(&mut httpd, &handler, port).await {
+                error!("HTTP server failed: {err:?}");
+            }
+        })
+        .detach();
+
+    info!("Starting executor, serving on http://localhost:{port}");
+
+    futures::executor::block_on(executor.run(tui::run(peripherals, args.ascii, port)))?;
+
+    Ok(())
+}
+
+fn certificate_name(
+    name: &Option<String>,
+) -> anyhow::Result<Option<heapless::String<MQTT_CERTIFICATE_NAME_MAX_LEN>>> {
+    name.as_deref()
+        .map(|name| {
+            name.try_into()
+                .map_err(|_| anyhow!("{name}: certificate file name too long"))
+        })
+        .transpose()
+}
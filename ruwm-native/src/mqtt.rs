@@ -0,0 +1,411 @@
+//! The `embedded-svc` MQTT client traits on top of the blocking `rumqttc` client.
+//!
+//! The `rumqttc` connection is polled in a thread of its own, which forwards its events
+//! to the `Connection` half through a channel.
+//!
+//! TLS is provided by `rustls`. The certificates of the configuration are files in a directory,
+//! named after their storage keys.
+
+use core::fmt::{self, Display};
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+
+use embedded_svc::mqtt::client::asynch::{
+    Client, Connection, ErrorType, Event, EventPayload, MessageId, Publish, QoS,
+};
+use embedded_svc::mqtt::client::Details;
+use embedded_svc::storage::{RawStorage, StorageBase};
+
+use rumqttc::tokio_rustls::rustls::{self, Certificate, ClientConfig, PrivateKey, RootCertStore};
+use rumqttc::{ConnectionError, MqttOptions, Packet, TlsConfiguration, Transport};
+
+use rustls_pemfile::Item;
+
+use ruwm::mqtt::{MqttCertificate, MqttCertificates, MqttConfiguration, MqttTlsError};
+
+const EVENTS_QUEUE_SIZE: usize = 16;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const CERTIFICATE_BUF_SIZE: usize = 16384;
+
+#[derive(Debug)]
+pub enum MqttError {
+    Configuration(&'static str),
+    Certificates(MqttTlsError<io::Error>),
+    Tls(rustls::Error),
+    Client(rumqttc::ClientError),
+    Connection(String),
+}
+
+impl Display for MqttError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Configuration(err) => write!(f, "Invalid configuration: {err}"),
+            Self::Certificates(err) => write!(f, "Invalid certificates: {err:?}"),
+            Self::Tls(err) => write!(f, "TLS error: {err}"),
+            Self::Client(err) => write!(f, "Client error: {err}"),
+            Self::Connection(err) => write!(f, "Connection error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MqttError {}
+
+pub enum MqttEvent {
+    Connected(bool),
+    Disconnected,
+    Subscribed(MessageId),
+    Published(MessageId),
+    Received {
+        id: MessageId,
+        topic: String,
+        data: Vec<u8>,
+    },
+    Error(MqttError),
+}
+
+impl ErrorType for MqttEvent {
+    type Error = MqttError;
+}
+
+impl Event for MqttEvent {
+    fn payload(&self) -> EventPayload<'_, <Self as ErrorType>::Error> {
+        match self {
+            Self::Connected(session_present) => EventPayload::Connected(*session_present),
+            Self::Disconnected => EventPayload::Disconnected,
+            Self::Subscribed(id) => EventPayload::Subscribed(*id),
+            Self::Published(id) => EventPayload::Published(*id),
+            Self::Received { id, topic, data } => EventPayload::Received {
+                id: *id,
+                topic: Some(topic),
+                data,
+                details: Details::Complete,
+            },
+            Self::Error(err) => EventPayload::Error(err),
+        }
+    }
+}
+
+type Events = Channel<CriticalSectionRawMutex, MqttEvent, EVENTS_QUEUE_SIZE>;
+
+pub struct MqttClient(rumqttc::Client);
+
+impl ErrorType for MqttClient {
+    type Error = MqttError;
+}
+
+impl Client for MqttClient {
+    async fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<MessageId, Self::Error> {
+        self.0
+            .try_subscribe(topic, to_qos(qos))
+            .map_err(MqttError::Client)?;
+
+        // `rumqttc` only assigns the packet ID once the request is processed by the connection
+        Ok(0)
+    }
+
+    async fn unsubscribe(&mut self, topic: &str) -> Result<MessageId, Self::Error> {
+        self.0.try_unsubscribe(topic).map_err(MqttError::Client)?;
+
+        Ok(0)
+    }
+}
+
+impl Publish for MqttClient {
+    async fn publish(
+        &mut self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<MessageId, Self::Error> {
+        self.0
+            .try_publish(topic, to_qos(qos), retain, payload)
+            .map_err(MqttError::Client)?;
+
+        Ok(0)
+    }
+}
+
+pub struct MqttConnection(Arc<Events>);
+
+impl ErrorType for MqttConnection {
+    type Error = MqttError;
+}
+
+impl Connection for MqttConnection {
+    type Event<'a>
+        = MqttEvent
+    where
+        Self: 'a;
+
+    async fn next(&mut self) -> Result<Self::Event<'_>, Self::Error> {
+        Ok(self.0.receive().await)
+    }
+}
+
+/// The certificates referenced by the MQTT configuration, as files in a directory
+pub struct CertificatesDir(PathBuf);
+
+impl CertificatesDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
+    }
+}
+
+impl StorageBase for CertificatesDir {
+    type Error = io::Error;
+
+    fn contains(&self, name: &str) -> Result<bool, Self::Error> {
+        Ok(self.len(name)?.is_some())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool, Self::Error> {
+        not_found_as_none(fs::remove_file(self.0.join(name))).map(|removed| removed.is_some())
+    }
+}
+
+impl RawStorage for CertificatesDir {
+    fn len(&self, name: &str) -> Result<Option<usize>, Self::Error> {
+        not_found_as_none(fs::metadata(self.0.join(name)))
+            .map(|metadata| metadata.map(|metadata| metadata.len() as _))
+    }
+
+    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Self::Error> {
+        let Some(data) = not_found_as_none(fs::read(self.0.join(name)))? else {
+            return Ok(None);
+        };
+
+        let buf = buf
+            .get_mut(..data.len())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Buffer too small"))?;
+
+        buf.copy_from_slice(&data);
+
+        Ok(Some(buf))
+    }
+
+    fn set_raw(&mut self, name: &str, buf: &[u8]) -> Result<bool, Self::Error> {
+        fs::write(self.0.join(name), buf)?;
+
+        Ok(true)
+    }
+}
+
+/// Creates a client for the broker of the configuration, over plain TCP (`mqtt://`, `tcp://`) or TLS (`mqtts://`, `ssl://`).
+/// Without a CA certificate in the configuration, the broker certificate is verified against the roots of the system.
+pub fn client(
+    configuration: &MqttConfiguration,
+    certificates: &CertificatesDir,
+) -> Result<(MqttClient, MqttConnection), MqttError> {
+    let (address, default_port) = [
+        ("mqtt://", 1883),
+        ("tcp://", 1883),
+        ("mqtts://", 8883),
+        ("ssl://", 8883),
+    ]
+    .iter()
+    .find_map(|(scheme, port)| {
+        configuration
+            .url
+            .strip_prefix(scheme)
+            .map(|address| (address, *port))
+    })
+    .ok_or(MqttError::Configuration("Unsupported URL scheme"))?;
+
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| MqttError::Configuration("Invalid port"))?,
+        ),
+        None => (address, default_port),
+    };
+
+    let client_id = if configuration.client_id.is_empty() {
+        "ruwm-native"
+    } else {
+        configuration.client_id.as_str()
+    };
+
+    let mut options = MqttOptions::new(client_id, host, port);
+
+    options.set_keep_alive(Duration::from_secs(30));
+
+    if configuration.is_tls() {
+        options.set_transport(Transport::tls_with_config(tls(
+            configuration,
+            certificates,
+        )?));
+    }
+
+    if !configuration.username.is_empty() {
+        options.set_credentials(
+            configuration.username.as_str(),
+            configuration.password.as_str(),
+        );
+    }
+
+    let (client, mut connection) = rumqttc::Client::new(options, EVENTS_QUEUE_SIZE);
+
+    let events = Arc::new(Events::new());
+
+    let sender = events.clone();
+
+    thread::Builder::new()
+        .name("mqtt".into())
+        .spawn(move || {
+            let send = |event| futures::executor::block_on(sender.send(event));
+
+            for notification in connection.iter() {
+                match notification {
+                    Ok(rumqttc::Event::Incoming(Packet::ConnAck(ack))) => {
+                        send(MqttEvent::Connected(ack.session_present))
+                    }
+                    Ok(rumqttc::Event::Incoming(Packet::SubAck(ack))) => {
+                        send(MqttEvent::Subscribed(ack.pkid as _))
+                    }
+                    Ok(rumqttc::Event::Incoming(Packet::PubAck(ack))) => {
+                        send(MqttEvent::Published(ack.pkid as _))
+                    }
+                    Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => {
+                        send(MqttEvent::Received {
+                            id: publish.pkid as _,
+                            topic: publish.topic,
+                            data: publish.payload.to_vec(),
+                        })
+                    }
+                    Ok(_) => (),
+                    // All clients are dropped, i.e. the configuration was changed
+                    Err(ConnectionError::RequestsDone) => break,
+                    Err(err) => {
+                        warn!("MQTT connection error: {err}");
+
+                        send(MqttEvent::Error(MqttError::Connection(err.to_string())));
+                        send(MqttEvent::Disconnected);
+
+                        thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+
+            info!("MQTT connection closed");
+        })
+        .unwrap();
+
+    Ok((MqttClient(client), MqttConnection(events)))
+}
+
+fn tls(
+    configuration: &MqttConfiguration,
+    certificates: &CertificatesDir,
+) -> Result<TlsConfiguration, MqttError> {
+    let mut bufs = vec![0; CERTIFICATE_BUF_SIZE * 3];
+
+    let (ca_certificate_buf, buf) = bufs.split_at_mut(CERTIFICATE_BUF_SIZE);
+    let (client_certificate_buf, client_key_buf) = buf.split_at_mut(CERTIFICATE_BUF_SIZE);
+
+    let loaded = MqttCertificates::load(
+        configuration,
+        certificates,
+        ca_certificate_buf,
+        client_certificate_buf,
+        client_key_buf,
+    )
+    .map_err(MqttError::Certificates)?;
+
+    let mut roots = RootCertStore::empty();
+
+    if let Some(ca_certificate) = loaded.ca_certificate {
+        for certificate in to_der(ca_certificate)? {
+            roots
+                .add(&Certificate(certificate))
+                .map_err(|_| malformed())?;
+        }
+    } else {
+        let certificates = rustls_native_certs::load_native_certs()
+            .map_err(|err| MqttError::Certificates(MqttTlsError::Storage(err)))?;
+
+        roots.add_parsable_certificates(
+            &certificates
+                .into_iter()
+                .map(|certificate| certificate.0)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    let config = match (loaded.client_certificate, loaded.client_key) {
+        (Some(certificate), Some(key)) => {
+            let chain = to_der(certificate)?.into_iter().map(Certificate).collect();
+            let key = to_der(key)?.into_iter().next().ok_or_else(malformed)?;
+
+            builder
+                .with_client_auth_cert(chain, PrivateKey(key))
+                .map_err(MqttError::Tls)?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+
+    Ok(TlsConfiguration::Rustls(Arc::new(config)))
+}
+
+/// The DER encoding of each certificate or key in `certificate`
+fn to_der(certificate: MqttCertificate<'_>) -> Result<Vec<Vec<u8>>, MqttError> {
+    match certificate {
+        MqttCertificate::Der(der) => Ok(vec![der.to_vec()]),
+        MqttCertificate::Pem(pem) => {
+            let mut pem = pem.strip_suffix(&[0]).unwrap_or(pem);
+
+            let der = rustls_pemfile::read_all(&mut pem)
+                .map_err(|_| malformed())?
+                .into_iter()
+                .filter_map(|item| match item {
+                    Item::X509Certificate(der)
+                    | Item::RSAKey(der)
+                    | Item::PKCS8Key(der)
+                    | Item::ECKey(der) => Some(der),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            if der.is_empty() {
+                Err(malformed())
+            } else {
+                Ok(der)
+            }
+        }
+    }
+}
+
+fn malformed() -> MqttError {
+    MqttError::Certificates(MqttTlsError::Malformed)
+}
+
+fn not_found_as_none<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn to_qos(qos: QoS) -> rumqttc::QoS {
+    match qos {
+        QoS::AtMostOnce => rumqttc::QoS::AtMostOnce,
+        QoS::AtLeastOnce => rumqttc::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => rumqttc::QoS::ExactlyOnce,
+    }
+}
@@ -0,0 +1,276 @@
+use std::io::{self, Stdout, Write};
+
+use crossterm::cursor::{Hide, MoveTo, MoveToNextLine, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{
+    Color as TermColor, Print, ResetColor, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use embassy_time::{Duration, Timer};
+
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+
+use ruwm::battery::{self, BatteryState};
+use ruwm::button::BUTTON3_HOLD_DURATION;
+use ruwm::keepalive::{self, RemainingTime};
+use ruwm::screen::Color;
+use ruwm::{emergency, mqtt, valve, wm};
+
+use ruwm_mock::{MockAdc, MockDisplay, MockInputPin, MockPulseCounter, Snapshot};
+
+const POLL_PERIOD: Duration = Duration::from_millis(50);
+const CLICK_DURATION: Duration = Duration::from_millis(150);
+
+const VOLTAGE_STEP: u16 = 50;
+const VOLTAGE_MIN: u16 = BatteryState::LOW_VOLTAGE - 300;
+const VOLTAGE_MAX: u16 = BatteryState::MAX_VOLTAGE + 100;
+
+const HELP: &str =
+    "[1][2][3] buttons  [h] hold 3  [p] pulse  [P] 10 pulses  [+][-] voltage  [c] power  [q] quit";
+
+pub struct Peripherals {
+    pub buttons: [MockInputPin; 3],
+    pub pulses: MockPulseCounter,
+    pub battery_voltage: MockAdc,
+    pub power: MockInputPin,
+    pub display: MockDisplay,
+}
+
+/// Restores the terminal when dropped, including on panic
+struct Terminal(Stdout);
+
+impl Terminal {
+    fn new() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        Ok(Self(stdout))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(self.0, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Renders the display and the system state, and maps the keyboard to the peripherals.
+/// Returns when the user quits.
+pub async fn run(peripherals: Peripherals, ascii: bool, web_port: u16) -> io::Result<()> {
+    let mut terminal = Terminal::new()?;
+
+    let mut rendered = None;
+
+    loop {
+        while event::poll(core::time::Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if !process_key(&peripherals, key) {
+                    return Ok(());
+                }
+            }
+        }
+
+        let status = status(&peripherals, web_port);
+        let flushes = peripherals.display.flushes();
+
+        if rendered.as_ref() != Some(&(flushes, status.clone())) {
+            render(
+                &mut terminal.0,
+                &peripherals.display.flushed(),
+                ascii,
+                &status,
+            )?;
+
+            rendered = Some((flushes, status));
+        }
+
+        Timer::after(POLL_PERIOD).await;
+    }
+}
+
+/// Returns `false` if the user quits
+fn process_key(peripherals: &Peripherals, key: KeyEvent) -> bool {
+    if key.kind != KeyEventKind::Press {
+        return true;
+    }
+
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return false,
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+        KeyCode::Char('1') => peripherals.buttons[0].click(Duration::from_ticks(0), CLICK_DURATION),
+        KeyCode::Char('2') => peripherals.buttons[1].click(Duration::from_ticks(0), CLICK_DURATION),
+        KeyCode::Char('3') => peripherals.buttons[2].click(Duration::from_ticks(0), CLICK_DURATION),
+        KeyCode::Char('h') => peripherals.buttons[2].click(
+            Duration::from_ticks(0),
+            BUTTON3_HOLD_DURATION + Duration::from_millis(500),
+        ),
+        KeyCode::Char('p') => peripherals.pulses.add_pulses(1),
+        KeyCode::Char('P') => peripherals.pulses.add_pulses(10),
+        KeyCode::Char('+') | KeyCode::Up => {
+            let voltage = peripherals.battery_voltage.value();
+
+            peripherals
+                .battery_voltage
+                .set_value((voltage + VOLTAGE_STEP).min(VOLTAGE_MAX));
+        }
+        KeyCode::Char('-') | KeyCode::Down => {
+            let voltage = peripherals.battery_voltage.value();
+
+            peripherals
+                .battery_voltage
+                .set_value(voltage.saturating_sub(VOLTAGE_STEP).max(VOLTAGE_MIN));
+        }
+        KeyCode::Char('c') => peripherals.power.set_level(!peripherals.power.level()),
+        _ => (),
+    }
+
+    true
+}
+
+fn status(peripherals: &Peripherals, web_port: u16) -> Vec<String> {
+    let wm = wm::STATE.get();
+    let battery = battery::STATE.get();
+
+    let valve = match valve::STATE.get() {
+        Some(state) => format!("{state:?}"),
+        None => "Unknown".into(),
+    };
+
+    let source = valve::SOURCE_STATE
+        .get()
+        .map(|source| source.text())
+        .unwrap_or("-");
+
+    let lockout = match emergency::STATE.get() {
+        Some(lockout) if lockout.condition_present => {
+            format!("LOCKOUT ({})", lockout.reason.text())
+        }
+        Some(lockout) => format!("LOCKOUT ({}, acknowledge)", lockout.reason.text()),
+        None => "no lockout".into(),
+    };
+
+    let remaining_time = match keepalive::STATE.get() {
+        RemainingTime::Indefinite => "awake".into(),
+        RemainingTime::Duration(duration) => format!("sleep in {}s", duration.as_secs()),
+    };
+
+    let mqtt = match mqtt::STATE.get() {
+        Some(true) => "connected",
+        Some(false) => "disconnected",
+        None => "off",
+    };
+
+    let voltage = peripherals.battery_voltage.value();
+
+    vec![
+        format!("Valve: {valve} (by {source}), {lockout}"),
+        format!(
+            "Meter: {} edges, {}, {}",
+            wm.edges_count,
+            if wm.armed { "armed" } else { "disarmed" },
+            if wm.leaking { "LEAKING" } else { "no leak" },
+        ),
+        format!(
+            "Battery: {} {voltage} mV ({}), {}, {remaining_time}",
+            slider(voltage, VOLTAGE_MIN, VOLTAGE_MAX, 20),
+            battery
+                .voltage
+                .map(|voltage| format!("{voltage} mV measured"))
+                .unwrap_or_else(|| "not measured".into()),
+            if peripherals.power.level() {
+                "powered"
+            } else {
+                "on battery"
+            },
+        ),
+        format!("Web: ws://localhost:{web_port}/ws, MQTT: {mqtt}"),
+        HELP.into(),
+    ]
+}
+
+fn slider(value: u16, min: u16, max: u16, width: usize) -> String {
+    let filled = (value.clamp(min, max) - min) as usize * width / (max - min) as usize;
+
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(width - filled))
+}
+
+/// Renders two rows of pixels per line of text: as colored half blocks, or as monochrome ASCII
+fn render(
+    out: &mut impl Write,
+    snapshot: &Snapshot<Color>,
+    ascii: bool,
+    status: &[String],
+) -> io::Result<()> {
+    queue!(out, MoveTo(0, 0))?;
+
+    let rows = snapshot.rows().collect::<Vec<_>>();
+
+    for pair in rows.chunks(2) {
+        let top = pair[0];
+        let bottom = pair.get(1).copied();
+
+        let mut colors = None;
+
+        for (x, top) in top.iter().enumerate() {
+            let bottom = bottom.map(|bottom| bottom[x]).unwrap_or(Color::Black);
+
+            if ascii {
+                let c = match (top.is_off(), bottom.is_off()) {
+                    (true, true) => ' ',
+                    (false, true) => '\'',
+                    (true, false) => '.',
+                    (false, false) => ':',
+                };
+
+                queue!(out, Print(c))?;
+            } else {
+                let pixel_colors = (term_color(*top), term_color(bottom));
+
+                if colors != Some(pixel_colors) {
+                    queue!(
+                        out,
+                        SetForegroundColor(pixel_colors.0),
+                        SetBackgroundColor(pixel_colors.1)
+                    )?;
+
+                    colors = Some(pixel_colors);
+                }
+
+                queue!(out, Print('▀'))?;
+            }
+        }
+
+        queue!(
+            out,
+            ResetColor,
+            Clear(ClearType::UntilNewLine),
+            MoveToNextLine(1)
+        )?;
+    }
+
+    for line in status {
+        queue!(
+            out,
+            Print(line),
+            Clear(ClearType::UntilNewLine),
+            MoveToNextLine(1)
+        )?;
+    }
+
+    out.flush()
+}
+
+fn term_color(color: Color) -> TermColor {
+    let rgb = Rgb888::from(color);
+
+    TermColor::Rgb {
+        r: rgb.r(),
+        g: rgb.g(),
+        b: rgb.b(),
+    }
+}
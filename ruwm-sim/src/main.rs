@@ -72,6 +72,7 @@ fn start() {
     //     services::RTC_MEMORY.wm = wm_state;
 
     //     ruwm::valve::STATE.set(services::RTC_MEMORY.valve);
+    //     ruwm::emergency::STATE.set(services::RTC_MEMORY.lockout);
     //     ruwm::wm::STATE.set(services::RTC_MEMORY.wm);
     //     ruwm::wm_stats::STATE.set(services::RTC_MEMORY.wm_stats);
     // }
@@ -99,6 +100,9 @@ fn start() {
         |state| unsafe {
             services::RTC_MEMORY.valve = state;
         },
+        |state| unsafe {
+            services::RTC_MEMORY.lockout = state;
+        },
         pulse_counter,
         pulse_wakeup,
         |state| unsafe {
@@ -134,6 +138,14 @@ fn start() {
         flash_wm_state(storage, _new_state);
     });
 
+    // Audit log; not persisted, as there is no storage
+
+    ruwm::audit::record(ruwm::audit::AuditEvent::Boot(
+        ruwm::audit::BootReason::PowerOn,
+    ));
+
+    spawn::audit(executor, |_log| ());
+
     let (sender, receiver) = ruwm_web::local_queue();
 
     spawn::web(
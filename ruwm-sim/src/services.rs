@@ -18,6 +18,7 @@ use hal_sim::display::Display;
 use hal_sim::gpio::{Input, Pin};
 
 use ruwm::button::PressedLevel;
+use ruwm::emergency::LockoutState;
 use ruwm::pulse_counter::PulseCounter;
 use ruwm::pulse_counter::PulseWakeup;
 use ruwm::screen::Color;
@@ -32,6 +33,7 @@ pub static mut RTC_MEMORY: RtcMemory = RtcMemory::new();
 #[derive(Default)]
 pub struct RtcMemory {
     pub valve: Option<ValveState>,
+    pub lockout: Option<LockoutState>,
     pub wm: WaterMeterState,
     pub wm_stats: WaterMeterStatsState,
 }
@@ -40,6 +42,7 @@ impl RtcMemory {
     pub const fn new() -> Self {
         Self {
             valve: None,
+            lockout: None,
             wm: WaterMeterState::new(),
             wm_stats: WaterMeterStatsState::new(),
         }
@@ -0,0 +1,29 @@
+use std::rc::Rc;
+
+use yewdux::prelude::*;
+
+use ruwm::dto::web::UserRole;
+
+/// The role of the current connection, as last reported by the backend; `None` while not logged in.
+/// Used to disable controls the user is not allowed to operate.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Store)]
+pub struct AccessStore(pub Option<UserRole>);
+
+impl AccessStore {
+    pub fn allows(&self, role: UserRole) -> bool {
+        Some(role) <= self.0
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AccessMsg(pub Option<UserRole>);
+
+impl Reducer<AccessStore> for AccessMsg {
+    fn apply(self, mut store: Rc<AccessStore>) -> Rc<AccessStore> {
+        let state = Rc::make_mut(&mut store);
+
+        state.0 = self.0;
+
+        store
+    }
+}
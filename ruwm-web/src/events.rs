@@ -0,0 +1,88 @@
+use std::rc::Rc;
+
+use yew::prelude::*;
+use yewdux::prelude::*;
+use yewdux_middleware::*;
+
+use ruwm::dto::audit::{AuditLog, AUDIT_LOG_SIZE};
+use ruwm::dto::web::WebRequest;
+
+#[derive(Default, Clone, Debug, Eq, PartialEq, Store)]
+pub struct AuditStore(pub Option<AuditLog<AUDIT_LOG_SIZE>>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditMsg(pub AuditLog<AUDIT_LOG_SIZE>);
+
+impl Reducer<AuditStore> for AuditMsg {
+    fn apply(self, mut store: Rc<AuditStore>) -> Rc<AuditStore> {
+        let state = Rc::make_mut(&mut store);
+
+        state.0 = Some(self.0);
+
+        store
+    }
+}
+
+/// The audit log of the device, newest entry first; fetched when shown and on refresh
+#[function_component(Events)]
+pub fn events() -> Html {
+    let mcx = use_mcx();
+    let audit_store = use_store_value::<AuditStore>();
+
+    {
+        let mcx = mcx.clone();
+
+        use_effect_with((), move |_| {
+            mcx.invoke(WebRequest::GetEvents);
+
+            move || ()
+        });
+    }
+
+    let onclick = Callback::from(move |_| mcx.invoke(WebRequest::GetEvents));
+
+    html! {
+        <div class="box">
+            <h2 class="title is-5">{"Events"}</h2>
+            {
+                if let Some(log) = audit_store.0.as_ref() {
+                    html! {
+                        <table class="table is-fullwidth is-narrow">
+                            <thead>
+                                <tr>
+                                    <th>{"Time"}</th>
+                                    <th>{"Event"}</th>
+                                </tr>
+                            </thead>
+                            <tbody>
+                                {
+                                    for log.iter().rev().map(|entry| html! {
+                                        <tr>
+                                            <td>
+                                                {
+                                                    if entry.boot == log.boot() {
+                                                        uptime_label(entry.time_secs)
+                                                    } else {
+                                                        "Previous boot".into()
+                                                    }
+                                                }
+                                            </td>
+                                            <td>{entry.event.to_string()}</td>
+                                        </tr>
+                                    })
+                                }
+                            </tbody>
+                        </table>
+                    }
+                } else {
+                    html! {}
+                }
+            }
+            <button class="button is-small" {onclick}>{"Refresh"}</button>
+        </div>
+    }
+}
+
+fn uptime_label(secs: u64) -> String {
+    format!("+{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -1,6 +1,7 @@
 #![recursion_limit = "1024"]
 
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use std::rc::Rc;
 
@@ -17,11 +18,22 @@ use edge_frame::wifi_setup::*;
 
 use ruwm::dto::web::*;
 
+use crate::access::*;
 use crate::battery::*;
+use crate::events::*;
+use crate::meter::*;
 use crate::valve::*;
+use crate::wifi::{WifiMsg, WifiSettings, WifiStore};
 
+mod access;
 mod battery;
+mod events;
+mod meter;
+mod session;
 mod valve;
+mod wifi;
+
+static SETUP_REQUIRED: AtomicBool = AtomicBool::new(false);
 
 #[cfg(feature = "sim")]
 static REQUEST_QUEUE: embassy_sync::channel::Channel<
@@ -92,7 +104,9 @@ fn render(route: Routes) -> Html {
                         Routes::Home => html! {
                             <Role role={RoleDto::User} auth=true>
                                 <Valve/>
+                                <Meter/>
                                 <Battery/>
+                                <Events/>
                             </Role>
                         },
                         Routes::AuthState => html! {
@@ -100,7 +114,7 @@ fn render(route: Routes) -> Html {
                         },
                         Routes::Wifi => html! {
                             <Role role={RoleDto::Admin} auth=true>
-                                <WifiSetup/>
+                                <WifiSettings/>
                             </Role>
                         },
                     }
@@ -111,7 +125,7 @@ fn render(route: Routes) -> Html {
 }
 
 fn init_middleware(mcx: &MiddlewareContext) {
-    // Dispatch WebEvent messages => redispatch as BatteryMsg, ValveMsg, RoleState or WifiConf messages
+    // Dispatch WebEvent messages => redispatch as BatteryMsg, ValveMsg, WaterMeterMsg, WaterMeterStatsMsg, AuditMsg, RoleState or WifiMsg messages
     mcx.register::<WebEvent, _>(|mcx: &MiddlewareContext, event| {
         match event {
             WebEvent::NoPermissions => unreachable!(),
@@ -121,10 +135,42 @@ fn init_middleware(mcx: &MiddlewareContext) {
                     password: "".into(),
                 }))
             } // TODO
-            WebEvent::RoleState(role) => mcx.invoke(RoleState::Role(role)),
-            WebEvent::ValveState(valve) => mcx.invoke(ValveMsg(valve)),
+            WebEvent::SetupRequired => SETUP_REQUIRED.store(true, Ordering::SeqCst),
+            WebEvent::RequestFailed => (), // TODO
+            WebEvent::RoleState(user_role, token) => {
+                let role = user_role
+                    .map(|user_role| user_role.role())
+                    .unwrap_or(RoleDto::None);
+
+                if role != RoleDto::None {
+                    SETUP_REQUIRED.store(false, Ordering::SeqCst);
+                }
+
+                if let Some(token) = token {
+                    session::store(&token);
+                } else if role == RoleDto::None {
+                    // Try to resume the previous session; a stale token is discarded
+                    // as the backend answers with another `RoleState` either way
+                    if let Some(token) = session::load() {
+                        session::clear();
+                        mcx.invoke(WebRequest::Resume(token));
+                    }
+                }
+
+                mcx.invoke(AccessMsg(user_role));
+                mcx.invoke(RoleState::Role(role))
+            }
+            WebEvent::ValveState(valve) => mcx.invoke(ValveMsg::State(valve)),
+            WebEvent::ValveSourceState(source) => mcx.invoke(ValveMsg::Source(source)),
+            WebEvent::LockoutState(lockout) => mcx.invoke(ValveMsg::Lockout(lockout)),
             WebEvent::BatteryState(battery) => mcx.invoke(BatteryMsg(battery)),
-            WebEvent::WaterMeterState(_) => (), // TODO
+            WebEvent::WaterMeterState(wm) => mcx.invoke(WaterMeterMsg(wm)),
+            WebEvent::WaterMeterStatsState(wm_stats) => mcx.invoke(WaterMeterStatsMsg(wm_stats)),
+            WebEvent::RemainingTimeState(_) => (), // TODO
+            WebEvent::MqttState(_) => (),          // TODO
+            WebEvent::WifiState(connected) => mcx.invoke(WifiMsg::State(connected)),
+            WebEvent::WifiSettingsState(settings) => mcx.invoke(WifiMsg::Settings(settings)),
+            WebEvent::AuditLog(log) => mcx.invoke(AuditMsg(log)),
         }
     });
 
@@ -133,7 +179,16 @@ fn init_middleware(mcx: &MiddlewareContext) {
     ));
     mcx.register(log::<WifiConfStore, WifiConf>(MiddlewareContext::store));
     mcx.register(log::<BatteryStore, BatteryMsg>(MiddlewareContext::store));
+    mcx.register(log::<AuditStore, AuditMsg>(MiddlewareContext::store));
+    mcx.register(log::<AccessStore, AccessMsg>(MiddlewareContext::store));
     mcx.register(log::<ValveStore, ValveMsg>(MiddlewareContext::store));
+    mcx.register(log::<WifiStore, WifiMsg>(MiddlewareContext::store));
+    mcx.register(log::<WaterMeterStore, WaterMeterMsg>(
+        MiddlewareContext::store,
+    ));
+    mcx.register(log::<WaterMeterStatsStore, WaterMeterStatsMsg>(
+        MiddlewareContext::store,
+    ));
 
     #[cfg(not(feature = "sim"))]
     {
@@ -183,11 +238,21 @@ fn role_as_request(
     dispatch: impl MiddlewareDispatch<RoleState>,
 ) {
     let request = match &msg {
+        // On first boot, the first login creates the admin account
+        RoleState::Authenticating(credentials) if SETUP_REQUIRED.load(Ordering::SeqCst) => {
+            Some(WebRequest::SetupAdmin(
+                credentials.username.as_str().try_into().unwrap(),
+                credentials.password.as_str().try_into().unwrap(),
+            ))
+        }
         RoleState::Authenticating(credentials) => Some(WebRequest::Authenticate(
             credentials.username.as_str().try_into().unwrap(),
             credentials.password.as_str().try_into().unwrap(),
         )),
-        RoleState::LoggingOut(_) => Some(WebRequest::Logout),
+        RoleState::LoggingOut(_) => {
+            session::clear();
+            Some(WebRequest::Logout)
+        }
         _ => None,
     };
 
@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use yew::prelude::*;
+use yewdux::prelude::*;
+use yewdux_middleware::*;
+
+use ruwm::dto::water_meter::{WaterMeterCommand, WaterMeterState};
+use ruwm::dto::water_meter_stats::{FlowSnapshot, WaterMeterStatsState, DURATIONS};
+use ruwm::dto::web::{UserRole, WebRequest};
+
+use crate::access::AccessStore;
+use crate::valve::confirm;
+
+const HISTORY_LEN: usize = 120;
+
+#[derive(Default, Clone, Debug, Eq, PartialEq, Store)]
+pub struct WaterMeterStore(pub WaterMeterState);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WaterMeterMsg(pub WaterMeterState);
+
+impl Reducer<WaterMeterStore> for WaterMeterMsg {
+    fn apply(self, mut store: Rc<WaterMeterStore>) -> Rc<WaterMeterStore> {
+        let state = Rc::make_mut(&mut store);
+
+        state.0 = self.0;
+
+        store
+    }
+}
+
+#[derive(Default, Clone, Debug, Eq, PartialEq, Store)]
+pub struct WaterMeterStatsStore {
+    pub stats: WaterMeterStatsState,
+    /// The most recent snapshots received since the page was loaded, oldest first
+    pub history: VecDeque<FlowSnapshot>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WaterMeterStatsMsg(pub WaterMeterStatsState);
+
+impl Reducer<WaterMeterStatsStore> for WaterMeterStatsMsg {
+    fn apply(self, mut store: Rc<WaterMeterStatsStore>) -> Rc<WaterMeterStatsStore> {
+        let state = Rc::make_mut(&mut store);
+
+        if state.history.back() != Some(&self.0.most_recent) {
+            if state.history.len() == HISTORY_LEN {
+                state.history.pop_front();
+            }
+
+            state.history.push_back(self.0.most_recent);
+        }
+
+        state.stats = self.0;
+
+        store
+    }
+}
+
+#[function_component(Meter)]
+pub fn meter() -> Html {
+    let wm_store = use_store_value::<WaterMeterStore>();
+    let stats_store = use_store_value::<WaterMeterStatsStore>();
+
+    let wm = &wm_store.0;
+    let stats = &stats_store.stats;
+
+    html! {
+        <div class="box">
+            <h2 class="title is-5">{"Water Meter"}</h2>
+            <nav class="level">
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">{"Reading"}</p>
+                        <p class="title">{wm.edges_count.to_string()}</p>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">{"Since installation"}</p>
+                        <p class="title">{wm.edges_count.saturating_sub(stats.installation.edges_count).to_string()}</p>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">{"Flow watch"}</p>
+                        <FlowWatch/>
+                    </div>
+                </div>
+                <div class="level-item has-text-centered">
+                    <div>
+                        <p class="heading">{"Leak"}</p>
+                        <p class={classes!("title", wm.leaking.then_some("has-text-danger"))}>
+                            {if wm.leaking { "Detected" } else { "None" }}
+                        </p>
+                    </div>
+                </div>
+            </nav>
+            <table class="table is-fullwidth is-narrow">
+                <thead>
+                    <tr>
+                        <th>{"Period"}</th>
+                        <th class="has-text-right">{"Consumption"}</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {
+                        for DURATIONS.iter().zip(stats.measurements.iter()).map(|(duration, measurement)| html! {
+                            <tr>
+                                <td>{period_label(*duration)}</td>
+                                <td class="has-text-right">
+                                    {
+                                        measurement
+                                            .map(|measurement| measurement.start().statistics(measurement.end().edges_count()).to_string())
+                                            .unwrap_or_else(|| "-".into())
+                                    }
+                                </td>
+                            </tr>
+                        })
+                    }
+                </tbody>
+            </table>
+            <MeterChart/>
+        </div>
+    }
+}
+
+/// Arms or disarms the leak detection of the water meter
+#[function_component(FlowWatch)]
+pub fn flow_watch() -> Html {
+    let mcx = use_mcx();
+    let wm_store = use_store_value::<WaterMeterStore>();
+    let access_store = use_store_value::<AccessStore>();
+
+    let armed = wm_store.0.armed;
+
+    let onclick = Callback::from(move |_| {
+        let (command, question) = if armed {
+            (WaterMeterCommand::Disarm, "Disarm the flow watch?")
+        } else {
+            (WaterMeterCommand::Arm, "Arm the flow watch?")
+        };
+
+        if confirm(question) {
+            mcx.invoke(WebRequest::WaterMeterCommand(command));
+        }
+    });
+
+    html! {
+        <div class="tags has-addons is-centered">
+            <span class={classes!("tag", "is-medium", armed.then_some("is-success"))}>
+                {if armed { "Armed" } else { "Disarmed" }}
+            </span>
+            <button
+                class="tag is-medium is-link button"
+                disabled={!access_store.allows(UserRole::User)}
+                {onclick}
+            >
+                {if armed { "Disarm" } else { "Arm" }}
+            </button>
+        </div>
+    }
+}
+
+/// Cumulative consumption over the snapshots received since the page was loaded
+#[function_component(MeterChart)]
+pub fn meter_chart() -> Html {
+    const WIDTH: u64 = 400;
+    const HEIGHT: u64 = 100;
+
+    let stats_store = use_store_value::<WaterMeterStatsStore>();
+    let history = &stats_store.history;
+
+    let (Some(first), Some(last)) = (history.front(), history.back()) else {
+        return html! {};
+    };
+
+    let time_span = last.time_secs.saturating_sub(first.time_secs).max(1);
+    let consumption = last.edges_count.saturating_sub(first.edges_count).max(1);
+
+    let points = history
+        .iter()
+        .map(|snapshot| {
+            let x = snapshot.time_secs.saturating_sub(first.time_secs) * WIDTH / time_span;
+            let y = HEIGHT.saturating_sub(
+                snapshot.edges_count.saturating_sub(first.edges_count) * HEIGHT / consumption,
+            );
+
+            format!("{},{}", x, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    html! {
+        <figure class="image">
+            <svg viewBox={format!("0 0 {} {}", WIDTH, HEIGHT)} preserveAspectRatio="none" style="width: 100%; height: 8em;">
+                <polyline points={points} fill="none" stroke="hsl(204, 86%, 53%)" stroke-width="2"/>
+            </svg>
+            <figcaption class="has-text-centered is-size-7">
+                {format!("{} edges over {}", last.edges_count.saturating_sub(first.edges_count), period_label(time_span))}
+            </figcaption>
+        </figure>
+    }
+}
+
+fn period_label(secs: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = MINUTE * 60;
+    const DAY: u64 = HOUR * 24;
+
+    if secs >= DAY && secs % DAY == 0 {
+        format!("{} d", secs / DAY)
+    } else if secs >= HOUR && secs % HOUR == 0 {
+        format!("{} h", secs / HOUR)
+    } else if secs >= MINUTE {
+        format!("{} min", secs / MINUTE)
+    } else {
+        format!("{} s", secs)
+    }
+}
@@ -0,0 +1,25 @@
+use ruwm::dto::web::SessionToken;
+
+const SESSION_KEY: &str = "ruwm-session";
+
+/// Session tokens are kept in the browser local storage, so that a page reload or
+/// a reconnect of the websocket does not require the user to log in again.
+pub fn load() -> Option<SessionToken> {
+    storage()?.get_item(SESSION_KEY).ok()??.parse().ok()
+}
+
+pub fn store(token: &SessionToken) {
+    if let Some(storage) = storage() {
+        let _ = storage.set_item(SESSION_KEY, &token.to_string());
+    }
+}
+
+pub fn clear() {
+    if let Some(storage) = storage() {
+        let _ = storage.remove_item(SESSION_KEY);
+    }
+}
+
+fn storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
@@ -2,20 +2,38 @@ use std::rc::Rc;
 
 use yew::prelude::*;
 use yewdux::prelude::*;
+use yewdux_middleware::*;
 
-use ruwm::dto::valve::ValveState;
+use ruwm::dto::command::CommandSource;
+use ruwm::dto::emergency::LockoutState;
+use ruwm::dto::valve::{ValveCommand, ValveState};
+use ruwm::dto::web::{UserRole, WebRequest};
+
+use crate::access::AccessStore;
 
 #[derive(Default, Clone, Debug, Eq, PartialEq, Store)]
-pub struct ValveStore(pub Option<ValveState>);
+pub struct ValveStore {
+    pub state: Option<ValveState>,
+    pub source: Option<CommandSource>,
+    pub lockout: Option<LockoutState>,
+}
 
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct ValveMsg(pub Option<ValveState>);
+pub enum ValveMsg {
+    State(Option<ValveState>),
+    Source(Option<CommandSource>),
+    Lockout(Option<LockoutState>),
+}
 
 impl Reducer<ValveStore> for ValveMsg {
     fn apply(self, mut store: Rc<ValveStore>) -> Rc<ValveStore> {
         let state = Rc::make_mut(&mut store);
 
-        state.0 = self.0;
+        match self {
+            Self::State(valve_state) => state.state = valve_state,
+            Self::Source(source) => state.source = source,
+            Self::Lockout(lockout) => state.lockout = lockout,
+        }
 
         store
     }
@@ -23,9 +41,122 @@ impl Reducer<ValveStore> for ValveMsg {
 
 #[function_component(Valve)]
 pub fn valve() -> Html {
+    let mcx = use_mcx();
     let valve_store = use_store_value::<ValveStore>();
+    let access_store = use_store_value::<AccessStore>();
+
+    let valve_state = valve_store.state;
+    let lockout = valve_store.lockout;
+    let disabled = !access_store.allows(UserRole::User);
+
+    let command = |command: ValveCommand, question: &'static str| {
+        let mcx = mcx.clone();
+
+        Callback::from(move |_| {
+            if confirm(question) {
+                mcx.invoke(WebRequest::ValveCommand(command));
+            }
+        })
+    };
+
+    let (label, progress_class) = match valve_state {
+        Some(ValveState::Open) => ("Open", "is-success"),
+        Some(ValveState::Closed) => ("Closed", "is-danger"),
+        Some(ValveState::Opening(_)) => ("Opening", "is-info"),
+        Some(ValveState::Closing(_)) => ("Closing", "is-info"),
+        None => ("Unknown", "is-warning"),
+    };
+
+    let acknowledge = {
+        let mcx = mcx.clone();
+
+        Callback::from(move |_| {
+            if confirm("Acknowledge the emergency lockout and allow the valve to be opened again?")
+            {
+                mcx.invoke(WebRequest::AcknowledgeLockout);
+            }
+        })
+    };
 
     html! {
-        {format!("Valve State: {:?}", valve_store.0.as_ref())}
+        <div class="box">
+            <h2 class="title is-5">{"Valve"}</h2>
+            {
+                if let Some(lockout) = lockout {
+                    html! {
+                        <div class={classes!("notification", if lockout.condition_present { "is-danger" } else { "is-warning" })}>
+                            <p>
+                                {format!("Emergency lockout ({})", lockout.reason.text())}
+                                {
+                                    if lockout.condition_present {
+                                        " - the valve stays closed until the condition clears"
+                                    } else {
+                                        " - the condition has cleared and awaits acknowledgement by an admin, or by holding the third button"
+                                    }
+                                }
+                            </p>
+                            <button
+                                class="button is-small mt-2"
+                                disabled={!access_store.allows(UserRole::Admin) || !lockout.can_acknowledge()}
+                                onclick={acknowledge}
+                            >
+                                {"Acknowledge"}
+                            </button>
+                        </div>
+                    }
+                } else {
+                    html! {}
+                }
+            }
+            <p class="subtitle is-6">
+                {label}
+                {
+                    if let Some(source) = valve_store.source {
+                        html! {
+                            <span class={classes!("tag", "ml-2", (source == CommandSource::Emergency).then_some("is-danger"))}>
+                                {format!("by {}", source.text())}
+                            </span>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+            </p>
+            {
+                if let Some(valve_state) = valve_state {
+                    html! {
+                        <progress class={classes!("progress", progress_class)} value={valve_state.open_percentage().to_string()} max="100">
+                            {format!("{}%", valve_state.open_percentage())}
+                        </progress>
+                    }
+                } else {
+                    html! {
+                        <progress class={classes!("progress", progress_class)} max="100"/>
+                    }
+                }
+            }
+            <div class="buttons">
+                <button
+                    class="button is-success"
+                    disabled={disabled || lockout.is_some() || matches!(valve_state, Some(ValveState::Open | ValveState::Opening(_)))}
+                    onclick={command(ValveCommand::Open, "Open the valve?")}
+                >
+                    {"Open"}
+                </button>
+                <button
+                    class="button is-danger"
+                    disabled={disabled || matches!(valve_state, Some(ValveState::Closed | ValveState::Closing(_)))}
+                    onclick={command(ValveCommand::Close, "Close the valve?")}
+                >
+                    {"Close"}
+                </button>
+            </div>
+        </div>
     }
 }
+
+pub(crate) fn confirm(question: &str) -> bool {
+    web_sys::window()
+        .and_then(|window| window.confirm_with_message(question).ok())
+        .unwrap_or(false)
+}
@@ -0,0 +1,119 @@
+use std::rc::Rc;
+
+use web_sys::HtmlInputElement;
+
+use yew::prelude::*;
+use yewdux::prelude::*;
+use yewdux_middleware::*;
+
+use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
+
+use ruwm::dto::web::{WebRequest, WifiSettings};
+
+#[derive(Default, Clone, Debug, PartialEq, Store)]
+pub struct WifiStore {
+    pub connected: Option<bool>,
+    pub settings: Option<WifiSettings>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WifiMsg {
+    State(Option<bool>),
+    Settings(Option<WifiSettings>),
+}
+
+impl Reducer<WifiStore> for WifiMsg {
+    fn apply(self, mut store: Rc<WifiStore>) -> Rc<WifiStore> {
+        let state = Rc::make_mut(&mut store);
+
+        match self {
+            Self::State(connected) => state.connected = connected,
+            Self::Settings(settings) => state.settings = settings,
+        }
+
+        store
+    }
+}
+
+/// Client mode settings; the device falls back to an open access point only when it has no configuration at all
+#[function_component(WifiSettings)]
+pub fn wifi_settings() -> Html {
+    let mcx = use_mcx();
+    let wifi_store = use_store_value::<WifiStore>();
+
+    let ssid_ref = use_node_ref();
+    let password_ref = use_node_ref();
+
+    let current_ssid = match wifi_store.settings.as_ref() {
+        Some(settings) if settings.access_point => format!("{} (access point)", settings.ssid),
+        Some(settings) => settings.ssid.as_str().to_owned(),
+        None => "-".into(),
+    };
+
+    // The device keeps the current password when an empty one is sent for the same network
+    let password_placeholder = match wifi_store.settings.as_ref() {
+        Some(settings) if !settings.access_point && settings.password_set => "(unchanged)",
+        _ => "",
+    };
+
+    let status = match wifi_store.connected {
+        Some(true) => "Connected",
+        Some(false) => "Not connected",
+        None => "Stopped",
+    };
+
+    let onsubmit = {
+        let ssid_ref = ssid_ref.clone();
+        let password_ref = password_ref.clone();
+
+        Callback::from(move |event: SubmitEvent| {
+            event.prevent_default();
+
+            let ssid = ssid_ref.cast::<HtmlInputElement>().unwrap().value();
+            let password = password_ref.cast::<HtmlInputElement>().unwrap().value();
+
+            let (Ok(ssid), Ok(password_value)) =
+                (ssid.as_str().try_into(), password.as_str().try_into())
+            else {
+                return;
+            };
+
+            mcx.invoke(WebRequest::WifiSettingsUpdate(Configuration::Client(
+                ClientConfiguration {
+                    ssid,
+                    password: password_value,
+                    auth_method: if password.is_empty() {
+                        AuthMethod::None
+                    } else {
+                        AuthMethod::WPA2Personal
+                    },
+                    ..Default::default()
+                },
+            )));
+        })
+    };
+
+    html! {
+        <div class="box">
+            <h2 class="title is-5">{"WiFi"}</h2>
+            <p class="subtitle is-6">{format!("{}: {}", current_ssid, status)}</p>
+            <form {onsubmit}>
+                <div class="field">
+                    <label class="label">{"SSID"}</label>
+                    <div class="control">
+                        <input class="input" type="text" maxlength="32" ref={ssid_ref}/>
+                    </div>
+                </div>
+                <div class="field">
+                    <label class="label">{"Password"}</label>
+                    <div class="control">
+                        <input class="input" type="password" maxlength="64" placeholder={password_placeholder} ref={password_ref}/>
+                    </div>
+                </div>
+                <div class="control">
+                    <button class="button is-link" type="submit">{"Apply"}</button>
+                </div>
+            </form>
+        </div>
+    }
+}
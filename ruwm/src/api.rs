@@ -0,0 +1,461 @@
+use core::fmt::Write as _;
+use core::str;
+
+use serde::{Deserialize, Serialize};
+
+use log::info;
+
+use heapless::String;
+
+use embedded_io_async::Write;
+
+use embedded_svc::http::server::asynch::{Connection, Request};
+use embedded_svc::http::Method;
+
+use crate::battery::{self, BatteryState};
+use crate::command::CommandSource;
+use crate::emergency::{self, LockoutState};
+use crate::mqtt::{self, MqttConfiguration};
+use crate::users;
+use crate::valve::{self, ValveCommand, ValveState};
+use crate::web::{UserRole, WebEvent, WebRequest, PASSWORD_MAX_LEN, USERNAME_MAX_LEN};
+use crate::wifi;
+use crate::wm::{self, WaterMeterCommand, WaterMeterState, PULSE_TRACE_ENCODED_MAX_LEN};
+use crate::wm_stats;
+
+/// All API endpoints live under this path prefix
+pub const API_PREFIX: &str = "/api/";
+
+const BODY_MAX_LEN: usize = 64;
+/// Long enough for a configuration with all of its fields at their maximum length
+const MQTT_BODY_MAX_LEN: usize = 1536;
+const JSON_MAX_LEN: usize = 2048;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Endpoint {
+    State,
+    Stats,
+    Valve,
+    MeterArm,
+    MeterTrace,
+    MeterTracing,
+    LockoutAck,
+    Mqtt,
+}
+
+impl Endpoint {
+    fn resolve(path: &str) -> Option<Self> {
+        match path.split('?').next().unwrap_or(path) {
+            "/api/state" => Some(Self::State),
+            "/api/stats" => Some(Self::Stats),
+            "/api/valve" => Some(Self::Valve),
+            "/api/meter/arm" => Some(Self::MeterArm),
+            "/api/meter/trace" => Some(Self::MeterTrace),
+            "/api/meter/tracing" => Some(Self::MeterTracing),
+            "/api/lockout/ack" => Some(Self::LockoutAck),
+            "/api/mqtt" => Some(Self::Mqtt),
+            _ => None,
+        }
+    }
+
+    fn method(&self) -> Method {
+        match self {
+            Self::State | Self::Stats | Self::MeterTrace => Method::Get,
+            Self::Valve | Self::MeterArm | Self::MeterTracing | Self::LockoutAck | Self::Mqtt => {
+                Method::Post
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct ApiState {
+    valve: Option<ValveState>,
+    valve_source: Option<CommandSource>,
+    lockout: Option<LockoutState>,
+    water_meter: WaterMeterState,
+    battery: BatteryState,
+    mqtt_connected: Option<bool>,
+    wifi_connected: Option<bool>,
+}
+
+impl ApiState {
+    fn get() -> Self {
+        Self {
+            valve: valve::STATE.get(),
+            valve_source: valve::SOURCE_STATE.get(),
+            lockout: emergency::STATE.get(),
+            water_meter: wm::STATE.get(),
+            battery: battery::STATE.get(),
+            mqtt_connected: mqtt::STATE.get(),
+            wifi_connected: wifi::STATE.get(),
+        }
+    }
+
+    /// The web events carrying the same states, so that the API grants exactly what the web UI does
+    fn events(&self) -> [WebEvent; 7] {
+        [
+            WebEvent::ValveState(self.valve),
+            WebEvent::ValveSourceState(self.valve_source),
+            WebEvent::LockoutState(self.lockout),
+            WebEvent::WaterMeterState(self.water_meter),
+            WebEvent::BatteryState(self.battery),
+            WebEvent::MqttState(self.mqtt_connected),
+            WebEvent::WifiState(self.wifi_connected),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ValveBody {
+    command: ValveCommand,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MeterArmBody {
+    armed: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MeterTracingBody {
+    enabled: bool,
+}
+
+/// Serves a request whose path starts with `API_PREFIX`.
+///
+/// Clients authenticate on each request, either with a session token issued to the web UI
+/// (`Authorization: Bearer <token>`), or with the credentials of a user account (`Authorization: Basic ...`).
+/// Failed credentials count towards the lockout of `users::authenticate`.
+///
+/// - `GET /api/state` - the valve (with the source of its last command and its lockout), water meter, battery and connectivity states
+/// - `GET /api/stats` - the water meter statistics
+/// - `POST /api/valve` - `{"command": "Open"}` or `{"command": "Close"}`
+/// - `POST /api/meter/arm` - `{"armed": true}` or `{"armed": false}`
+/// - `GET /api/meter/trace` - the recorded pulse trace, encoded as in `PulseTrace::encode`; admins only
+/// - `POST /api/meter/tracing` - `{"enabled": true}` starts recording a fresh pulse trace, `{"enabled": false}` stops; admins only
+/// - `POST /api/lockout/ack` - acknowledges the emergency lockout; admins only, 409 while it cannot be acknowledged
+/// - `POST /api/mqtt` - a `MqttConfiguration`, which replaces the current one and reconnects the client; admins only
+pub async fn handle<C>(mut request: Request<C>) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    let Some(endpoint) = Endpoint::resolve(request.uri()) else {
+        return respond(request, 404).await;
+    };
+
+    if request.method() != endpoint.method() {
+        return respond(request, 405).await;
+    }
+
+    let user_role = request.header("Authorization").and_then(authorize);
+
+    info!("[API] {:?}; role: {:?}", endpoint, user_role);
+
+    match endpoint {
+        Endpoint::State => {
+            let state = ApiState::get();
+
+            if state
+                .events()
+                .iter()
+                .all(|event| is_allowed(event.role(), user_role))
+            {
+                respond_json(request, &state).await
+            } else {
+                respond_denied(request, user_role).await
+            }
+        }
+        Endpoint::Stats => {
+            let stats = wm_stats::STATE.get();
+
+            if is_allowed(WebEvent::WaterMeterStatsState(stats).role(), user_role) {
+                respond_json(request, &stats).await
+            } else {
+                respond_denied(request, user_role).await
+            }
+        }
+        Endpoint::Valve => {
+            let web_request = read_json::<_, _, BODY_MAX_LEN>(&mut request)
+                .await?
+                .map(|body: ValveBody| WebRequest::ValveCommand(body.command));
+
+            execute(request, web_request, user_role).await
+        }
+        Endpoint::MeterArm => {
+            let web_request =
+                read_json::<_, _, BODY_MAX_LEN>(&mut request)
+                    .await?
+                    .map(|body: MeterArmBody| {
+                        WebRequest::WaterMeterCommand(if body.armed {
+                            WaterMeterCommand::Arm
+                        } else {
+                            WaterMeterCommand::Disarm
+                        })
+                    });
+
+            execute(request, web_request, user_role).await
+        }
+        Endpoint::MeterTrace => {
+            if is_allowed(Some(UserRole::Admin), user_role) {
+                respond_pulse_trace(request).await
+            } else {
+                respond_denied(request, user_role).await
+            }
+        }
+        Endpoint::MeterTracing => {
+            if !is_allowed(Some(UserRole::Admin), user_role) {
+                return respond_denied(request, user_role).await;
+            }
+
+            if let Some(body) = read_json::<_, MeterTracingBody, BODY_MAX_LEN>(&mut request).await?
+            {
+                wm::set_tracing(body.enabled);
+
+                respond(request, 204).await
+            } else {
+                respond(request, 400).await
+            }
+        }
+        Endpoint::LockoutAck => {
+            execute(request, Some(WebRequest::AcknowledgeLockout), user_role).await
+        }
+        Endpoint::Mqtt => {
+            if !is_allowed(Some(UserRole::Admin), user_role) {
+                return respond_denied(request, user_role).await;
+            }
+
+            if let Some(configuration) =
+                read_json::<_, MqttConfiguration, MQTT_BODY_MAX_LEN>(&mut request).await?
+            {
+                mqtt::CONFIGURATION.update(Some(configuration));
+
+                respond(request, 204).await
+            } else {
+                respond(request, 400).await
+            }
+        }
+    }
+}
+
+async fn execute<C>(
+    request: Request<C>,
+    web_request: Option<WebRequest>,
+    user_role: Option<UserRole>,
+) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    let Some(web_request) = web_request else {
+        return respond(request, 400).await;
+    };
+
+    if !web_request.is_permitted(user_role) {
+        return respond_denied(request, user_role).await;
+    }
+
+    let status = match web_request {
+        WebRequest::ValveCommand(command) => {
+            valve::command(command, CommandSource::Api);
+            202
+        }
+        WebRequest::WaterMeterCommand(command) => {
+            wm::command(command, CommandSource::Api);
+            202
+        }
+        WebRequest::AcknowledgeLockout => {
+            if emergency::acknowledge(CommandSource::Api) {
+                204
+            } else {
+                409
+            }
+        }
+        _ => unreachable!(),
+    };
+
+    respond(request, status).await
+}
+
+pub(crate) fn authorize(authorization: &str) -> Option<UserRole> {
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        users::resume_session(&token.trim().parse().ok()?).map(|(_, role)| role)
+    } else if let Some(credentials) = authorization.strip_prefix("Basic ") {
+        let mut buf = [0; USERNAME_MAX_LEN + PASSWORD_MAX_LEN + 1];
+        let len = base64_decode(credentials.trim(), &mut buf)?;
+
+        let (username, password) = str::from_utf8(&buf[..len]).ok()?.split_once(':')?;
+
+        users::authenticate(username, password)
+    } else {
+        None
+    }
+}
+
+/// Whether a user with the given role - or an anonymous one - may see data restricted to `role`
+pub(crate) fn is_allowed(role: Option<UserRole>, user_role: Option<UserRole>) -> bool {
+    role <= user_role
+}
+
+/// Reads the request body and parses it as JSON; `None` if the body is too long or malformed.
+async fn read_json<C, T, const N: usize>(request: &mut Request<C>) -> Result<Option<T>, C::Error>
+where
+    C: Connection,
+    T: for<'de> Deserialize<'de>,
+{
+    let mut buf = [0; N];
+    let mut len = 0;
+
+    loop {
+        if len == buf.len() {
+            // The body does not fit, so it cannot be a valid one
+            return Ok(None);
+        }
+
+        let read = request.read(&mut buf[len..]).await?;
+        if read == 0 {
+            break;
+        }
+
+        len += read;
+    }
+
+    Ok(serde_json_core::from_slice(&buf[..len])
+        .ok()
+        .map(|(value, _)| value))
+}
+
+async fn respond_json<C, T>(request: Request<C>, value: &T) -> Result<(), C::Error>
+where
+    C: Connection,
+    T: Serialize,
+{
+    let mut buf = [0; JSON_MAX_LEN];
+
+    let Ok(len) = serde_json_core::to_slice(value, &mut buf) else {
+        return respond(request, 500).await;
+    };
+
+    let mut content_len = String::<10>::new();
+    write!(&mut content_len, "{}", len).unwrap();
+
+    let mut response = request
+        .into_response(
+            200,
+            None,
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Length", &content_len),
+                ("Cache-Control", "no-store"),
+            ],
+        )
+        .await?;
+
+    response.write_all(&buf[..len]).await
+}
+
+async fn respond_pulse_trace<C>(request: Request<C>) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    let mut buf = [0; PULSE_TRACE_ENCODED_MAX_LEN];
+
+    let len = wm::TRACE.lock(|trace| trace.borrow().encode(&mut buf));
+
+    let mut content_len = String::<10>::new();
+    write!(&mut content_len, "{}", len).unwrap();
+
+    let mut response = request
+        .into_response(
+            200,
+            None,
+            &[
+                ("Content-Type", "application/octet-stream"),
+                ("Content-Length", &content_len),
+                ("Cache-Control", "no-store"),
+            ],
+        )
+        .await?;
+
+    response.write_all(&buf[..len]).await
+}
+
+/// Anonymous clients are asked to authenticate, while authenticated ones lack the role
+pub(crate) async fn respond_denied<C>(
+    request: Request<C>,
+    user_role: Option<UserRole>,
+) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    if user_role.is_some() {
+        respond(request, 403).await
+    } else {
+        request
+            .into_response(
+                401,
+                None,
+                &[
+                    ("WWW-Authenticate", "Basic realm=\"ruwm\""),
+                    ("Content-Length", "0"),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+pub(crate) async fn respond<C>(request: Request<C>, status: u16) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    request
+        .into_response(status, None, &[("Content-Length", "0")])
+        .await?;
+
+    Ok(())
+}
+
+/// Decodes standard, padded base64; `None` if the input is malformed or does not fit
+fn base64_decode(input: &str, buf: &mut [u8]) -> Option<usize> {
+    fn sextet(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let input = input.as_bytes();
+    if input.len() % 4 != 0 {
+        return None;
+    }
+
+    let mut len = 0;
+
+    for (index, chunk) in input.chunks(4).enumerate() {
+        let last = index == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut value = 0;
+        for c in &chunk[..4 - padding] {
+            value = (value << 6) | sextet(*c)?;
+        }
+        value <<= 6 * padding as u32;
+
+        let bytes = value.to_be_bytes();
+        let decoded = &bytes[1..4 - padding];
+
+        buf.get_mut(len..len + decoded.len())?
+            .copy_from_slice(decoded);
+        len += decoded.len();
+    }
+
+    Some(len)
+}
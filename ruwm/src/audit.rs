@@ -0,0 +1,126 @@
+use core::cell::RefCell;
+
+use log::info;
+
+use embassy_futures::select::{select, select_slice, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use embedded_svc::wifi::Configuration;
+
+use channel_bridge::notification::Notification;
+
+use crate::mqtt::{self, MqttConfiguration, OutboxPayload};
+use crate::state::{self, Subscriber};
+use crate::users::{self, Users, USERS_MAX};
+use crate::wifi;
+use crate::wm::{self, WaterMeterState};
+
+pub use crate::dto::audit::*;
+
+pub static LOG: Mutex<CriticalSectionRawMutex, RefCell<AuditLog<AUDIT_LOG_SIZE>>> =
+    Mutex::new(RefCell::new(AuditLog::new()));
+
+static NOTIFY: &[&Notification] = &[
+    &crate::screen::AUDIT_LOG_NOTIF,
+    &crate::mqtt::AUDIT_LOG_NOTIF,
+    &PERSIST_NOTIFY,
+];
+
+static PERSIST_NOTIFY: Notification = Notification::new();
+
+static WM_STATE: Subscriber<WaterMeterState> = Subscriber::new();
+static MQTT_CONFIGURATION: Subscriber<Option<MqttConfiguration>> = Subscriber::new();
+static WIFI_CONFIGURATION: Subscriber<Option<Configuration>> = Subscriber::new();
+static USERS: Subscriber<Users<USERS_MAX>> = Subscriber::new();
+
+pub(crate) fn reset() {
+    LOG.lock(|log| *log.borrow_mut() = AuditLog::new());
+
+    wm::STATE.unsubscribe(&WM_STATE);
+    mqtt::CONFIGURATION.unsubscribe(&MQTT_CONFIGURATION);
+    wifi::CONFIGURATION.unsubscribe(&WIFI_CONFIGURATION);
+    users::STATE.unsubscribe(&USERS);
+
+    state::clear(&[&PERSIST_NOTIFY]);
+}
+
+/// Appends an event to the log and publishes it over MQTT.
+pub fn record(event: AuditEvent) {
+    let entry = LOG.lock(|log| log.borrow_mut().push(Instant::now().as_secs(), event));
+
+    info!(
+        "[AUDIT] #{} {}s: {}",
+        entry.boot, entry.time_secs, entry.event
+    );
+
+    mqtt::enqueue(OutboxPayload::Event(event));
+
+    for notification in NOTIFY {
+        notification.notify();
+    }
+}
+
+/// Records a boot; wakeups from deep sleep only start a new boot in the log.
+///
+/// A battery-powered device wakes up on every timer tick and on every pulse, which would otherwise evict the
+/// useful history from the log and rewrite the persisted log on each wakeup.
+pub fn boot(reason: BootReason) {
+    if reason.is_wakeup() {
+        let boot = LOG.lock(|log| log.borrow_mut().wake());
+
+        info!("[AUDIT] #{} wakeup ({})", boot, reason.text());
+    } else {
+        record(AuditEvent::Boot(reason));
+    }
+}
+
+pub fn get() -> AuditLog<AUDIT_LOG_SIZE> {
+    LOG.lock(|log| log.borrow().clone())
+}
+
+/// Records the state transitions worth auditing; commands are recorded by the subsystems handling them.
+pub async fn process() {
+    let subscribed = wm::STATE.subscribe(&WM_STATE)
+        && mqtt::CONFIGURATION.subscribe(&MQTT_CONFIGURATION)
+        && wifi::CONFIGURATION.subscribe(&WIFI_CONFIGURATION)
+        && users::STATE.subscribe(&USERS);
+
+    if !subscribed {
+        panic!("Too many state subscribers");
+    }
+
+    let mut leaking = wm::STATE.get().leaking;
+
+    // Only which of the settings changed is recorded, so the changed values are left pending in the subscribers
+    let mut notifs = [
+        MQTT_CONFIGURATION.notification().wait(),
+        WIFI_CONFIGURATION.notification().wait(),
+        USERS.notification().wait(),
+    ];
+
+    loop {
+        match select(WM_STATE.next(), select_slice(&mut notifs)).await {
+            Either::First(update) => {
+                if update.value.leaking != leaking {
+                    leaking = update.value.leaking;
+
+                    record(AuditEvent::Leak(leaking));
+                }
+            }
+            Either::Second((_, 0)) => record(AuditEvent::SettingsChanged(Settings::Mqtt)),
+            Either::Second((_, 1)) => record(AuditEvent::SettingsChanged(Settings::Wifi)),
+            Either::Second((_, 2)) => record(AuditEvent::SettingsChanged(Settings::Users)),
+            Either::Second(_) => unreachable!(),
+        }
+    }
+}
+
+pub async fn persist(mut persister: impl FnMut(AuditLog<AUDIT_LOG_SIZE>)) {
+    loop {
+        PERSIST_NOTIFY.wait().await;
+
+        persister(get());
+    }
+}
@@ -14,17 +14,7 @@ pub trait Adc {
     async fn read(&mut self) -> Result<u16, Self::Error>;
 }
 
-pub static STATE: State<BatteryState> = State::new(
-    "BATTERY",
-    BatteryState::new(),
-    &[
-        &crate::keepalive::NOTIF,
-        &crate::emergency::BATTERY_STATE_NOTIF,
-        &crate::screen::BATTERY_STATE_NOTIF,
-        &crate::mqtt::BATTERY_STATE_NOTIF,
-        &crate::web::BATTERY_STATE_NOTIF,
-    ],
-);
+pub static STATE: State<BatteryState> = State::new("BATTERY", BatteryState::new(), &[]);
 
 pub async fn process(mut battery_adc: impl Adc, mut power_pin: impl InputPin) {
     const ROUND_UP: u16 = 50; // TODO: Make it smaller once ADC is connected
@@ -21,6 +21,10 @@ pub enum PressedLevel {
 static BUTTON1_NOTIFY: &[&Notification] = &[&crate::screen::BUTTON1_PRESSED_NOTIF];
 static BUTTON2_NOTIFY: &[&Notification] = &[&crate::screen::BUTTON2_PRESSED_NOTIF];
 static BUTTON3_NOTIFY: &[&Notification] = &[&crate::screen::BUTTON3_PRESSED_NOTIF];
+static BUTTON3_HELD_NOTIFY: &[&Notification] = &[&crate::emergency::BUTTON_HELD_NOTIF];
+
+/// How long the third button has to be held to acknowledge an emergency lockout
+pub const BUTTON3_HOLD_DURATION: Duration = Duration::from_secs(5);
 
 pub async fn button1_process(pin: impl InputPin + Wait, pressed_level: PressedLevel) {
     button_process(pin, pressed_level, "BUTTON1 STATE", BUTTON1_NOTIFY).await;
@@ -31,7 +35,16 @@ pub async fn button2_process(pin: impl InputPin + Wait, pressed_level: PressedLe
 }
 
 pub async fn button3_process(pin: impl InputPin + Wait, pressed_level: PressedLevel) {
-    button_process(pin, pressed_level, "BUTTON3 STATE", BUTTON3_NOTIFY).await;
+    hold_process(
+        pin,
+        pressed_level,
+        Some(Duration::from_millis(50)),
+        "BUTTON3 STATE",
+        BUTTON3_NOTIFY,
+        BUTTON3_HOLD_DURATION,
+        BUTTON3_HELD_NOTIFY,
+    )
+    .await;
 }
 
 async fn button_process<'a>(
@@ -68,6 +81,53 @@ pub async fn process(
     }
 }
 
+/// Like `process`, but additionally notifies `held_sink` when the button is kept pressed for `hold_duration`
+pub async fn hold_process(
+    mut pin: impl InputPin + Wait,
+    pressed_level: PressedLevel,
+    debounce_duration: Option<Duration>,
+    pressed_sink_msg: &str,
+    pressed_sink: &[&Notification],
+    hold_duration: Duration,
+    held_sink: &[&Notification],
+) {
+    let mut released = false;
+
+    loop {
+        // Once the release was awaited below, waiting for it again would miss a press following it
+        // within the debounce duration, and with it a hold
+        if released {
+            log_err!(wait_level(&mut pin, pressed_level, true, debounce_duration).await);
+        } else {
+            log_err!(wait_press(&mut pin, pressed_level, debounce_duration).await);
+        }
+
+        log::info!("[{}]", pressed_sink_msg);
+
+        for notification in pressed_sink {
+            notification.notify();
+        }
+
+        released = match select(
+            wait_level(&mut pin, pressed_level, false, debounce_duration),
+            Timer::after(hold_duration),
+        )
+        .await
+        {
+            Either::First(_) => true,
+            Either::Second(_) => {
+                log::info!("[{}]: held", pressed_sink_msg);
+
+                for notification in held_sink {
+                    notification.notify();
+                }
+
+                false
+            }
+        };
+    }
+}
+
 async fn wait_level<P>(
     pin: &mut P,
     pressed_level: PressedLevel,
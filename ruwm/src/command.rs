@@ -0,0 +1,128 @@
+use core::cell::Cell;
+use core::fmt::Debug;
+
+use log::warn;
+
+use heapless::Vec;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::{Channel, TrySendError};
+
+pub use crate::dto::command::*;
+
+/// A bounded FIFO queue of the commands for a subsystem.
+///
+/// Commands are received in the order they were issued, regardless of their source. Two coalescing rules apply:
+/// - A command identical - including its source - to the most recently queued one is dropped
+///   while the latter is still pending, as executing it again would be a no-op
+/// - When the queue is full, the oldest pending command of a lower priority than the new one is dropped
+///   to make room; if there is none, the new command is dropped instead, so that e.g. an emergency
+///   command is never evicted by remote ones
+pub struct CommandQueue<T, const N: usize> {
+    channel: Channel<CriticalSectionRawMutex, Command<T>, N>,
+    queued: Mutex<CriticalSectionRawMutex, Cell<Queued<T>>>,
+}
+
+/// The most recently queued command, and how many commands are pending
+#[derive(Copy, Clone)]
+struct Queued<T> {
+    last: Option<Command<T>>,
+    len: usize,
+}
+
+impl<T> Queued<T> {
+    const fn new() -> Self {
+        Self { last: None, len: 0 }
+    }
+}
+
+impl<T, const N: usize> CommandQueue<T, N>
+where
+    T: Copy + PartialEq + Debug,
+{
+    pub const fn new() -> Self {
+        Self {
+            channel: Channel::new(),
+            queued: Mutex::new(Cell::new(Queued::new())),
+        }
+    }
+
+    pub fn send(&self, command: Command<T>) {
+        self.queued.lock(|queued| {
+            let mut state = queued.get();
+
+            if state.last == Some(command) {
+                return;
+            }
+
+            if let Err(TrySendError::Full(command)) = self.channel.try_send(command) {
+                let mut pending = Vec::<_, N>::new();
+
+                while let Ok(queued) = self.channel.try_receive() {
+                    pending.push(queued).unwrap();
+                }
+
+                let evicted = pending
+                    .iter()
+                    .position(|queued| queued.priority() < command.priority());
+
+                if let Some(evicted) = evicted {
+                    let evicted = pending.remove(evicted);
+
+                    warn!("Command queue full, dropped {:?}", evicted);
+
+                    pending.push(command).unwrap();
+                    state.last = Some(command);
+                } else {
+                    warn!("Command queue full, dropped {:?}", command);
+                }
+
+                for queued in pending {
+                    self.channel.try_send(queued).unwrap();
+                }
+            } else {
+                state.last = Some(command);
+                state.len += 1;
+            }
+
+            queued.set(state);
+        });
+    }
+
+    pub async fn receive(&self) -> Command<T> {
+        let command = self.channel.receive().await;
+
+        self.queued.lock(|queued| {
+            let mut state = queued.get();
+
+            state.len = state.len.saturating_sub(1);
+
+            if state.len == 0 {
+                state.last = None;
+            }
+
+            queued.set(state);
+        });
+
+        command
+    }
+
+    /// Drops all pending commands
+    pub fn clear(&self) {
+        self.queued.lock(|queued| {
+            while self.channel.try_receive().is_ok() {}
+
+            queued.set(Queued::new());
+        });
+    }
+}
+
+impl<T, const N: usize> Default for CommandQueue<T, N>
+where
+    T: Copy + PartialEq + Debug,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
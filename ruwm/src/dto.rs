@@ -1,4 +1,9 @@
+pub mod audit;
 pub mod battery;
+pub mod command;
+pub mod emergency;
+pub mod mqtt;
+pub mod pulse_trace;
 pub mod valve;
 pub mod water_meter;
 pub mod water_meter_stats;
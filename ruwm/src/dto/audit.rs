@@ -0,0 +1,188 @@
+use core::fmt::{self, Debug, Display};
+
+use serde::{Deserialize, Serialize};
+
+use heapless::Vec;
+
+use super::command::CommandSource;
+use super::emergency::LockoutReason;
+use super::valve::ValveCommand;
+use super::water_meter::WaterMeterCommand;
+
+pub const AUDIT_LOG_SIZE: usize = 32;
+
+/// Why the device started; mapped from the platform-specific reset or wakeup cause
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BootReason {
+    PowerOn,
+    Timer,
+    Pulse,
+    Button,
+    Other,
+}
+
+impl BootReason {
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::PowerOn => "power on",
+            Self::Timer => "timer",
+            Self::Pulse => "pulse",
+            Self::Button => "button",
+            Self::Other => "other",
+        }
+    }
+
+    /// A wakeup from deep sleep which the device does on its own, as opposed to a cold boot, a reset or a user action
+    pub fn is_wakeup(&self) -> bool {
+        matches!(self, Self::Timer | Self::Pulse)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Settings {
+    Mqtt,
+    Wifi,
+    Users,
+}
+
+impl Settings {
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Mqtt => "mqtt",
+            Self::Wifi => "wifi",
+            Self::Users => "users",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    Boot(BootReason),
+    ValveCommand(ValveCommand, CommandSource),
+    /// A valve command refused due to an emergency lockout
+    ValveCommandRefused(ValveCommand, CommandSource),
+    WaterMeterCommand(WaterMeterCommand, CommandSource),
+    /// A leak was detected (`true`) or the leak flag was cleared (`false`)
+    Leak(bool),
+    SettingsChanged(Settings),
+    Lockout(LockoutReason),
+    LockoutAcknowledged(CommandSource),
+}
+
+/// A short, human readable form, e.g. `valve close (web)`; used on the screen and as the MQTT payload
+impl Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Boot(reason) => write!(f, "boot ({})", reason.text()),
+            Self::ValveCommand(command, source) => {
+                write!(
+                    f,
+                    "valve {} ({})",
+                    valve_command_text(command),
+                    source.text()
+                )
+            }
+            Self::ValveCommandRefused(command, source) => write!(
+                f,
+                "valve {} refused ({})",
+                valve_command_text(command),
+                source.text()
+            ),
+            Self::WaterMeterCommand(command, source) => write!(
+                f,
+                "{} ({})",
+                match command {
+                    WaterMeterCommand::Arm => "arm",
+                    WaterMeterCommand::Disarm => "disarm",
+                },
+                source.text()
+            ),
+            Self::Leak(true) => write!(f, "leak detected"),
+            Self::Leak(false) => write!(f, "leak cleared"),
+            Self::SettingsChanged(settings) => write!(f, "{} settings changed", settings.text()),
+            Self::Lockout(reason) => write!(f, "lockout ({})", reason.text()),
+            Self::LockoutAcknowledged(source) => write!(f, "lockout ack ({})", source.text()),
+        }
+    }
+}
+
+fn valve_command_text(command: &ValveCommand) -> &'static str {
+    match command {
+        ValveCommand::Open => "open",
+        ValveCommand::Close => "close",
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// The boot the entry was recorded in, as there is no wall clock to order entries across restarts
+    pub boot: u16,
+    /// Seconds since that boot
+    pub time_secs: u64,
+    pub event: AuditEvent,
+}
+
+/// A bounded log of the most recent audit entries; the oldest entry is dropped when the log is full
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLog<const N: usize> {
+    boot: u16,
+    entries: Vec<AuditEntry, N>,
+}
+
+impl<const N: usize> AuditLog<N> {
+    pub const fn new() -> Self {
+        Self {
+            boot: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn boot(&self) -> u16 {
+        self.boot
+    }
+
+    /// Oldest entry first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &AuditEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Starts a new boot without recording an entry, so that the entries recorded after a wakeup are still ordered
+    pub fn wake(&mut self) -> u16 {
+        self.boot = self.boot.wrapping_add(1);
+        self.boot
+    }
+
+    pub fn push(&mut self, time_secs: u64, event: AuditEvent) -> AuditEntry {
+        if matches!(event, AuditEvent::Boot(_)) {
+            self.boot = self.boot.wrapping_add(1);
+        }
+
+        let entry = AuditEntry {
+            boot: self.boot,
+            time_secs,
+            event,
+        };
+
+        if self.entries.is_full() {
+            self.entries.remove(0);
+        }
+
+        self.entries.push(entry).unwrap();
+
+        entry
+    }
+}
+
+impl<const N: usize> Default for AuditLog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,61 @@
+use core::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+/// Where a command came from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandSource {
+    Button,
+    Web,
+    Api,
+    Mqtt,
+    Emergency,
+    /// A recorded pulse trace being replayed
+    Replay,
+}
+
+impl CommandSource {
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Button => "button",
+            Self::Web => "web",
+            Self::Api => "api",
+            Self::Mqtt => "mqtt",
+            Self::Emergency => "emergency",
+            Self::Replay => "replay",
+        }
+    }
+
+    pub fn priority(&self) -> CommandPriority {
+        match self {
+            Self::Web | Self::Api | Self::Mqtt | Self::Replay => CommandPriority::Remote,
+            Self::Button => CommandPriority::Local,
+            Self::Emergency => CommandPriority::Emergency,
+        }
+    }
+}
+
+/// Commands of a lower priority cannot undo what a command of a higher priority is holding
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CommandPriority {
+    Remote,
+    Local,
+    Emergency,
+}
+
+/// A command, together with where it came from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Command<T> {
+    pub command: T,
+    pub source: CommandSource,
+}
+
+impl<T> Command<T> {
+    pub const fn new(command: T, source: CommandSource) -> Self {
+        Self { command, source }
+    }
+
+    pub fn priority(&self) -> CommandPriority {
+        self.source.priority()
+    }
+}
@@ -0,0 +1,34 @@
+use core::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+/// The condition which triggered an emergency lockout
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockoutReason {
+    Leak,
+    BatteryLow,
+}
+
+impl LockoutReason {
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Leak => "leak",
+            Self::BatteryLow => "battery low",
+        }
+    }
+}
+
+/// An emergency lockout, which keeps the valve closed until acknowledged
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockoutState {
+    pub reason: LockoutReason,
+    /// Whether the triggering condition is still present
+    pub condition_present: bool,
+}
+
+impl LockoutState {
+    /// A lockout can only be acknowledged once its triggering condition has cleared
+    pub fn can_acknowledge(&self) -> bool {
+        !self.condition_present
+    }
+}
@@ -0,0 +1,136 @@
+use core::fmt::{self, Debug};
+
+use serde::{Deserialize, Serialize};
+
+use heapless::String;
+
+pub const MQTT_URL_MAX_LEN: usize = 128;
+pub const MQTT_CLIENT_ID_MAX_LEN: usize = 64;
+pub const MQTT_USERNAME_MAX_LEN: usize = 64;
+pub const MQTT_PASSWORD_MAX_LEN: usize = 64;
+pub const MQTT_CERTIFICATE_NAME_MAX_LEN: usize = 15;
+pub const MQTT_TOPIC_SEGMENT_MAX_LEN: usize = 32;
+
+#[derive(Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MqttConfiguration {
+    pub protocol_311: bool,
+    pub url: String<MQTT_URL_MAX_LEN>,
+    pub client_id: String<MQTT_CLIENT_ID_MAX_LEN>,
+    pub username: String<MQTT_USERNAME_MAX_LEN>,
+    pub password: String<MQTT_PASSWORD_MAX_LEN>,
+    /// Storage key of the CA certificate (PEM or DER) the broker certificate is pinned to
+    pub ca_certificate: Option<String<MQTT_CERTIFICATE_NAME_MAX_LEN>>,
+    /// Storage key of the client certificate (PEM or DER)
+    pub client_certificate: Option<String<MQTT_CERTIFICATE_NAME_MAX_LEN>>,
+    /// Storage key of the client private key (PEM or DER)
+    pub client_key: Option<String<MQTT_CERTIFICATE_NAME_MAX_LEN>>,
+    pub topics: MqttTopics,
+}
+
+impl MqttConfiguration {
+    pub const fn new() -> Self {
+        Self {
+            protocol_311: false,
+            url: String::new(),
+            client_id: String::new(),
+            username: String::new(),
+            password: String::new(),
+            ca_certificate: None,
+            client_certificate: None,
+            client_key: None,
+            topics: MqttTopics::new(),
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        ["mqtts://", "ssl://", "wss://"]
+            .iter()
+            .any(|scheme| self.url.starts_with(scheme))
+    }
+}
+
+impl Debug for MqttConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttConfiguration")
+            .field("protocol_311", &self.protocol_311)
+            .field("url", &self.url)
+            .field("client_id", &self.client_id)
+            .field("username", &self.username)
+            .field("password", &"***")
+            .field("ca_certificate", &self.ca_certificate)
+            .field("client_certificate", &self.client_certificate)
+            .field("client_key", &self.client_key)
+            .field("topics", &self.topics)
+            .finish()
+    }
+}
+
+/// The topic layout is `[<prefix>/]<device id>/<entity>[/<attribute>]` for the published state,
+/// and `[<prefix>/]<device id>/<commands>/<command>` for the received commands.
+///
+/// Empty segments fall back to their default names, and an empty device ID falls back
+/// to the MQTT client ID.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MqttTopics {
+    pub prefix: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+    pub device_id: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+    pub valve: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+    pub meter: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+    pub battery: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+    pub powered: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+    pub commands: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+    pub events: String<MQTT_TOPIC_SEGMENT_MAX_LEN>,
+}
+
+impl MqttTopics {
+    pub const fn new() -> Self {
+        Self {
+            prefix: String::new(),
+            device_id: String::new(),
+            valve: String::new(),
+            meter: String::new(),
+            battery: String::new(),
+            powered: String::new(),
+            commands: String::new(),
+            events: String::new(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        self.prefix.trim_matches('/')
+    }
+
+    pub fn valve(&self) -> &str {
+        Self::or_default(&self.valve, "valve")
+    }
+
+    pub fn meter(&self) -> &str {
+        Self::or_default(&self.meter, "meter")
+    }
+
+    pub fn battery(&self) -> &str {
+        Self::or_default(&self.battery, "battery")
+    }
+
+    pub fn powered(&self) -> &str {
+        Self::or_default(&self.powered, "powered")
+    }
+
+    pub fn commands(&self) -> &str {
+        Self::or_default(&self.commands, "commands")
+    }
+
+    pub fn events(&self) -> &str {
+        Self::or_default(&self.events, "events")
+    }
+
+    fn or_default<'a>(segment: &'a str, default: &'a str) -> &'a str {
+        let segment = segment.trim_matches('/');
+
+        if segment.is_empty() {
+            default
+        } else {
+            segment
+        }
+    }
+}
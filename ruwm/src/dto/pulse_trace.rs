@@ -0,0 +1,170 @@
+use core::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use heapless::Deque;
+
+pub const PULSE_TRACE_SIZE: usize = 256;
+
+/// The maximum length of an encoded trace of `PULSE_TRACE_SIZE` entries
+pub const PULSE_TRACE_ENCODED_MAX_LEN: usize = PULSE_TRACE_SIZE * PulseTraceEntry::ENCODED_LEN;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PulseTraceEvent {
+    /// Edges counted at once; never more than `PulseTraceEntry::EDGES_MAX`
+    Edges(u16),
+    Armed,
+    Disarmed,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PulseTraceEntry {
+    /// Milliseconds on the clock of the trace - since boot, unless restored after a deep sleep - wrapping every ~49 days;
+    /// only the differences between entries are meaningful
+    pub time_ms: u32,
+    pub event: PulseTraceEvent,
+}
+
+impl PulseTraceEntry {
+    /// The length of an encoded entry: the time as a little-endian `u32`, followed by the event as a little-endian `u16`
+    pub const ENCODED_LEN: usize = 6;
+
+    pub const EDGES_MAX: u16 = 0x7fff;
+
+    const ARMED: u16 = 0x8001;
+    const DISARMED: u16 = 0x8000;
+
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let event = match self.event {
+            PulseTraceEvent::Edges(edges) => edges.min(Self::EDGES_MAX),
+            PulseTraceEvent::Armed => Self::ARMED,
+            PulseTraceEvent::Disarmed => Self::DISARMED,
+        };
+
+        let mut buf = [0; Self::ENCODED_LEN];
+
+        buf[..4].copy_from_slice(&self.time_ms.to_le_bytes());
+        buf[4..].copy_from_slice(&event.to_le_bytes());
+
+        buf
+    }
+
+    pub fn decode(buf: &[u8; Self::ENCODED_LEN]) -> Option<Self> {
+        let time_ms = u32::from_le_bytes(buf[..4].try_into().unwrap());
+
+        let event = match u16::from_le_bytes(buf[4..].try_into().unwrap()) {
+            Self::ARMED => PulseTraceEvent::Armed,
+            Self::DISARMED => PulseTraceEvent::Disarmed,
+            edges if edges <= Self::EDGES_MAX => PulseTraceEvent::Edges(edges),
+            _ => return None,
+        };
+
+        Some(Self { time_ms, event })
+    }
+
+    /// Decodes a downloaded trace; `None` if it is truncated or malformed
+    pub fn decode_all(data: &[u8]) -> Option<impl Iterator<Item = Self> + '_> {
+        let chunks = data.chunks_exact(Self::ENCODED_LEN);
+
+        if !chunks.remainder().is_empty()
+            || chunks
+                .clone()
+                .any(|chunk| Self::decode(chunk.try_into().unwrap()).is_none())
+        {
+            return None;
+        }
+
+        Some(chunks.map(|chunk| Self::decode(chunk.try_into().unwrap()).unwrap()))
+    }
+}
+
+/// A ring buffer of the most recent pulse trace entries; the oldest entry is dropped when the trace is full
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PulseTrace<const N: usize> {
+    entries: Deque<PulseTraceEntry, N>,
+    dropped: u32,
+}
+
+impl<const N: usize> PartialEq for PulseTrace<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.dropped == other.dropped && self.iter().eq(other.iter())
+    }
+}
+
+impl<const N: usize> Eq for PulseTrace<N> {}
+
+impl<const N: usize> PulseTrace<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: Deque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Number of entries lost due to overflow since the trace was created or cleared
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Oldest entry first
+    pub fn iter(&self) -> impl Iterator<Item = &PulseTraceEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dropped = 0;
+    }
+
+    pub fn push(&mut self, time_ms: u32, event: PulseTraceEvent) {
+        if self.entries.is_full() {
+            self.entries.pop_front();
+            self.dropped = self.dropped.wrapping_add(1);
+        }
+
+        self.entries
+            .push_back(PulseTraceEntry { time_ms, event })
+            .unwrap();
+    }
+
+    /// Splits edges above `PulseTraceEntry::EDGES_MAX` over several entries
+    pub fn push_edges(&mut self, time_ms: u32, mut edges: u64) {
+        while edges > 0 {
+            let chunk = edges.min(PulseTraceEntry::EDGES_MAX as u64);
+
+            self.push(time_ms, PulseTraceEvent::Edges(chunk as u16));
+
+            edges -= chunk;
+        }
+    }
+
+    /// Encodes the entries, oldest first, into `buf`; returns the encoded length
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+
+        for (entry, chunk) in self
+            .entries
+            .iter()
+            .zip(buf.chunks_exact_mut(PulseTraceEntry::ENCODED_LEN))
+        {
+            chunk.copy_from_slice(&entry.encode());
+            len += PulseTraceEntry::ENCODED_LEN;
+        }
+
+        len
+    }
+}
+
+impl<const N: usize> Default for PulseTrace<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -2,9 +2,10 @@ use core::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
 
-const FLOW_STATS_INSTANCES: usize = 8;
+pub const FLOW_STATS_INSTANCES: usize = 8;
 
-const DURATIONS: [u64; FLOW_STATS_INSTANCES] = [
+/// The lengths (in seconds) of the periods flow is measured over, one per `WaterMeterStatsState::measurements` slot
+pub const DURATIONS: [u64; FLOW_STATS_INSTANCES] = [
     60 * 5,
     60 * 30,
     60 * 60,
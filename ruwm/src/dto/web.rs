@@ -1,66 +1,274 @@
-use core::fmt::Debug;
+use core::fmt::{self, Debug, Display};
+use core::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
 use heapless::String;
 
+use embedded_svc::wifi::{AuthMethod, Configuration};
+
 use edge_frame::dto::Role;
 
+use super::audit::{AuditLog, AUDIT_LOG_SIZE};
 use super::battery::BatteryState;
+use super::command::CommandSource;
+use super::emergency::LockoutState;
 use super::valve::{ValveCommand, ValveState};
 use super::water_meter::{WaterMeterCommand, WaterMeterState};
+use super::water_meter_stats::WaterMeterStatsState;
 
 pub const USERNAME_MAX_LEN: usize = 32;
 pub const PASSWORD_MAX_LEN: usize = 32;
 
-#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+/// The role of a user account, ordered by privilege.
+/// Viewers see the same state as users, but cannot issue commands which change the device.
+///
+/// Permissions are checked against an `Option<UserRole>`, where `None` is an anonymous client.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum UserRole {
+    Viewer,
+    User,
+    Admin,
+}
+
+impl UserRole {
+    /// The role the navigation of the UI is based on; it does not tell viewers apart from users
+    pub fn role(&self) -> Role {
+        match self {
+            Self::Viewer | Self::User => Role::User,
+            Self::Admin => Role::Admin,
+        }
+    }
+}
+
+/// The WiFi settings as shown in the UI; the password never leaves the device
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct WifiSettings {
+    /// The network the device connects to or, for an access point, the network it provides
+    pub ssid: String<32>,
+    pub access_point: bool,
+    pub password_set: bool,
+}
+
+impl WifiSettings {
+    pub fn new(configuration: &Configuration) -> Option<Self> {
+        match configuration {
+            Configuration::Client(conf) | Configuration::Mixed(conf, _) => Some(Self {
+                ssid: conf.ssid.clone(),
+                access_point: false,
+                password_set: conf.auth_method != AuthMethod::None && !conf.password.is_empty(),
+            }),
+            Configuration::AccessPoint(conf) => Some(Self {
+                ssid: conf.ssid.clone(),
+                access_point: true,
+                password_set: conf.auth_method != AuthMethod::None && !conf.password.is_empty(),
+            }),
+            Configuration::None => None,
+        }
+    }
+}
+
+/// Identifies a web session, so that it can be resumed from a new connection.
+/// Signed by the device; the session itself is tracked on the device and can be revoked there.
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionToken {
+    pub id: u32,
+    pub expires_secs: u64,
+    pub mac: [u8; 32],
+}
+
+impl Debug for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionToken")
+            .field("id", &self.id)
+            .field("expires_secs", &self.expires_secs)
+            .field("mac", &"***")
+            .finish()
+    }
+}
+
+/// The textual form of a token is `<id>:<expires_secs>:<hex mac>`, as used in HTTP `Authorization: Bearer` headers
+impl Display for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:", self.id, self.expires_secs)?;
+
+        for byte in self.mac {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for SessionToken {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(':');
+
+        let id = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let expires_secs = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let mac_hex = parts.next().ok_or(())?;
+
+        if parts.next().is_some() || mac_hex.len() != 64 {
+            return Err(());
+        }
+
+        let mut mac = [0; 32];
+
+        for (index, byte) in mac.iter_mut().enumerate() {
+            let hex = mac_hex.get(index * 2..index * 2 + 2).ok_or(())?;
+
+            *byte = u8::from_str_radix(hex, 16).map_err(|_| ())?;
+        }
+
+        Ok(Self {
+            id,
+            expires_secs,
+            mac,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum WebRequest {
     Authenticate(String<USERNAME_MAX_LEN>, String<PASSWORD_MAX_LEN>),
+    Resume(SessionToken),
     Logout,
 
+    /// Creates the first admin account; only honored while no accounts exist
+    SetupAdmin(String<USERNAME_MAX_LEN>, String<PASSWORD_MAX_LEN>),
+    /// Changes the password of the logged in user: old password, new password
+    ChangePassword(String<PASSWORD_MAX_LEN>, String<PASSWORD_MAX_LEN>),
+
+    AddUser(String<USERNAME_MAX_LEN>, String<PASSWORD_MAX_LEN>, UserRole),
+    RemoveUser(String<USERNAME_MAX_LEN>),
+
     ValveCommand(ValveCommand),
     WaterMeterCommand(WaterMeterCommand),
-    // TODO
-    //WifiSettingsUpdate(...),
+    /// Lifts an emergency lockout whose triggering condition has cleared
+    AcknowledgeLockout,
+
+    /// Requests the audit log, answered with `WebEvent::AuditLog`
+    GetEvents,
+
+    /// An empty password for the network the device is already configured with keeps the current password
+    WifiSettingsUpdate(Configuration),
+}
+
+/// Passwords are never logged
+impl Debug for WebRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Authenticate(username, _) => f
+                .debug_tuple("Authenticate")
+                .field(username)
+                .field(&"***")
+                .finish(),
+            Self::Resume(token) => f.debug_tuple("Resume").field(token).finish(),
+            Self::Logout => write!(f, "Logout"),
+            Self::SetupAdmin(username, _) => f
+                .debug_tuple("SetupAdmin")
+                .field(username)
+                .field(&"***")
+                .finish(),
+            Self::ChangePassword(_, _) => f
+                .debug_tuple("ChangePassword")
+                .field(&"***")
+                .field(&"***")
+                .finish(),
+            Self::AddUser(username, _, role) => f
+                .debug_tuple("AddUser")
+                .field(username)
+                .field(&"***")
+                .field(role)
+                .finish(),
+            Self::RemoveUser(username) => f.debug_tuple("RemoveUser").field(username).finish(),
+            Self::ValveCommand(command) => f.debug_tuple("ValveCommand").field(command).finish(),
+            Self::WaterMeterCommand(command) => {
+                f.debug_tuple("WaterMeterCommand").field(command).finish()
+            }
+            Self::AcknowledgeLockout => write!(f, "AcknowledgeLockout"),
+            Self::GetEvents => write!(f, "GetEvents"),
+            Self::WifiSettingsUpdate(configuration) => f
+                .debug_tuple("WifiSettingsUpdate")
+                .field(&WifiSettings::new(configuration))
+                .finish(),
+        }
+    }
 }
 
 impl WebRequest {
-    pub fn role(&self) -> Role {
+    /// The least privileged role which may issue the request; `None` if anonymous clients may issue it
+    pub fn role(&self) -> Option<UserRole> {
         match self {
-            Self::Authenticate(_, _) => Role::None,
-            Self::Logout => Role::None,
-            Self::ValveCommand(_) => Role::User,
-            Self::WaterMeterCommand(_) => Role::User,
+            Self::Authenticate(_, _) => None,
+            Self::Resume(_) => None,
+            Self::Logout => None,
+            Self::SetupAdmin(_, _) => None,
+            Self::ChangePassword(_, _) => Some(UserRole::Viewer),
+            Self::AddUser(_, _, _) => Some(UserRole::Admin),
+            Self::RemoveUser(_) => Some(UserRole::Admin),
+            Self::ValveCommand(_) => Some(UserRole::User),
+            Self::WaterMeterCommand(_) => Some(UserRole::User),
+            Self::AcknowledgeLockout => Some(UserRole::Admin),
+            Self::GetEvents => Some(UserRole::Viewer),
+            Self::WifiSettingsUpdate(_) => Some(UserRole::Admin),
         }
     }
+
+    /// Whether a user with the given role - or an anonymous one - may issue the request.
+    pub fn is_permitted(&self, user_role: Option<UserRole>) -> bool {
+        self.role() <= user_role
+    }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum WebEvent {
     NoPermissions,
 
     AuthenticationFailed,
+    SetupRequired,
+    RequestFailed,
 
-    RoleState(Role),
+    RoleState(Option<UserRole>, Option<SessionToken>),
     ValveState(Option<ValveState>),
+    /// The source of the last command which moved the valve
+    ValveSourceState(Option<CommandSource>),
+    LockoutState(Option<LockoutState>),
     WaterMeterState(WaterMeterState),
+    WaterMeterStatsState(WaterMeterStatsState),
     BatteryState(BatteryState),
-    //WifiState(Status),
-
+    /// Seconds until the device goes to sleep; `None` while it stays awake indefinitely
+    RemainingTimeState(Option<u32>),
+    MqttState(Option<bool>),
+    WifiState(Option<bool>),
+    WifiSettingsState(Option<WifiSettings>),
+    AuditLog(AuditLog<AUDIT_LOG_SIZE>),
     // MqttPublishNotification(MessageId),
     // MqttClientNotification(MqttClientNotification),
 }
 
 impl WebEvent {
-    pub fn role(&self) -> Role {
+    /// The least privileged role which may receive the event; `None` if anonymous clients may receive it
+    pub fn role(&self) -> Option<UserRole> {
         match self {
-            Self::NoPermissions => Role::None,
-            Self::AuthenticationFailed => Role::None,
-            Self::RoleState(_) => Role::None,
-            Self::ValveState(_) => Role::User,
-            Self::WaterMeterState(_) => Role::User,
-            Self::BatteryState(_) => Role::User,
-            //Self::WifiState(_) => Role::User,
+            Self::NoPermissions => None,
+            Self::AuthenticationFailed => None,
+            Self::SetupRequired => None,
+            Self::RequestFailed => None,
+            Self::RoleState(_, _) => None,
+            Self::ValveState(_) => Some(UserRole::Viewer),
+            Self::ValveSourceState(_) => Some(UserRole::Viewer),
+            Self::LockoutState(_) => Some(UserRole::Viewer),
+            Self::WaterMeterState(_) => Some(UserRole::Viewer),
+            Self::WaterMeterStatsState(_) => Some(UserRole::Viewer),
+            Self::BatteryState(_) => Some(UserRole::Viewer),
+            Self::RemainingTimeState(_) => Some(UserRole::Viewer),
+            Self::MqttState(_) => Some(UserRole::Viewer),
+            Self::WifiState(_) => Some(UserRole::Viewer),
+            Self::WifiSettingsState(_) => Some(UserRole::Admin),
+            Self::AuditLog(_) => Some(UserRole::Viewer),
         }
     }
 }
@@ -1,53 +1,163 @@
-use embassy_futures::select::{select3, Either3};
+use core::pin::pin;
+
+use log::{info, warn};
+
+use embassy_futures::select::{select4, Either4};
 
 use channel_bridge::notification::Notification;
 
+use crate::audit::{self, AuditEvent};
 use crate::battery::{self, BatteryState};
+use crate::command::CommandSource;
+use crate::state::{self, State, Subscriber};
 use crate::valve::{self, ValveCommand, ValveState};
-use crate::wm;
+use crate::wm::{self, WaterMeterState};
+
+pub use crate::dto::emergency::*;
+
+/// The current emergency lockout, if any.
+///
+/// A lockout starts with a leak, or a low battery without power, and outlives its triggering condition:
+/// while it is present, the valve refuses to open by commands of a priority lower than `CommandPriority::Emergency`.
+/// Once the condition has cleared, it has to be acknowledged by an admin, or by holding the third button.
+pub static STATE: State<Option<LockoutState>> =
+    State::new("LOCKOUT", None, &[&STATE_PERSIST_NOTIFY]);
+
+static STATE_PERSIST_NOTIFY: Notification = Notification::new();
 
-pub(crate) static VALVE_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WM_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static BATTERY_STATE_NOTIF: Notification = Notification::new();
+static VALVE_STATE: Subscriber<Option<ValveState>> = Subscriber::new();
+static WM_STATE: Subscriber<WaterMeterState> = Subscriber::new();
+static BATTERY_STATE: Subscriber<BatteryState> = Subscriber::new();
+
+pub(crate) static BUTTON_HELD_NOTIF: Notification = Notification::new();
+
+pub(crate) fn reset() {
+    valve::STATE.unsubscribe(&VALVE_STATE);
+    wm::STATE.unsubscribe(&WM_STATE);
+    battery::STATE.unsubscribe(&BATTERY_STATE);
+
+    state::clear(&[&STATE_PERSIST_NOTIFY, &BUTTON_HELD_NOTIF]);
+}
+
+pub fn is_lockout() -> bool {
+    STATE.get().is_some()
+}
+
+/// Lifts the lockout, provided that its triggering condition has cleared.
+/// Returns `false` if there is no lockout, or if it cannot be acknowledged yet.
+pub fn acknowledge(source: CommandSource) -> bool {
+    let mut acknowledged = false;
+
+    STATE.update_with(|state| match state {
+        Some(lockout) if lockout.can_acknowledge() => {
+            acknowledged = true;
+            None
+        }
+        state => state,
+    });
+
+    if acknowledged {
+        audit::record(AuditEvent::LockoutAcknowledged(source));
+    } else {
+        warn!(
+            "Lockout not acknowledged ({}): {:?}",
+            source.text(),
+            STATE.get()
+        );
+    }
+
+    acknowledged
+}
 
 pub async fn process() {
-    let mut valve_state = None;
+    let subscribed = valve::STATE.subscribe(&VALVE_STATE)
+        && wm::STATE.subscribe(&WM_STATE)
+        && battery::STATE.subscribe(&BATTERY_STATE);
+
+    if !subscribed {
+        panic!("Too many state subscribers");
+    }
+
+    let mut valve_state = valve::STATE.get();
+    let mut leaking = wm::STATE.get().leaking;
+    let mut battery_low = false;
 
     loop {
-        let emergency_close = match select3(
-            VALVE_STATE_NOTIF.wait(),
-            WM_STATE_NOTIF.wait(),
-            BATTERY_STATE_NOTIF.wait(),
-        )
-        .await
-        {
-            Either3::First(_) => {
-                valve_state = valve::STATE.get();
+        // Pinned in place, as the futures of the three subscribers are too large to be moved around
+        let changed = pin!(select4(
+            VALVE_STATE.next(),
+            WM_STATE.next(),
+            BATTERY_STATE.next(),
+            BUTTON_HELD_NOTIF.wait(),
+        ));
 
-                false
-            }
-            Either3::Second(_) => wm::STATE.get().leaking,
-            Either3::Third(_) => {
-                let battery = battery::STATE.get();
+        match changed.await {
+            Either4::First(update) => valve_state = update.value,
+            Either4::Second(update) => leaking = update.value.leaking,
+            Either4::Third(update) => {
+                let battery = update.value;
 
-                let battery_low = battery
+                let voltage_low = battery
                     .voltage
                     .map(|voltage| voltage <= BatteryState::LOW_VOLTAGE)
                     .unwrap_or(false);
 
                 let powered = battery.powered.unwrap_or(false);
 
-                battery_low && !powered
+                battery_low = voltage_low && !powered;
             }
+            Either4::Fourth(_) => {
+                if is_lockout() {
+                    acknowledge(CommandSource::Button);
+                }
+
+                continue;
+            }
+        }
+
+        let reason = if leaking {
+            Some(LockoutReason::Leak)
+        } else if battery_low {
+            Some(LockoutReason::BatteryLow)
+        } else {
+            None
         };
 
-        if emergency_close
+        let new_lockout = reason.filter(|_| !is_lockout());
+
+        STATE.update_with(|state| match (state, reason) {
+            (state, Some(reason)) => Some(LockoutState {
+                reason: state.map(|lockout| lockout.reason).unwrap_or(reason),
+                condition_present: true,
+            }),
+            (Some(lockout), None) => Some(LockoutState {
+                condition_present: false,
+                ..lockout
+            }),
+            (None, None) => None,
+        });
+
+        if let Some(reason) = new_lockout {
+            info!("Emergency lockout: {}", reason.text());
+
+            audit::record(AuditEvent::Lockout(reason));
+        }
+
+        if reason.is_some()
             && !matches!(
                 valve_state,
                 Some(ValveState::Closing(_)) | Some(ValveState::Closed)
             )
         {
-            valve::COMMAND.signal(ValveCommand::Close);
+            valve::command(ValveCommand::Close, CommandSource::Emergency);
         }
     }
 }
+
+pub async fn persist(mut persister: impl FnMut(Option<LockoutState>)) {
+    loop {
+        STATE_PERSIST_NOTIFY.wait().await;
+
+        persister(STATE.get());
+    }
+}
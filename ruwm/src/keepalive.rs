@@ -1,26 +1,46 @@
 use core::fmt::Debug;
 
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select, select_slice, Either};
 use embassy_time::{Duration, Instant, Timer};
 
 use channel_bridge::notification::Notification;
 
-use crate::state::State;
-use crate::{battery, quit};
+use crate::battery::{self, BatteryState};
+use crate::emergency::{self, LockoutState};
+use crate::state::{self, State, Subscriber};
+use crate::valve::{self, ValveState};
+use crate::wm::{self, WaterMeterState};
+use crate::wm_stats::{self, WaterMeterStatsState};
+use crate::{mqtt, quit, wifi};
 
 const TIMEOUT: Duration = Duration::from_secs(20);
 
-pub static STATE: State<RemainingTime> = State::new(
-    "REMAINING TIME",
-    RemainingTime::Indefinite,
-    &[
-        &crate::screen::REMAINING_TIME_NOTIF,
-        &crate::web::REMAINING_TIME_STATE_NOTIF,
-    ],
-);
+pub static STATE: State<RemainingTime> =
+    State::new("REMAINING TIME", RemainingTime::Indefinite, &[]);
 
+/// Notified on activity other than a state change, e.g. MQTT traffic
 pub(crate) static NOTIF: Notification = Notification::new();
 
+static VALVE_STATE: Subscriber<Option<ValveState>> = Subscriber::new();
+static WM_STATE: Subscriber<WaterMeterState> = Subscriber::new();
+static WM_STATS_STATE: Subscriber<WaterMeterStatsState> = Subscriber::new();
+static BATTERY_STATE: Subscriber<BatteryState> = Subscriber::new();
+static LOCKOUT_STATE: Subscriber<Option<LockoutState>> = Subscriber::new();
+static WIFI_STATE: Subscriber<Option<bool>> = Subscriber::new();
+static MQTT_STATE: Subscriber<Option<bool>> = Subscriber::new();
+
+pub(crate) fn reset() {
+    valve::STATE.unsubscribe(&VALVE_STATE);
+    wm::STATE.unsubscribe(&WM_STATE);
+    wm_stats::STATE.unsubscribe(&WM_STATS_STATE);
+    battery::STATE.unsubscribe(&BATTERY_STATE);
+    emergency::STATE.unsubscribe(&LOCKOUT_STATE);
+    wifi::STATE.unsubscribe(&WIFI_STATE);
+    mqtt::STATE.unsubscribe(&MQTT_STATE);
+
+    state::clear(&[&NOTIF]);
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum RemainingTime {
     Indefinite,
@@ -28,12 +48,36 @@ pub enum RemainingTime {
 }
 
 pub async fn process() {
+    let subscribed = valve::STATE.subscribe(&VALVE_STATE)
+        && wm::STATE.subscribe(&WM_STATE)
+        && wm_stats::STATE.subscribe(&WM_STATS_STATE)
+        && battery::STATE.subscribe(&BATTERY_STATE)
+        && emergency::STATE.subscribe(&LOCKOUT_STATE)
+        && wifi::STATE.subscribe(&WIFI_STATE)
+        && mqtt::STATE.subscribe(&MQTT_STATE);
+
+    if !subscribed {
+        panic!("Too many state subscribers");
+    }
+
+    // Only the activity matters, not the changed values, which are left pending in the subscribers
+    let mut notifs = [
+        NOTIF.wait(),
+        VALVE_STATE.notification().wait(),
+        WM_STATE.notification().wait(),
+        WM_STATS_STATE.notification().wait(),
+        BATTERY_STATE.notification().wait(),
+        LOCKOUT_STATE.notification().wait(),
+        WIFI_STATE.notification().wait(),
+        MQTT_STATE.notification().wait(),
+    ];
+
     let mut quit_time = None;
     let mut remaining_time_sent = None;
 
     loop {
         let result = select(
-            NOTIF.wait(),
+            select_slice(&mut notifs),
             Timer::after(Duration::from_secs(2) /*Duration::from_millis(500)*/),
         )
         .await;
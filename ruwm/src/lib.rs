@@ -2,10 +2,16 @@
 #![allow(async_fn_in_trait)]
 #![warn(clippy::large_futures)]
 
+#[cfg(feature = "system")]
+pub mod api;
+#[cfg(feature = "system")]
+pub mod audit;
 #[cfg(feature = "system")]
 pub mod battery;
 #[cfg(feature = "system")]
 pub mod button;
+#[cfg(feature = "system")]
+pub mod command;
 pub mod dto;
 #[cfg(feature = "system")]
 pub mod emergency;
@@ -14,6 +20,8 @@ pub mod error;
 #[cfg(feature = "system")]
 pub mod keepalive;
 #[cfg(feature = "system")]
+pub mod metrics;
+#[cfg(feature = "system")]
 pub mod mqtt;
 #[cfg(feature = "system")]
 pub mod pulse_counter;
@@ -24,8 +32,12 @@ pub mod screen;
 #[cfg(all(feature = "system", feature = "edge-executor"))]
 pub mod spawn;
 #[cfg(feature = "system")]
+pub mod sse;
+#[cfg(feature = "system")]
 pub mod state;
 #[cfg(feature = "system")]
+pub mod users;
+#[cfg(feature = "system")]
 pub mod utils;
 #[cfg(feature = "system")]
 pub mod valve;
@@ -39,3 +51,22 @@ pub mod wm;
 pub mod wm_stats;
 #[cfg(feature = "system")]
 pub mod ws;
+
+/// Resets what the tasks share besides the public `STATE`s of the subsystems - pending commands, signals
+/// and notifications, sessions and lockouts, the MQTT outbox and the audit log - so that the tasks can be
+/// spawned afresh, e.g. by the next test.
+///
+/// Must not be called while the tasks are running.
+#[cfg(feature = "system")]
+pub fn reset() {
+    audit::reset();
+    emergency::reset();
+    keepalive::reset();
+    mqtt::reset();
+    screen::reset();
+    users::reset();
+    valve::reset();
+    wifi::reset();
+    wm::reset();
+    wm_stats::reset();
+}
@@ -0,0 +1,187 @@
+use core::cell::Cell;
+use core::fmt::{self, Write as _};
+
+use heapless::String;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use embedded_io_async::Write;
+
+use embedded_svc::http::server::asynch::{Connection, Request};
+
+use crate::api;
+use crate::battery;
+use crate::emergency;
+use crate::mqtt;
+use crate::valve;
+use crate::web::UserRole;
+use crate::wifi;
+use crate::wm;
+use crate::wm_stats::{self, DURATIONS};
+
+pub const METRICS_PATH: &str = "/metrics";
+
+const METRICS_MAX_LEN: usize = 3072;
+
+type FreeHeapHook = Mutex<CriticalSectionRawMutex, Cell<Option<fn() -> usize>>>;
+
+static FREE_HEAP_HOOK: FreeHeapHook = Mutex::new(Cell::new(None));
+
+/// Sets the function reporting the free heap of the platform; without it, the metric is not rendered.
+pub fn set_free_heap_hook(hook: fn() -> usize) {
+    FREE_HEAP_HOOK.lock(|free_heap_hook| free_heap_hook.set(Some(hook)));
+}
+
+/// Renders the current values of all states in the Prometheus text exposition format.
+/// States which are not known yet (e.g. the valve position before the first reading) are skipped.
+pub fn render(out: &mut impl fmt::Write) -> fmt::Result {
+    let wm = wm::STATE.get();
+    let wm_stats = wm_stats::STATE.get();
+    let battery = battery::STATE.get();
+
+    metric(
+        out,
+        "ruwm_water_meter_edges_total",
+        "counter",
+        "Edges counted by the water meter since installation",
+    )?;
+    writeln!(out, "ruwm_water_meter_edges_total {}", wm.edges_count)?;
+
+    metric(
+        out,
+        "ruwm_water_flow_edges",
+        "gauge",
+        "Edges counted during the last completed period",
+    )?;
+    for (duration, measurement) in DURATIONS.iter().zip(wm_stats.measurements.iter()) {
+        if let Some(measurement) = measurement {
+            writeln!(
+                out,
+                "ruwm_water_flow_edges{{period_seconds=\"{}\"}} {}",
+                duration,
+                measurement
+                    .start()
+                    .statistics(measurement.end().edges_count())
+            )?;
+        }
+    }
+
+    gauge(
+        out,
+        "ruwm_water_meter_armed",
+        "Whether the flow watch is armed",
+        Some(wm.armed as u64),
+    )?;
+    gauge(
+        out,
+        "ruwm_water_meter_leaking",
+        "Whether a leak is detected",
+        Some(wm.leaking as u64),
+    )?;
+    gauge(
+        out,
+        "ruwm_valve_open_percent",
+        "How far the valve is open",
+        valve::STATE
+            .get()
+            .map(|valve| valve.open_percentage() as u64),
+    )?;
+    gauge(
+        out,
+        "ruwm_emergency_lockout",
+        "Whether an emergency lockout keeps the valve closed",
+        Some(emergency::is_lockout() as u64),
+    )?;
+    gauge(
+        out,
+        "ruwm_battery_voltage_millivolts",
+        "Battery voltage",
+        battery.voltage.map(|voltage| voltage as u64),
+    )?;
+    gauge(
+        out,
+        "ruwm_powered",
+        "Whether the device runs on external power",
+        battery.powered.map(|powered| powered as u64),
+    )?;
+    gauge(
+        out,
+        "ruwm_wifi_connected",
+        "Whether WiFi is connected",
+        wifi::STATE.get().map(|connected| connected as u64),
+    )?;
+    gauge(
+        out,
+        "ruwm_mqtt_connected",
+        "Whether the MQTT client is connected",
+        mqtt::STATE.get().map(|connected| connected as u64),
+    )?;
+
+    metric(
+        out,
+        "ruwm_uptime_seconds",
+        "counter",
+        "Seconds since the device started",
+    )?;
+    writeln!(out, "ruwm_uptime_seconds {}", Instant::now().as_secs())?;
+
+    gauge(
+        out,
+        "ruwm_free_heap_bytes",
+        "Free heap memory",
+        FREE_HEAP_HOOK
+            .lock(Cell::get)
+            .map(|free_heap| free_heap() as u64),
+    )
+}
+
+/// Serves `render` at `METRICS_PATH`. Scrapers authenticate the same way as API clients.
+pub async fn handle<C>(request: Request<C>) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    let user_role = request.header("Authorization").and_then(api::authorize);
+
+    if !api::is_allowed(Some(UserRole::Viewer), user_role) {
+        return api::respond_denied(request, user_role).await;
+    }
+
+    let mut metrics = String::<METRICS_MAX_LEN>::new();
+
+    if render(&mut metrics).is_err() {
+        return api::respond(request, 500).await;
+    }
+
+    let mut content_len = String::<10>::new();
+    write!(&mut content_len, "{}", metrics.len()).unwrap();
+
+    let mut response = request
+        .into_response(
+            200,
+            None,
+            &[
+                ("Content-Type", "text/plain; version=0.0.4"),
+                ("Content-Length", &content_len),
+                ("Cache-Control", "no-store"),
+            ],
+        )
+        .await?;
+
+    response.write_all(metrics.as_bytes()).await
+}
+
+fn gauge(out: &mut impl fmt::Write, name: &str, help: &str, value: Option<u64>) -> fmt::Result {
+    if let Some(value) = value {
+        metric(out, name, "gauge", help)?;
+        writeln!(out, "{} {}", name, value)?;
+    }
+
+    Ok(())
+}
+
+fn metric(out: &mut impl fmt::Write, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
@@ -1,15 +1,18 @@
-use core::str::{self, FromStr};
+use core::cell::{Cell, RefCell};
+use core::fmt::Debug;
+use core::pin::pin;
+use core::str;
 use core::time::Duration;
 
-use log::{error, info};
+use log::{error, info, warn};
 
 use serde::{Deserialize, Serialize};
 
-use heapless::String;
-
-use embassy_futures::select::{select4, Either4};
+use embassy_futures::select::{select3, select4, Either3, Either4};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::signal::Signal;
+use embassy_time::{Instant, Timer};
 
 use embedded_svc::mqtt::client::asynch::{Client, Connection, Event, EventPayload, Publish, QoS};
 use embedded_svc::mqtt::client::Details;
@@ -18,18 +21,25 @@ use channel_bridge::notification::Notification;
 use wm::WaterMeterState;
 
 use crate::battery::{self, BatteryState};
-use crate::valve::{ValveCommand, ValveState};
-use crate::wm::WaterMeterCommand;
-use crate::{error, valve, wm};
-
-#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
-pub struct MqttConfiguration {
-    protocol_311: bool,
-    url: heapless::String<128>,
-    client_id: heapless::String<64>,
-    username: heapless::String<64>,
-    password: heapless::String<64>,
-}
+use crate::command::CommandSource;
+use crate::state::{self, State};
+use crate::valve::ValveCommand;
+use crate::wm::{WaterMeterCommand, PULSE_TRACE_ENCODED_MAX_LEN};
+use crate::{emergency, error, valve, wm};
+
+pub use crate::dto::mqtt::*;
+
+pub use outbox::*;
+pub use reassembly::*;
+pub use tls::*;
+pub use topics::*;
+
+mod outbox;
+mod reassembly;
+mod tls;
+mod topics;
+
+pub type MqttOutbox = Outbox<OUTBOX_SIZE>;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum MqttCommand {
@@ -37,6 +47,10 @@ pub enum MqttCommand {
     Valve(bool),
     FlowWatch(bool),
     SystemUpdate,
+    /// Enables or disables the recording of the pulse trace
+    PulseTrace(bool),
+    /// Publishes the recorded pulse trace, encoded, on the `trace` attribute of the meter
+    PulseTraceDump,
 }
 
 // TODO: Web: connected info at least
@@ -45,75 +59,222 @@ static PUBLISH_NOTIFY: &[&Notification] =
 static RECEIVE_NOTIFY: &[&Notification] =
     &[&crate::keepalive::NOTIF, &crate::screen::MQTT_STATE_NOTIF];
 
-pub(crate) static VALVE_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WM_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static BATTERY_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WIFI_STATE_NOTIF: Notification = Notification::new();
+pub(crate) static AUDIT_LOG_NOTIF: Notification = Notification::new();
+
+static PULSE_TRACE_DUMP_NOTIFY: Notification = Notification::new();
 
 static CONN_SIGNAL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
 
-pub async fn send<const L: usize>(topic_prefix: &str, mut mqtt: impl Client + Publish) {
-    let mut connected = false;
+pub static CONFIGURATION: State<Option<MqttConfiguration>> = State::new(
+    "MQTT CONFIGURATION",
+    None,
+    &[&CONFIGURATION_NOTIF, &CONFIGURATION_PERSIST_NOTIFY],
+);
 
-    let topic = |topic_suffix| {
-        String::<L>::from_str(topic_prefix)
-            .and_then(|mut s| s.push_str(topic_suffix).map(|_| s))
-            .unwrap_or_else(|_| panic!(""))
-    };
+static CONFIGURATION_NOTIF: Notification = Notification::new();
+static CONFIGURATION_PERSIST_NOTIFY: Notification = Notification::new();
+
+pub static STATE: State<Option<bool>> = State::new("MQTT", None, &[]);
+
+pub static OUTBOX: Mutex<CriticalSectionRawMutex, RefCell<MqttOutbox>> =
+    Mutex::new(RefCell::new(Outbox::new(OverflowPolicy::DropOldest)));
+
+static OUTBOX_PERSIST_NOTIFY: Notification = Notification::new();
+
+type ClockHook = Mutex<CriticalSectionRawMutex, Cell<Option<fn() -> u64>>>;
+
+static CLOCK_HOOK: ClockHook = Mutex::new(Cell::new(None));
+
+/// Sets the function reporting the seconds on a clock of the platform which keeps running during deep sleep
+/// (e.g. the RTC), so that the entries persisted in the outbox keep a meaningful time across wakeups.
+/// Without it, the entries are timed with `Instant`, which restarts from zero on every boot.
+pub fn set_clock_hook(hook: fn() -> u64) {
+    CLOCK_HOOK.lock(|clock_hook| clock_hook.set(Some(hook)));
+}
+
+fn now_secs() -> u64 {
+    CLOCK_HOOK
+        .lock(Cell::get)
+        .map(|clock| clock())
+        .unwrap_or_else(|| Instant::now().as_secs())
+}
+
+pub(crate) fn reset() {
+    OUTBOX.lock(|outbox| *outbox.borrow_mut() = Outbox::new(OverflowPolicy::DropOldest));
+    CONN_SIGNAL.reset();
+
+    state::clear(&[
+        &AUDIT_LOG_NOTIF,
+        &PULSE_TRACE_DUMP_NOTIFY,
+        &CONFIGURATION_NOTIF,
+        &CONFIGURATION_PERSIST_NOTIFY,
+        &OUTBOX_PERSIST_NOTIFY,
+    ]);
+}
+
+pub async fn process<const L: usize, const P: usize, F, C, N, E>(mut factory: F)
+where
+    F: FnMut(&MqttConfiguration) -> Result<(C, N), E>,
+    C: Client + Publish,
+    N: Connection,
+    E: Debug,
+{
+    loop {
+        let configuration = CONFIGURATION.get();
+
+        if let Some(configuration) = configuration {
+            match factory(&configuration) {
+                Ok((client, connection)) => {
+                    info!("MQTT client created for {}", configuration.url);
 
-    let topic_commands = topic("/commands/#");
+                    CONN_SIGNAL.reset();
+                    STATE.update(Some(false));
 
-    let topic_valve = topic("/valve");
+                    let layout = TopicLayout::new(&configuration);
 
-    let topic_meter_edges = topic("/meter/edges");
-    let topic_meter_armed = topic("/meter/armed");
-    let topic_meter_leak = topic("/meter/leak");
+                    let result = select3(
+                        send::<L>(&layout, client),
+                        receive::<L, P>(&layout, connection),
+                        CONFIGURATION_NOTIF.wait(),
+                    )
+                    .await;
+
+                    info!("MQTT client torn down");
+
+                    STATE.update(None);
+
+                    if !matches!(result, Either3::Third(_)) {
+                        // Connection closed by the client itself; back off a bit before recreating it
+                        Timer::after(embassy_time::Duration::from_secs(1)).await;
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to create MQTT client: {:?}", err);
 
-    let topic_battery_voltage = topic("/battery/voltage");
-    let topic_battery_low = topic("/battery/low");
-    let topic_battery_charged = topic("/battery/charged");
+                    STATE.update(None);
 
-    let topic_powered = topic("/powered");
+                    CONFIGURATION_NOTIF.wait().await;
+                }
+            }
+        } else {
+            STATE.update(None);
 
+            CONFIGURATION_NOTIF.wait().await;
+        }
+    }
+}
+
+pub async fn persist_configuration(mut persister: impl FnMut(Option<MqttConfiguration>)) {
+    loop {
+        CONFIGURATION_PERSIST_NOTIFY.wait().await;
+
+        persister(CONFIGURATION.get());
+    }
+}
+
+/// Enqueues the changes of the published states, and flushes the outbox while connected.
+/// Each value is preceded by the time it was queued at, on the `time` attribute of its topic.
+///
+/// The revision of each state as last enqueued is kept, so a change is never missed nor enqueued twice -
+/// even if it happened while the broker was unreachable.
+pub async fn send<const L: usize>(layout: &TopicLayout<'_>, mut mqtt: impl Client + Publish) {
+    let mut connected = false;
+
+    let mut valve_revision = 0;
+    let mut valve_source_revision = 0;
+    let mut lockout_revision = 0;
+    let mut wm_revision = 0;
+    let mut battery_revision = 0;
+
+    // A revision only tells that a state has changed, not which of its attributes did, whereas each attribute
+    // is published on its own topic: the meter edges, armed and leak flags, the battery voltage and power,
+    // and the `Low` / `Charged` thresholds, which are derived from the previously published voltage.
+    // The valve is compared once simplified, so the progress of `Opening` / `Closing` is not republished.
     let mut published_valve_state = None;
     let mut published_wm_state: Option<WaterMeterState> = None;
     let mut published_battery_state: Option<BatteryState> = None;
 
     loop {
-        let (conn_state, valve_state, wm_state, battery_state) = if connected {
-            match select4(
-                CONN_SIGNAL.wait(),
-                VALVE_STATE_NOTIF.wait(),
-                WM_STATE_NOTIF.wait(),
-                BATTERY_STATE_NOTIF.wait(),
-            )
-            .await
-            {
-                Either4::First(conn_state) => (Some(conn_state), None, None, None),
-                Either4::Second(_) => (
+        // Pinned in place, as the futures of eight sources are too large to be moved around
+        let changed = pin!(select4(
+            CONN_SIGNAL.wait(),
+            select3(
+                valve::STATE.wait_changed(valve_revision),
+                valve::SOURCE_STATE.wait_changed(valve_source_revision),
+                emergency::STATE.wait_changed(lockout_revision),
+            ),
+            wm::STATE.wait_changed(wm_revision),
+            select3(
+                battery::STATE.wait_changed(battery_revision),
+                AUDIT_LOG_NOTIF.wait(),
+                PULSE_TRACE_DUMP_NOTIFY.wait(),
+            ),
+        ));
+
+        let (conn_state, valve_state, wm_state, battery_state) = match changed.await {
+            Either4::First(conn_state) => (Some(conn_state), None, None, None),
+            Either4::Second(Either3::First(update)) => {
+                valve_revision = update.revision;
+
+                (
                     None,
-                    Some(valve::STATE.get().map(|state| state.simplify())),
+                    Some(update.value.map(|state| state.simplify())),
                     None,
                     None,
-                ),
-                Either4::Third(_) => (None, None, Some(wm::STATE.get()), None),
-                Either4::Fourth(_) => (None, None, None, Some(battery::STATE.get())),
+                )
             }
-        } else {
-            let conn_state = CONN_SIGNAL.wait().await;
+            Either4::Second(Either3::Second(update)) => {
+                valve_source_revision = update.revision;
+
+                if let Some(source) = update.value {
+                    enqueue(OutboxPayload::ValveSource(source));
+                }
 
-            (Some(conn_state), None, None, None)
+                (None, None, None, None)
+            }
+            Either4::Second(Either3::Third(update)) => {
+                lockout_revision = update.revision;
+
+                enqueue(OutboxPayload::Lockout(update.value));
+
+                (None, None, None, None)
+            }
+            Either4::Third(update) => {
+                wm_revision = update.revision;
+
+                (None, None, Some(update.value), None)
+            }
+            Either4::Fourth(Either3::First(update)) => {
+                battery_revision = update.revision;
+
+                (None, None, None, Some(update.value))
+            }
+            // Audit events are already in the outbox, only flushing is due
+            Either4::Fourth(Either3::Second(_)) => (None, None, None, None),
+            Either4::Fourth(Either3::Third(_)) => {
+                if connected {
+                    publish_pulse_trace::<L>(&mut mqtt, layout).await;
+                } else {
+                    warn!("Client not connected, pulse trace not published");
+                }
+
+                (None, None, None, None)
+            }
         };
 
         if let Some(conn_state) = conn_state {
             if conn_state {
                 info!("MQTT is now connected, subscribing");
 
-                error::check!(
-                    mqtt.subscribe(topic_commands.as_str(), QoS::AtLeastOnce)
-                        .await
-                )
-                .unwrap();
+                if let Some(topic_commands) = layout.commands_filter::<L>() {
+                    error::check!(
+                        mqtt.subscribe(topic_commands.as_str(), QoS::AtLeastOnce)
+                            .await
+                    )
+                    .unwrap();
+                } else {
+                    error!("Commands topic too long, not subscribing");
+                }
 
                 connected = true;
             } else {
@@ -127,22 +288,7 @@ pub async fn send<const L: usize>(topic_prefix: &str, mut mqtt: impl Client + Pu
             if published_valve_state != valve_state {
                 published_valve_state = valve_state;
 
-                let status = match valve_state {
-                    Some(ValveState::Open) => "open",
-                    Some(ValveState::Opening(_)) => "opening",
-                    Some(ValveState::Closed) => "closed",
-                    Some(ValveState::Closing(_)) => "closing",
-                    None => "unknown",
-                };
-
-                publish(
-                    connected,
-                    &mut mqtt,
-                    &topic_valve,
-                    QoS::AtLeastOnce,
-                    status.as_bytes(),
-                )
-                .await;
+                enqueue(OutboxPayload::Valve(valve_state));
             }
         }
 
@@ -151,45 +297,21 @@ pub async fn send<const L: usize>(topic_prefix: &str, mut mqtt: impl Client + Pu
                 .map(|p| p.edges_count != wm_state.edges_count)
                 .unwrap_or(true)
             {
-                let num = wm_state.edges_count.to_le_bytes();
-                let num_slice: &[u8] = &num;
-
-                publish(
-                    connected,
-                    &mut mqtt,
-                    &topic_meter_edges,
-                    QoS::AtLeastOnce,
-                    num_slice,
-                )
-                .await;
+                enqueue(OutboxPayload::MeterEdges(wm_state.edges_count));
             }
 
             if published_wm_state
                 .map(|p| p.armed != wm_state.armed)
                 .unwrap_or(true)
             {
-                publish(
-                    connected,
-                    &mut mqtt,
-                    &topic_meter_armed,
-                    QoS::AtLeastOnce,
-                    (if wm_state.armed { "true" } else { "false" }).as_bytes(),
-                )
-                .await;
+                enqueue(OutboxPayload::MeterArmed(wm_state.armed));
             }
 
             if published_wm_state
                 .map(|p| p.leaking != wm_state.leaking)
                 .unwrap_or(true)
             {
-                publish(
-                    connected,
-                    &mut mqtt,
-                    &topic_meter_leak,
-                    QoS::AtLeastOnce,
-                    (if wm_state.armed { "true" } else { "false" }).as_bytes(),
-                )
-                .await;
+                enqueue(OutboxPayload::MeterLeak(wm_state.leaking));
             }
 
             published_wm_state = Some(wm_state);
@@ -201,55 +323,23 @@ pub async fn send<const L: usize>(topic_prefix: &str, mut mqtt: impl Client + Pu
                 .unwrap_or(true)
             {
                 if let Some(voltage) = battery_state.voltage {
-                    let num = voltage.to_le_bytes();
-                    let num_slice: &[u8] = &num;
-
-                    publish(
-                        connected,
-                        &mut mqtt,
-                        &topic_battery_voltage,
-                        QoS::AtMostOnce,
-                        num_slice,
-                    )
-                    .await;
+                    enqueue(OutboxPayload::BatteryVoltage(voltage));
 
                     if let Some(prev_voltage) = published_battery_state.and_then(|p| p.voltage) {
                         if (prev_voltage > BatteryState::LOW_VOLTAGE)
                             != (voltage > BatteryState::LOW_VOLTAGE)
                         {
-                            let status = if voltage > BatteryState::LOW_VOLTAGE {
-                                "false"
-                            } else {
-                                "true"
-                            };
-
-                            publish(
-                                connected,
-                                &mut mqtt,
-                                &topic_battery_low,
-                                QoS::AtLeastOnce,
-                                status.as_bytes(),
-                            )
-                            .await;
+                            enqueue(OutboxPayload::BatteryLow(
+                                voltage <= BatteryState::LOW_VOLTAGE,
+                            ));
                         }
 
                         if (prev_voltage >= BatteryState::MAX_VOLTAGE)
                             != (voltage >= BatteryState::MAX_VOLTAGE)
                         {
-                            let status = if voltage >= BatteryState::MAX_VOLTAGE {
-                                "true"
-                            } else {
-                                "false"
-                            };
-
-                            publish(
-                                connected,
-                                &mut mqtt,
-                                &topic_battery_charged,
-                                QoS::AtMostOnce,
-                                status.as_bytes(),
-                            )
-                            .await;
+                            enqueue(OutboxPayload::BatteryCharged(
+                                voltage >= BatteryState::MAX_VOLTAGE,
+                            ));
                         }
                     }
                 }
@@ -260,41 +350,138 @@ pub async fn send<const L: usize>(topic_prefix: &str, mut mqtt: impl Client + Pu
                 .unwrap_or(true)
             {
                 if let Some(powered) = battery_state.powered {
-                    publish(
-                        connected,
-                        &mut mqtt,
-                        &topic_powered,
-                        QoS::AtMostOnce,
-                        (if powered { "true" } else { "false" }).as_bytes(),
-                    )
-                    .await;
+                    enqueue(OutboxPayload::Powered(powered));
                 }
             }
 
             published_battery_state = Some(battery_state);
         };
+
+        if connected {
+            flush::<L>(&mut mqtt, layout).await;
+        } else {
+            let queued = OUTBOX.lock(|outbox| outbox.borrow().len());
+
+            if queued > 0 {
+                info!("Client not connected, {} message(s) queued", queued);
+            }
+        }
     }
 }
 
-async fn publish(connected: bool, mqtt: &mut impl Publish, topic: &str, qos: QoS, payload: &[u8]) {
-    if connected {
-        if let Ok(_msg_id) = error::check!(mqtt.publish(topic, qos, false, payload).await) {
-            // TODO
-            info!("Published to {}", topic);
+pub async fn persist(mut persister: impl FnMut(MqttOutbox)) {
+    loop {
+        OUTBOX_PERSIST_NOTIFY.wait().await;
 
-            if qos >= QoS::AtLeastOnce {
-                for notification in PUBLISH_NOTIFY {
-                    notification.notify();
-                }
+        persister(OUTBOX.lock(|outbox| outbox.borrow().clone()));
+    }
+}
+
+pub(crate) fn enqueue(payload: OutboxPayload) {
+    let overflowed = OUTBOX.lock(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+
+        let dropped = outbox.dropped();
+
+        outbox.push(OutboxEntry {
+            time_secs: now_secs(),
+            payload,
+        });
+
+        outbox.dropped() != dropped
+    });
+
+    if overflowed {
+        error!("MQTT outbox full, dropped a message ({:?})", payload);
+    }
+
+    OUTBOX_PERSIST_NOTIFY.notify();
+}
+
+async fn flush<const L: usize>(mqtt: &mut impl Publish, layout: &TopicLayout<'_>) {
+    while let Some(entry) = OUTBOX.lock(|outbox| outbox.borrow().front().copied()) {
+        let (entity, attribute) = entry.payload.topic_segments(layout.topics());
+
+        if let Some(topic) = layout.topic::<L>(entity, attribute) {
+            let mut time_topic = topic.clone();
+
+            if time_topic.push('/').is_err()
+                || time_topic.push_str(OutboxEntry::TIME_ATTRIBUTE).is_err()
+            {
+                error!(
+                    "Topic too long, not publishing the time of {:?}",
+                    entry.payload
+                );
+            } else if !publish(mqtt, &time_topic, entry.payload.qos(), &entry.encode_time()).await {
+                break;
+            }
+
+            let mut buf = [0; OUTBOX_PAYLOAD_MAX_LEN];
+
+            if !publish(
+                mqtt,
+                &topic,
+                entry.payload.qos(),
+                entry.payload.encode(&mut buf),
+            )
+            .await
+            {
+                break;
             }
+        } else {
+            error!("Topic too long, dropping {:?}", entry.payload);
         }
+
+        OUTBOX.lock(|outbox| {
+            let mut outbox = outbox.borrow_mut();
+
+            if outbox.front() == Some(&entry) {
+                outbox.pop_front();
+            }
+        });
+
+        OUTBOX_PERSIST_NOTIFY.notify();
+    }
+}
+
+/// Not queued in the outbox: the trace is too large for it, and only of interest to whoever asked for it just now
+async fn publish_pulse_trace<const L: usize>(mqtt: &mut impl Publish, layout: &TopicLayout<'_>) {
+    let Some(topic) = layout.topic::<L>(layout.topics().meter(), Some("trace")) else {
+        error!("Topic too long, not publishing the pulse trace");
+        return;
+    };
+
+    let mut buf = [0; PULSE_TRACE_ENCODED_MAX_LEN];
+
+    let len = wm::TRACE.lock(|trace| trace.borrow().encode(&mut buf));
+
+    publish(mqtt, &topic, QoS::AtMostOnce, &buf[..len]).await;
+}
+
+async fn publish(mqtt: &mut impl Publish, topic: &str, qos: QoS, payload: &[u8]) -> bool {
+    if let Ok(_msg_id) = error::check!(mqtt.publish(topic, qos, false, payload).await) {
+        // TODO
+        info!("Published to {}", topic);
+
+        if qos >= QoS::AtLeastOnce {
+            for notification in PUBLISH_NOTIFY {
+                notification.notify();
+            }
+        }
+
+        true
     } else {
-        error!("Client not connected, skipping publishment to {}", topic);
+        false
     }
 }
 
-pub async fn receive(mut connection: impl Connection) {
-    let mut parser = MessageParser::new();
+/// Receives and dispatches commands.
+/// Command payloads of up to `P` bytes (on topics of up to `L` bytes) are reassembled when chunked.
+pub async fn receive<const L: usize, const P: usize>(
+    layout: &TopicLayout<'_>,
+    mut connection: impl Connection,
+) {
+    let mut parser = MessageParser::<P, L>::new();
 
     while let Ok(event) = connection.next().await {
         let payload = event.payload();
@@ -308,29 +495,37 @@ pub async fn receive(mut connection: impl Connection) {
             ..
         } = payload
         {
-            if let Some(cmd) = parser.process(topic, data, &details) {
+            if let Some(cmd) = parser.process(layout, topic, data, &details) {
                 match cmd {
                     MqttCommand::Valve(open) => {
-                        valve::COMMAND.signal(if open {
+                        let command = if open {
                             ValveCommand::Open
                         } else {
                             ValveCommand::Close
-                        });
+                        };
+
+                        valve::command(command, CommandSource::Mqtt);
                     }
                     MqttCommand::FlowWatch(enable) => {
-                        wm::COMMAND.signal(if enable {
+                        let command = if enable {
                             WaterMeterCommand::Arm
                         } else {
                             WaterMeterCommand::Disarm
-                        });
+                        };
+
+                        wm::command(command, CommandSource::Mqtt);
                     }
+                    MqttCommand::PulseTrace(enable) => wm::set_tracing(enable),
+                    MqttCommand::PulseTraceDump => PULSE_TRACE_DUMP_NOTIFY.notify(),
                     _ => (),
                 }
             }
         } else if matches!(payload, EventPayload::Connected(_)) {
             CONN_SIGNAL.signal(true);
+            STATE.update(Some(true));
         } else if matches!(payload, EventPayload::Disconnected) {
             CONN_SIGNAL.signal(false);
+            STATE.update(Some(false));
         }
 
         for notification in RECEIVE_NOTIFY {
@@ -339,69 +534,50 @@ pub async fn receive(mut connection: impl Connection) {
     }
 }
 
-#[derive(Default)]
-struct MessageParser {
-    #[allow(clippy::type_complexity)]
-    command_parser: Option<fn(&[u8]) -> Option<MqttCommand>>,
-    payload_buf: [u8; 16],
+struct MessageParser<const P: usize, const L: usize> {
+    reassembler: Reassembler<P, L>,
 }
 
-impl MessageParser {
-    pub fn new() -> Self {
-        Default::default()
+impl<const P: usize, const L: usize> MessageParser<P, L> {
+    pub const fn new() -> Self {
+        Self {
+            reassembler: Reassembler::new(),
+        }
     }
 
     pub fn process(
         &mut self,
+        layout: &TopicLayout<'_>,
         topic: Option<&str>,
         payload: &[u8],
         details: &Details,
     ) -> Option<MqttCommand> {
-        match details {
-            Details::Complete => {
-                Self::parse_command(topic.unwrap()).and_then(|parser| parser(payload))
-            }
-            Details::InitialChunk(initial_chunk_data) => {
-                if initial_chunk_data.total_data_size > self.payload_buf.len() {
-                    self.command_parser = None;
-                } else {
-                    self.command_parser = Self::parse_command(topic.unwrap());
-                    self.payload_buf[..payload.len()].copy_from_slice(payload);
-                }
-
+        match self.reassembler.process(topic, payload, details) {
+            Ok(Some(message)) => message
+                .topic
+                .and_then(|topic| Self::parse_command(layout, topic))
+                .and_then(|parser| parser(message.payload)),
+            Ok(None) => None,
+            Err(err) => {
+                warn!("[MQTT/CONNECTION]: Dropping message: {:?}", err);
                 None
             }
-            Details::SubsequentChunk(subsequent_chunk_data) => {
-                if let Some(command_parser) = self.command_parser.as_ref() {
-                    self.payload_buf[subsequent_chunk_data.current_data_offset..payload.len()]
-                        .copy_from_slice(payload);
-
-                    if subsequent_chunk_data.total_data_size
-                        == subsequent_chunk_data.current_data_offset + payload.len()
-                    {
-                        command_parser(&self.payload_buf[0..subsequent_chunk_data.total_data_size])
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
         }
     }
 
     #[allow(clippy::type_complexity)]
-    fn parse_command(topic: &str) -> Option<fn(&[u8]) -> Option<MqttCommand>> {
-        if topic.ends_with("/commands/valve") {
-            Some(Self::parse_valve_command)
-        } else if topic.ends_with("/commands/flow_watch") {
-            Some(Self::parse_flow_watch_command)
-        } else if topic.ends_with("/commands/keep_alive") {
-            Some(Self::parse_keep_alive_command)
-        } else if topic.ends_with("/commands/system_update") {
-            Some(Self::parse_system_update_command)
-        } else {
-            None
+    fn parse_command(
+        layout: &TopicLayout<'_>,
+        topic: &str,
+    ) -> Option<fn(&[u8]) -> Option<MqttCommand>> {
+        match layout.command(topic)? {
+            "valve" => Some(Self::parse_valve_command),
+            "flow_watch" => Some(Self::parse_flow_watch_command),
+            "keep_alive" => Some(Self::parse_keep_alive_command),
+            "system_update" => Some(Self::parse_system_update_command),
+            "pulse_trace" => Some(Self::parse_pulse_trace_command),
+            "pulse_trace_dump" => Some(Self::parse_pulse_trace_dump_command),
+            _ => None,
         }
     }
 
@@ -421,6 +597,14 @@ impl MessageParser {
         Self::parse_empty(data).map(|_| MqttCommand::SystemUpdate)
     }
 
+    fn parse_pulse_trace_command(data: &[u8]) -> Option<MqttCommand> {
+        Self::parse::<bool>(data).map(MqttCommand::PulseTrace)
+    }
+
+    fn parse_pulse_trace_dump_command(data: &[u8]) -> Option<MqttCommand> {
+        Self::parse_empty(data).map(|_| MqttCommand::PulseTraceDump)
+    }
+
     fn parse<T>(data: &[u8]) -> Option<T>
     where
         T: str::FromStr,
@@ -438,3 +622,201 @@ impl MessageParser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use embedded_svc::mqtt::client::{Details, InitialChunkData, SubsequentChunkData};
+
+    use super::*;
+
+    type TestParser = MessageParser<64, 32>;
+
+    fn configuration() -> MqttConfiguration {
+        let mut configuration = MqttConfiguration::new();
+        configuration.client_id.push_str("ruwm").unwrap();
+
+        configuration
+    }
+
+    fn initial(total_data_size: usize) -> Details {
+        Details::InitialChunk(InitialChunkData { total_data_size })
+    }
+
+    fn subsequent(current_data_offset: usize, total_data_size: usize) -> Details {
+        Details::SubsequentChunk(SubsequentChunkData {
+            current_data_offset,
+            total_data_size,
+        })
+    }
+
+    /// Feeds `payload` split at `split`, as the MQTT client would; returns what the last chunk yields
+    fn chunked(
+        parser: &mut TestParser,
+        layout: &TopicLayout<'_>,
+        topic: &str,
+        payload: &[u8],
+        split: usize,
+    ) -> Option<MqttCommand> {
+        assert_eq!(
+            parser.process(
+                layout,
+                Some(topic),
+                &payload[..split],
+                &initial(payload.len())
+            ),
+            None
+        );
+
+        parser.process(
+            layout,
+            None,
+            &payload[split..],
+            &subsequent(split, payload.len()),
+        )
+    }
+
+    #[test]
+    fn complete_commands_are_parsed() {
+        let configuration = configuration();
+        let layout = TopicLayout::new(&configuration);
+
+        let mut parser = TestParser::new();
+
+        for (topic, payload, command) in [
+            (
+                "ruwm/commands/valve",
+                "true",
+                Some(MqttCommand::Valve(true)),
+            ),
+            (
+                "ruwm/commands/flow_watch",
+                "false",
+                Some(MqttCommand::FlowWatch(false)),
+            ),
+            (
+                "ruwm/commands/keep_alive",
+                "60",
+                Some(MqttCommand::KeepAlive(Duration::from_secs(60))),
+            ),
+            (
+                "ruwm/commands/system_update",
+                "",
+                Some(MqttCommand::SystemUpdate),
+            ),
+            (
+                "ruwm/commands/pulse_trace",
+                "true",
+                Some(MqttCommand::PulseTrace(true)),
+            ),
+            (
+                "ruwm/commands/pulse_trace_dump",
+                "",
+                Some(MqttCommand::PulseTraceDump),
+            ),
+            ("ruwm/commands/valve", "open", None),
+            ("ruwm/commands/system_update", "now", None),
+            ("ruwm/commands/unknown", "true", None),
+            ("other/commands/valve", "true", None),
+        ] {
+            assert_eq!(
+                parser.process(&layout, Some(topic), payload.as_bytes(), &Details::Complete),
+                command,
+                "{topic}: {payload}"
+            );
+        }
+    }
+
+    #[test]
+    fn chunked_commands_are_parsed() {
+        let configuration = configuration();
+        let layout = TopicLayout::new(&configuration);
+
+        let mut parser = TestParser::new();
+
+        for split in 1..4 {
+            assert_eq!(
+                chunked(&mut parser, &layout, "ruwm/commands/valve", b"true", split),
+                Some(MqttCommand::Valve(true))
+            );
+        }
+
+        assert_eq!(
+            chunked(&mut parser, &layout, "ruwm/commands/keep_alive", b"3600", 2),
+            Some(MqttCommand::KeepAlive(Duration::from_secs(3600)))
+        );
+
+        // Only the reassembled payload is parsed, not any of its chunks
+        assert_eq!(
+            chunked(&mut parser, &layout, "ruwm/commands/keep_alive", b"12", 1),
+            Some(MqttCommand::KeepAlive(Duration::from_secs(12)))
+        );
+    }
+
+    #[test]
+    fn broken_chunked_commands_are_dropped() {
+        let configuration = configuration();
+        let layout = TopicLayout::new(&configuration);
+
+        let mut parser = TestParser::new();
+
+        // A chunk of another message in between drops both
+        assert_eq!(
+            parser.process(&layout, Some("ruwm/commands/valve"), b"tr", &initial(4)),
+            None
+        );
+        assert_eq!(
+            parser.process(
+                &layout,
+                Some("ruwm/commands/flow_watch"),
+                b"ue",
+                &subsequent(2, 4)
+            ),
+            None
+        );
+        assert_eq!(
+            parser.process(&layout, None, b"ue", &subsequent(2, 4)),
+            None
+        );
+
+        // A complete message in between drops only the incomplete one
+        assert_eq!(
+            parser.process(&layout, Some("ruwm/commands/valve"), b"fa", &initial(5)),
+            None
+        );
+        assert_eq!(
+            parser.process(
+                &layout,
+                Some("ruwm/commands/flow_watch"),
+                b"true",
+                &Details::Complete
+            ),
+            Some(MqttCommand::FlowWatch(true))
+        );
+        assert_eq!(
+            parser.process(&layout, None, b"lse", &subsequent(2, 5)),
+            None
+        );
+
+        // Out of order chunks
+        assert_eq!(
+            parser.process(&layout, Some("ruwm/commands/valve"), b"fa", &initial(5)),
+            None
+        );
+        assert_eq!(
+            parser.process(&layout, None, b"se", &subsequent(3, 5)),
+            None
+        );
+
+        // Too large to be reassembled
+        assert_eq!(
+            parser.process(&layout, Some("ruwm/commands/valve"), b"tr", &initial(65)),
+            None
+        );
+
+        // None of which breaks the next message
+        assert_eq!(
+            chunked(&mut parser, &layout, "ruwm/commands/valve", b"false", 3),
+            Some(MqttCommand::Valve(false))
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use core::fmt::{Debug, Write};
+
+use serde::{Deserialize, Serialize};
+
+use heapless::{Deque, String};
+
+use embedded_svc::mqtt::client::QoS;
+
+use crate::audit::AuditEvent;
+use crate::command::CommandSource;
+use crate::emergency::LockoutState;
+use crate::valve::ValveState;
+
+use super::MqttTopics;
+
+pub const OUTBOX_SIZE: usize = 32;
+
+/// The maximum length of an encoded payload
+pub const OUTBOX_PAYLOAD_MAX_LEN: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutboxPayload {
+    Valve(Option<ValveState>),
+    /// The source of the last command which moved the valve
+    ValveSource(CommandSource),
+    /// The emergency lockout of the valve, if any
+    Lockout(Option<LockoutState>),
+    MeterEdges(u64),
+    MeterArmed(bool),
+    MeterLeak(bool),
+    BatteryVoltage(u16),
+    BatteryLow(bool),
+    BatteryCharged(bool),
+    Powered(bool),
+    /// An audit log event, published as text on the events entity
+    Event(AuditEvent),
+}
+
+impl OutboxPayload {
+    /// The entity and attribute segments of the topic this payload is published to.
+    pub fn topic_segments<'a>(&self, topics: &'a MqttTopics) -> (&'a str, Option<&'static str>) {
+        match self {
+            Self::Valve(_) => (topics.valve(), None),
+            Self::ValveSource(_) => (topics.valve(), Some("source")),
+            Self::Lockout(_) => (topics.valve(), Some("lockout")),
+            Self::MeterEdges(_) => (topics.meter(), Some("edges")),
+            Self::MeterArmed(_) => (topics.meter(), Some("armed")),
+            Self::MeterLeak(_) => (topics.meter(), Some("leak")),
+            Self::BatteryVoltage(_) => (topics.battery(), Some("voltage")),
+            Self::BatteryLow(_) => (topics.battery(), Some("low")),
+            Self::BatteryCharged(_) => (topics.battery(), Some("charged")),
+            Self::Powered(_) => (topics.powered(), None),
+            Self::Event(_) => (topics.events(), None),
+        }
+    }
+
+    pub fn qos(&self) -> QoS {
+        match self {
+            Self::BatteryVoltage(_) | Self::BatteryCharged(_) | Self::Powered(_) => QoS::AtMostOnce,
+            _ => QoS::AtLeastOnce,
+        }
+    }
+
+    /// High-rate values where only the most recent one is of interest.
+    /// A queued entry of the same kind is removed when a new one is appended, so that the value
+    /// is published after - and never before - whatever was queued ahead of it.
+    pub fn coalesce(&self) -> bool {
+        matches!(self, Self::MeterEdges(_) | Self::BatteryVoltage(_))
+    }
+
+    pub fn encode<'a>(&self, buf: &'a mut [u8; OUTBOX_PAYLOAD_MAX_LEN]) -> &'a [u8] {
+        match self {
+            Self::Valve(valve_state) => match valve_state {
+                Some(ValveState::Open) => "open",
+                Some(ValveState::Opening(_)) => "opening",
+                Some(ValveState::Closed) => "closed",
+                Some(ValveState::Closing(_)) => "closing",
+                None => "unknown",
+            }
+            .as_bytes(),
+            Self::ValveSource(source) => source.text().as_bytes(),
+            Self::Lockout(None) => "none".as_bytes(),
+            Self::Lockout(Some(lockout)) if lockout.condition_present => {
+                lockout.reason.text().as_bytes()
+            }
+            Self::Lockout(Some(lockout)) => {
+                let mut text = String::<OUTBOX_PAYLOAD_MAX_LEN>::new();
+
+                let _ = write!(&mut text, "{} (ack pending)", lockout.reason.text());
+
+                buf[..text.len()].copy_from_slice(text.as_bytes());
+                &buf[..text.len()]
+            }
+            Self::MeterEdges(edges_count) => {
+                buf[..8].copy_from_slice(&edges_count.to_le_bytes());
+                &buf[..8]
+            }
+            Self::BatteryVoltage(voltage) => {
+                buf[..2].copy_from_slice(&voltage.to_le_bytes());
+                &buf[..2]
+            }
+            Self::MeterArmed(value)
+            | Self::MeterLeak(value)
+            | Self::BatteryLow(value)
+            | Self::BatteryCharged(value)
+            | Self::Powered(value) => (if *value { "true" } else { "false" }).as_bytes(),
+            Self::Event(event) => {
+                let mut text = String::<OUTBOX_PAYLOAD_MAX_LEN>::new();
+
+                // Truncated rather than dropped, should an event ever not fit
+                let _ = write!(&mut text, "{}", event);
+
+                buf[..text.len()].copy_from_slice(text.as_bytes());
+                &buf[..text.len()]
+            }
+        }
+    }
+
+    fn same_kind(&self, other: &Self) -> bool {
+        core::mem::discriminant(self) == core::mem::discriminant(other)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Seconds on the clock set with `set_clock_hook` when the entry was queued
+    pub time_secs: u64,
+    pub payload: OutboxPayload,
+}
+
+impl OutboxEntry {
+    /// The attribute appended to the topic of the payload, on which the time of the entry is published
+    pub const TIME_ATTRIBUTE: &'static str = "time";
+
+    pub fn encode_time(&self) -> [u8; 8] {
+        self.time_secs.to_le_bytes()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    DropOldest,
+    DropNewest,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Outbox<const N: usize> {
+    entries: Deque<OutboxEntry, N>,
+    overflow_policy: OverflowPolicy,
+    dropped: u32,
+}
+
+impl<const N: usize> Outbox<N> {
+    pub const fn new(overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            entries: Deque::new(),
+            overflow_policy,
+            dropped: 0,
+        }
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    /// Number of entries lost due to overflow since the outbox was created.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, entry: OutboxEntry) {
+        if entry.payload.coalesce() {
+            // Rotate through the queue, so as to keep the order of everything but the replaced entry
+            for _ in 0..self.entries.len() {
+                let queued = self.entries.pop_front().unwrap();
+
+                if !queued.payload.same_kind(&entry.payload) {
+                    self.entries.push_back(queued).unwrap();
+                }
+            }
+        }
+
+        if self.entries.is_full() {
+            self.dropped = self.dropped.wrapping_add(1);
+
+            match self.overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    self.entries.pop_front();
+                }
+                OverflowPolicy::DropNewest => return,
+            }
+        }
+
+        self.entries.push_back(entry).unwrap();
+    }
+
+    pub fn front(&self) -> Option<&OutboxEntry> {
+        self.entries.front()
+    }
+
+    pub fn pop_front(&mut self) -> Option<OutboxEntry> {
+        self.entries.pop_front()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &OutboxEntry> {
+        self.entries.iter()
+    }
+}
+
+impl<const N: usize> Default for Outbox<N> {
+    fn default() -> Self {
+        Self::new(OverflowPolicy::DropOldest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time_secs: u64, payload: OutboxPayload) -> OutboxEntry {
+        OutboxEntry { time_secs, payload }
+    }
+
+    fn payloads<const N: usize>(outbox: &Outbox<N>) -> impl Iterator<Item = OutboxPayload> + '_ {
+        outbox.iter().map(|entry| entry.payload)
+    }
+
+    #[test]
+    fn coalesced_value_is_published_last() {
+        let mut outbox = Outbox::<8>::new(OverflowPolicy::DropOldest);
+
+        outbox.push(entry(1, OutboxPayload::MeterEdges(10)));
+        outbox.push(entry(2, OutboxPayload::MeterArmed(true)));
+        outbox.push(entry(3, OutboxPayload::BatteryVoltage(3000)));
+        outbox.push(entry(4, OutboxPayload::MeterEdges(20)));
+
+        assert!(payloads(&outbox).eq([
+            OutboxPayload::MeterArmed(true),
+            OutboxPayload::BatteryVoltage(3000),
+            OutboxPayload::MeterEdges(20),
+        ]));
+        assert_eq!(outbox.iter().last().unwrap().time_secs, 4);
+        assert_eq!(outbox.dropped(), 0);
+    }
+
+    #[test]
+    fn time_is_encoded_like_the_numeric_payloads() {
+        let entry = entry(1_700_000_000, OutboxPayload::MeterEdges(20));
+
+        let mut buf = [0; OUTBOX_PAYLOAD_MAX_LEN];
+
+        assert_eq!(entry.encode_time(), 1_700_000_000_u64.to_le_bytes());
+        assert_eq!(
+            entry.encode_time().len(),
+            entry.payload.encode(&mut buf).len()
+        );
+    }
+
+    #[test]
+    fn other_values_are_not_coalesced() {
+        let mut outbox = Outbox::<8>::new(OverflowPolicy::DropOldest);
+
+        outbox.push(entry(1, OutboxPayload::MeterArmed(true)));
+        outbox.push(entry(2, OutboxPayload::MeterArmed(false)));
+        outbox.push(entry(3, OutboxPayload::MeterArmed(true)));
+
+        assert_eq!(outbox.len(), 3);
+    }
+
+    #[test]
+    fn coalescing_a_full_outbox_drops_nothing() {
+        for overflow_policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let mut outbox = Outbox::<3>::new(overflow_policy);
+
+            outbox.push(entry(1, OutboxPayload::MeterEdges(10)));
+            outbox.push(entry(2, OutboxPayload::MeterArmed(true)));
+            outbox.push(entry(3, OutboxPayload::MeterLeak(false)));
+            outbox.push(entry(4, OutboxPayload::MeterEdges(20)));
+
+            assert!(payloads(&outbox).eq([
+                OutboxPayload::MeterArmed(true),
+                OutboxPayload::MeterLeak(false),
+                OutboxPayload::MeterEdges(20),
+            ]));
+            assert_eq!(outbox.dropped(), 0);
+        }
+    }
+
+    #[test]
+    fn drop_oldest_overflow() {
+        let mut outbox = Outbox::<2>::new(OverflowPolicy::DropOldest);
+
+        outbox.push(entry(1, OutboxPayload::MeterArmed(true)));
+        outbox.push(entry(2, OutboxPayload::MeterLeak(true)));
+        outbox.push(entry(3, OutboxPayload::Powered(false)));
+
+        assert!(payloads(&outbox).eq([
+            OutboxPayload::MeterLeak(true),
+            OutboxPayload::Powered(false),
+        ]));
+        assert_eq!(outbox.dropped(), 1);
+    }
+
+    #[test]
+    fn drop_newest_overflow() {
+        let mut outbox = Outbox::<2>::new(OverflowPolicy::DropNewest);
+
+        outbox.push(entry(1, OutboxPayload::MeterArmed(true)));
+        outbox.push(entry(2, OutboxPayload::MeterLeak(true)));
+        outbox.push(entry(3, OutboxPayload::Powered(false)));
+
+        assert!(payloads(&outbox).eq([
+            OutboxPayload::MeterArmed(true),
+            OutboxPayload::MeterLeak(true),
+        ]));
+        assert_eq!(outbox.dropped(), 1);
+
+        // Room again, once something got published
+        outbox.pop_front();
+        outbox.push(entry(4, OutboxPayload::Powered(false)));
+
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(outbox.dropped(), 1);
+    }
+}
@@ -0,0 +1,488 @@
+use log::warn;
+
+use heapless::String;
+
+use embedded_svc::mqtt::client::Details;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReassemblyError {
+    /// The message does not fit in the reassembly buffer
+    TooLarge,
+    /// The topic of the message does not fit in the reassembly buffer
+    TopicTooLong,
+    /// A chunk arrived without a preceding initial chunk
+    UnexpectedChunk,
+    /// A chunk does not continue the message being reassembled (wrong offset or size)
+    OutOfOrder,
+    /// A chunk of a message on a different topic arrived before the current message was complete;
+    /// as its initial chunk is not known, both messages are dropped
+    Interleaved,
+}
+
+/// A message (complete or reassembled from chunks) as returned by `Reassembler::process`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReassembledMessage<'a> {
+    pub topic: Option<&'a str>,
+    pub payload: &'a [u8],
+}
+
+/// Reassembles chunked MQTT messages into a buffer of `N` bytes.
+/// Topics of up to `T` bytes are tracked so that interleaved messages can be detected.
+///
+/// A message which starts while another one is still being reassembled aborts the latter,
+/// and is then processed as usual.
+pub struct Reassembler<const N: usize, const T: usize> {
+    topic: Option<String<T>>,
+    total_len: usize,
+    received_len: usize,
+    in_progress: bool,
+    aborted: u32,
+    buf: [u8; N],
+}
+
+impl<const N: usize, const T: usize> Reassembler<N, T> {
+    pub const fn new() -> Self {
+        Self {
+            topic: None,
+            total_len: 0,
+            received_len: 0,
+            in_progress: false,
+            aborted: 0,
+            buf: [0; N],
+        }
+    }
+
+    pub fn is_in_progress(&self) -> bool {
+        self.in_progress
+    }
+
+    /// The number of incomplete messages aborted so far, because a new message started
+    pub fn aborted(&self) -> u32 {
+        self.aborted
+    }
+
+    pub fn reset(&mut self) {
+        self.topic = None;
+        self.total_len = 0;
+        self.received_len = 0;
+        self.in_progress = false;
+    }
+
+    /// Feeds a received message or message chunk.
+    ///
+    /// Returns `Ok(Some(_))` once a complete message is available, and `Ok(None)` while
+    /// chunks are still outstanding. Any error aborts the message being reassembled.
+    /// The start of a new message aborts it as well, but is not an error.
+    pub fn process<'a>(
+        &'a mut self,
+        topic: Option<&'a str>,
+        payload: &'a [u8],
+        details: &Details,
+    ) -> Result<Option<ReassembledMessage<'a>>, ReassemblyError> {
+        match details {
+            Details::Complete => {
+                self.abort();
+
+                Ok(Some(ReassembledMessage { topic, payload }))
+            }
+            Details::InitialChunk(initial_chunk_data) => {
+                self.abort();
+
+                let total_len = initial_chunk_data.total_data_size;
+
+                if total_len > N {
+                    return Err(ReassemblyError::TooLarge);
+                }
+
+                if payload.len() > total_len {
+                    return Err(ReassemblyError::OutOfOrder);
+                }
+
+                if let Some(topic) = topic {
+                    let mut stored = String::new();
+                    stored
+                        .push_str(topic)
+                        .map_err(|_| ReassemblyError::TopicTooLong)?;
+
+                    self.topic = Some(stored);
+                }
+
+                self.buf[..payload.len()].copy_from_slice(payload);
+
+                self.total_len = total_len;
+                self.received_len = payload.len();
+                self.in_progress = true;
+
+                Ok(None)
+            }
+            Details::SubsequentChunk(subsequent_chunk_data) => {
+                if !self.in_progress {
+                    return Err(ReassemblyError::UnexpectedChunk);
+                }
+
+                if let Some(topic) = topic {
+                    if self.topic.as_deref() != Some(topic) {
+                        self.reset();
+                        return Err(ReassemblyError::Interleaved);
+                    }
+                }
+
+                let offset = subsequent_chunk_data.current_data_offset;
+
+                if subsequent_chunk_data.total_data_size != self.total_len
+                    || offset != self.received_len
+                    || offset + payload.len() > self.total_len
+                {
+                    self.reset();
+                    return Err(ReassemblyError::OutOfOrder);
+                }
+
+                self.buf[offset..offset + payload.len()].copy_from_slice(payload);
+                self.received_len += payload.len();
+
+                if self.received_len == self.total_len {
+                    self.in_progress = false;
+
+                    Ok(Some(ReassembledMessage {
+                        topic: self.topic.as_deref(),
+                        payload: &self.buf[..self.total_len],
+                    }))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    fn abort(&mut self) {
+        if self.in_progress {
+            warn!(
+                "Dropping incomplete message on {:?}: a new message started",
+                self.topic.as_deref()
+            );
+
+            self.reset();
+            self.aborted = self.aborted.wrapping_add(1);
+        }
+    }
+}
+
+impl<const N: usize, const T: usize> Default for Reassembler<N, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use embedded_svc::mqtt::client::{Details, InitialChunkData, SubsequentChunkData};
+
+    use super::*;
+
+    const N: usize = 64;
+
+    type TestReassembler = Reassembler<N, 16>;
+
+    fn initial(total_data_size: usize) -> Details {
+        Details::InitialChunk(InitialChunkData { total_data_size })
+    }
+
+    fn subsequent(current_data_offset: usize, total_data_size: usize) -> Details {
+        Details::SubsequentChunk(SubsequentChunkData {
+            current_data_offset,
+            total_data_size,
+        })
+    }
+
+    /// Feeds `payload` in chunks of the given lengths, as the MQTT client would; returns what the last chunk yields
+    fn feed(
+        reassembler: &mut TestReassembler,
+        topic: &str,
+        payload: &[u8],
+        lens: &[usize],
+    ) -> Result<Option<Vec<u8, N>>, ReassemblyError> {
+        let mut offset = 0;
+        let mut result = Ok(None);
+
+        for (index, len) in lens.iter().enumerate() {
+            let chunk = &payload[offset..offset + len];
+
+            result = if index == 0 {
+                reassembler.process(Some(topic), chunk, &initial(payload.len()))
+            } else {
+                reassembler.process(None, chunk, &subsequent(offset, payload.len()))
+            }
+            .map(|message| message.map(|message| Vec::from_slice(message.payload).unwrap()));
+
+            if index < lens.len() - 1 {
+                assert_eq!(result, Ok(None));
+            }
+
+            offset += len;
+        }
+
+        result
+    }
+
+    /// A xorshift generator, so that the properties are checked against the same inputs on every run
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            self.next() as usize % bound
+        }
+
+        fn payload(&mut self, len: usize) -> Vec<u8, N> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        /// Splits `len` into at least two chunks
+        fn lens(&mut self, len: usize) -> Vec<usize, N> {
+            let mut lens = Vec::new();
+            let mut remaining = len;
+
+            while remaining > 0 {
+                let max = if lens.is_empty() {
+                    remaining - 1
+                } else {
+                    remaining
+                };
+                let chunk = 1 + self.below(max);
+
+                lens.push(chunk).unwrap();
+                remaining -= chunk;
+            }
+
+            lens
+        }
+    }
+
+    #[test]
+    fn complete_message_passes_through() {
+        let mut reassembler = TestReassembler::new();
+
+        let message = reassembler
+            .process(Some("a"), b"payload", &Details::Complete)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(message.topic, Some("a"));
+        assert_eq!(message.payload, b"payload");
+    }
+
+    #[test]
+    fn chunks_are_reassembled() {
+        let mut reassembler = TestReassembler::new();
+
+        let message = feed(&mut reassembler, "a", b"chunked payload", &[3, 7, 5]);
+
+        assert_eq!(message.unwrap().unwrap(), b"chunked payload");
+        assert!(!reassembler.is_in_progress());
+    }
+
+    #[test]
+    fn new_complete_message_aborts_only_the_incomplete_one() {
+        let mut reassembler = TestReassembler::new();
+
+        assert_eq!(
+            reassembler.process(Some("a"), b"sta", &initial(5)),
+            Ok(None)
+        );
+
+        let message = reassembler
+            .process(Some("b"), b"new", &Details::Complete)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(message.topic, Some("b"));
+        assert_eq!(message.payload, b"new");
+        assert_eq!(reassembler.aborted(), 1);
+
+        // The rest of the aborted message is not taken for the start of another one
+        assert_eq!(
+            reassembler
+                .process(None, b"le", &subsequent(3, 5))
+                .map(|message| message.is_some()),
+            Err(ReassemblyError::UnexpectedChunk)
+        );
+    }
+
+    #[test]
+    fn new_initial_chunk_aborts_only_the_incomplete_one() {
+        let mut reassembler = TestReassembler::new();
+
+        assert_eq!(
+            reassembler.process(Some("a"), b"sta", &initial(5)),
+            Ok(None)
+        );
+
+        let message = feed(&mut reassembler, "b", b"new one", &[4, 3]);
+
+        assert_eq!(message.unwrap().unwrap(), b"new one");
+        assert_eq!(reassembler.aborted(), 1);
+    }
+
+    #[test]
+    fn chunk_of_another_topic_is_interleaved() {
+        let mut reassembler = TestReassembler::new();
+
+        assert_eq!(
+            reassembler.process(Some("a"), b"sta", &initial(5)),
+            Ok(None)
+        );
+
+        assert_eq!(
+            reassembler
+                .process(Some("b"), b"le", &subsequent(3, 5))
+                .map(|message| message.is_some()),
+            Err(ReassemblyError::Interleaved)
+        );
+        assert!(!reassembler.is_in_progress());
+    }
+
+    #[test]
+    fn malformed_chunks_are_rejected() {
+        let mut reassembler = TestReassembler::new();
+
+        assert_eq!(
+            reassembler
+                .process(None, b"le", &subsequent(3, 5))
+                .map(|message| message.is_some()),
+            Err(ReassemblyError::UnexpectedChunk)
+        );
+
+        assert_eq!(
+            reassembler
+                .process(Some("a"), b"sta", &initial(N + 1))
+                .map(|message| message.is_some()),
+            Err(ReassemblyError::TooLarge)
+        );
+
+        assert_eq!(
+            reassembler
+                .process(Some("a topic too long to track"), b"sta", &initial(5))
+                .map(|message| message.is_some()),
+            Err(ReassemblyError::TopicTooLong)
+        );
+
+        assert_eq!(
+            reassembler.process(Some("a"), b"sta", &initial(5)),
+            Ok(None)
+        );
+        assert_eq!(
+            reassembler
+                .process(None, b"le", &subsequent(2, 5))
+                .map(|message| message.is_some()),
+            Err(ReassemblyError::OutOfOrder)
+        );
+        assert!(!reassembler.is_in_progress());
+    }
+
+    #[test]
+    fn any_split_reassembles_the_payload() {
+        let mut rng = Rng(0x2545_f491);
+        let mut reassembler = TestReassembler::new();
+
+        for _ in 0..1000 {
+            let len = 2 + rng.below(N - 1);
+            let payload = rng.payload(len);
+            let lens = rng.lens(payload.len());
+
+            let message = feed(&mut reassembler, "a", &payload, &lens);
+
+            assert_eq!(message.unwrap().unwrap(), payload);
+            assert!(!reassembler.is_in_progress());
+        }
+
+        assert_eq!(reassembler.aborted(), 0);
+    }
+
+    #[test]
+    fn interruptions_lose_only_the_interrupted_message() {
+        let mut rng = Rng(0x9e37_79b9);
+        let mut reassembler = TestReassembler::new();
+
+        for round in 0..1000 {
+            let len = 2 + rng.below(N - 1);
+            let payload = rng.payload(len);
+            let lens = rng.lens(payload.len());
+            let interrupted_at = rng.below(lens.len());
+
+            let mut offset = 0;
+
+            for (index, len) in lens.iter().enumerate() {
+                if index == interrupted_at && index > 0 {
+                    let message = reassembler
+                        .process(Some("b"), b"interruption", &Details::Complete)
+                        .unwrap()
+                        .unwrap();
+
+                    assert_eq!(message.payload, b"interruption");
+                }
+
+                let chunk = &payload[offset..offset + len];
+
+                let result = if index == 0 {
+                    reassembler.process(Some("a"), chunk, &initial(payload.len()))
+                } else {
+                    reassembler.process(None, chunk, &subsequent(offset, payload.len()))
+                };
+
+                if interrupted_at > 0 && index >= interrupted_at {
+                    assert_eq!(
+                        result.map(|message| message.is_some()),
+                        Err(ReassemblyError::UnexpectedChunk)
+                    );
+                } else if index == lens.len() - 1 {
+                    assert_eq!(result.unwrap().unwrap().payload, &payload[..]);
+                } else {
+                    assert_eq!(result.map(|message| message.is_some()), Ok(false));
+                }
+
+                offset += len;
+            }
+
+            assert!(!reassembler.is_in_progress(), "round {round}");
+        }
+    }
+
+    #[test]
+    fn arbitrary_input_is_handled() {
+        let mut rng = Rng(0x1234_5678);
+        let mut reassembler = TestReassembler::new();
+
+        let mut buf = [0; N * 2];
+
+        for _ in 0..10_000 {
+            let len = rng.below(buf.len());
+            let payload = &mut buf[..len];
+
+            payload.fill(rng.next() as u8);
+
+            let total = rng.below(N * 2);
+            let topic = [None, Some("a"), Some("b")][rng.below(3)];
+
+            let details = match rng.below(3) {
+                0 => Details::Complete,
+                1 => initial(total),
+                _ => subsequent(rng.below(N * 2), total),
+            };
+
+            if let Ok(Some(message)) = reassembler.process(topic, payload, &details) {
+                if !matches!(details, Details::Complete) {
+                    assert_eq!(message.payload.len(), total);
+                    assert!(message.payload.len() <= N);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use embedded_svc::storage::RawStorage;
+
+use super::MqttConfiguration;
+
+#[derive(Debug)]
+pub enum MqttTlsError<E> {
+    Storage(E),
+    NotFound,
+    BufferTooSmall,
+    NotTls,
+    IncompleteClientIdentity,
+    /// Neither PEM, nor a single DER-encoded ASN.1 structure
+    Malformed,
+}
+
+/// A certificate or private key, as loaded from storage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MqttCertificate<'a> {
+    /// PEM text, NUL-terminated, as expected by most embedded TLS stacks
+    Pem(&'a [u8]),
+    Der(&'a [u8]),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Format {
+    Pem,
+    Der,
+}
+
+impl Format {
+    /// Tells PEM from DER; PEM `data` may or may not be NUL-terminated already
+    fn detect(data: &[u8]) -> Option<Self> {
+        let text = data.strip_suffix(&[0]).unwrap_or(data);
+        let text = &text[text
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .unwrap_or(text.len())..];
+
+        if text.starts_with(b"-----BEGIN ") && contains(text, b"-----END ") {
+            Some(Self::Pem)
+        } else if der_len(data) == Some(data.len()) {
+            Some(Self::Der)
+        } else {
+            None
+        }
+    }
+}
+
+/// The certificates referenced by an `MqttConfiguration`, loaded from storage
+#[derive(Debug, Default)]
+pub struct MqttCertificates<'a> {
+    pub ca_certificate: Option<MqttCertificate<'a>>,
+    pub client_certificate: Option<MqttCertificate<'a>>,
+    pub client_key: Option<MqttCertificate<'a>>,
+}
+
+impl<'a> MqttCertificates<'a> {
+    pub fn load<S>(
+        configuration: &MqttConfiguration,
+        storage: &S,
+        ca_certificate_buf: &'a mut [u8],
+        client_certificate_buf: &'a mut [u8],
+        client_key_buf: &'a mut [u8],
+    ) -> Result<Self, MqttTlsError<S::Error>>
+    where
+        S: RawStorage,
+    {
+        let tls_requested = configuration.ca_certificate.is_some()
+            || configuration.client_certificate.is_some()
+            || configuration.client_key.is_some();
+
+        if tls_requested && !configuration.is_tls() {
+            return Err(MqttTlsError::NotTls);
+        }
+
+        if configuration.client_certificate.is_some() != configuration.client_key.is_some() {
+            return Err(MqttTlsError::IncompleteClientIdentity);
+        }
+
+        Ok(Self {
+            ca_certificate: Self::load_one(
+                storage,
+                configuration.ca_certificate.as_deref(),
+                ca_certificate_buf,
+            )?,
+            client_certificate: Self::load_one(
+                storage,
+                configuration.client_certificate.as_deref(),
+                client_certificate_buf,
+            )?,
+            client_key: Self::load_one(
+                storage,
+                configuration.client_key.as_deref(),
+                client_key_buf,
+            )?,
+        })
+    }
+
+    fn load_one<S>(
+        storage: &S,
+        name: Option<&str>,
+        buf: &'a mut [u8],
+    ) -> Result<Option<MqttCertificate<'a>>, MqttTlsError<S::Error>>
+    where
+        S: RawStorage,
+    {
+        let Some(name) = name else {
+            return Ok(None);
+        };
+
+        let len = storage
+            .len(name)
+            .map_err(MqttTlsError::Storage)?
+            .ok_or(MqttTlsError::NotFound)?;
+
+        if len + 1 > buf.len() {
+            return Err(MqttTlsError::BufferTooSmall);
+        }
+
+        let len = storage
+            .get_raw(name, buf)
+            .map_err(MqttTlsError::Storage)?
+            .ok_or(MqttTlsError::NotFound)?
+            .len();
+
+        match Format::detect(&buf[..len]).ok_or(MqttTlsError::Malformed)? {
+            Format::Pem if buf[len - 1] != 0 => {
+                buf[len] = 0;
+
+                Ok(Some(MqttCertificate::Pem(&buf[..len + 1])))
+            }
+            Format::Pem => Ok(Some(MqttCertificate::Pem(&buf[..len]))),
+            Format::Der => Ok(Some(MqttCertificate::Der(&buf[..len]))),
+        }
+    }
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+/// The length of the DER-encoded ASN.1 `SEQUENCE` at the start of `data`, header included
+fn der_len(data: &[u8]) -> Option<usize> {
+    const SEQUENCE: u8 = 0x30;
+
+    let (&tag, data) = data.split_first()?;
+    let (&len, data) = data.split_first()?;
+
+    if tag != SEQUENCE {
+        return None;
+    }
+
+    if len < 0x80 {
+        return Some(2 + len as usize);
+    }
+
+    // The long form, with up to four length bytes
+    let len_len = (len & 0x7f) as usize;
+
+    if len_len == 0 || len_len > 4 || data.len() < len_len {
+        return None;
+    }
+
+    let len = data[..len_len]
+        .iter()
+        .fold(0, |len, byte| (len << 8) | *byte as usize);
+
+    len.checked_add(2 + len_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_svc::storage::StorageBase;
+
+    use super::*;
+
+    const PEM: &[u8] = b"-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n";
+    const PEM_NUL: &[u8] = b"-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n\0";
+
+    /// A `SEQUENCE` holding an `INTEGER`
+    const DER: &[u8] = &[0x30, 0x03, 0x02, 0x01, 0x01];
+
+    /// A `SEQUENCE` of 0x100 bytes, with the length in its long form
+    const DER_LONG: [u8; 0x104] = {
+        let mut der = [0; 0x104];
+
+        der[0] = 0x30;
+        der[1] = 0x82;
+        der[2] = 0x01;
+        der[3] = 0x00;
+
+        der
+    };
+
+    struct MockStorage<'a>(&'a [(&'a str, &'a [u8])]);
+
+    impl<'a> MockStorage<'a> {
+        fn find(&self, name: &str) -> Option<&'a [u8]> {
+            self.0
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, data)| *data)
+        }
+    }
+
+    impl<'a> StorageBase for MockStorage<'a> {
+        type Error = ();
+
+        fn contains(&self, name: &str) -> Result<bool, Self::Error> {
+            Ok(self.find(name).is_some())
+        }
+
+        fn remove(&mut self, _name: &str) -> Result<bool, Self::Error> {
+            Err(())
+        }
+    }
+
+    impl<'a> RawStorage for MockStorage<'a> {
+        fn len(&self, name: &str) -> Result<Option<usize>, Self::Error> {
+            Ok(self.find(name).map(|data| data.len()))
+        }
+
+        fn get_raw<'b>(
+            &self,
+            name: &str,
+            buf: &'b mut [u8],
+        ) -> Result<Option<&'b [u8]>, Self::Error> {
+            let Some(data) = self.find(name) else {
+                return Ok(None);
+            };
+
+            buf[..data.len()].copy_from_slice(data);
+
+            Ok(Some(&buf[..data.len()]))
+        }
+
+        fn set_raw(&mut self, _name: &str, _buf: &[u8]) -> Result<bool, Self::Error> {
+            Err(())
+        }
+    }
+
+    fn configuration(
+        url: &str,
+        ca: Option<&str>,
+        client: Option<(&str, &str)>,
+    ) -> MqttConfiguration {
+        let mut configuration = MqttConfiguration::new();
+
+        configuration.url.push_str(url).unwrap();
+        configuration.ca_certificate = ca.map(|name| name.try_into().unwrap());
+        configuration.client_certificate = client.map(|(name, _)| name.try_into().unwrap());
+        configuration.client_key = client.map(|(_, name)| name.try_into().unwrap());
+
+        configuration
+    }
+
+    fn load<'a>(
+        configuration: &MqttConfiguration,
+        storage: &MockStorage<'_>,
+        bufs: &'a mut [[u8; 512]; 3],
+    ) -> Result<MqttCertificates<'a>, MqttTlsError<()>> {
+        let [ca, cert, key] = bufs;
+
+        MqttCertificates::load(configuration, storage, ca, cert, key)
+    }
+
+    fn load_ca<'a>(
+        data: &[u8],
+        bufs: &'a mut [[u8; 512]; 3],
+    ) -> Result<Option<MqttCertificate<'a>>, MqttTlsError<()>> {
+        let entries = [("ca", data)];
+        let storage = MockStorage(&entries);
+
+        load(&with_ca("ca"), &storage, bufs).map(|certificates| certificates.ca_certificate)
+    }
+
+    #[test]
+    fn plain_connection_loads_nothing() {
+        let storage = MockStorage(&[]);
+        let mut bufs = [[0; 512]; 3];
+
+        let certificates = load(
+            &configuration("mqtt://broker", None, None),
+            &storage,
+            &mut bufs,
+        )
+        .unwrap();
+
+        assert_eq!(certificates.ca_certificate, None);
+        assert_eq!(certificates.client_certificate, None);
+        assert_eq!(certificates.client_key, None);
+    }
+
+    #[test]
+    fn pem_is_nul_terminated() {
+        assert_eq!(
+            load_ca(PEM, &mut [[0; 512]; 3]).unwrap(),
+            Some(MqttCertificate::Pem(PEM_NUL))
+        );
+
+        // Already terminated in storage
+        assert_eq!(
+            load_ca(PEM_NUL, &mut [[0; 512]; 3]).unwrap(),
+            Some(MqttCertificate::Pem(PEM_NUL))
+        );
+    }
+
+    #[test]
+    fn der_is_taken_as_is() {
+        assert_eq!(
+            load_ca(DER, &mut [[0; 512]; 3]).unwrap(),
+            Some(MqttCertificate::Der(DER))
+        );
+        assert_eq!(
+            load_ca(&DER_LONG, &mut [[0; 512]; 3]).unwrap(),
+            Some(MqttCertificate::Der(&DER_LONG))
+        );
+    }
+
+    #[test]
+    fn malformed_certificates_are_rejected() {
+        for data in [
+            &b""[..],
+            b"\0",
+            b"not a certificate",
+            b"-----BEGIN CERTIFICATE-----\nMIIB\n",
+            // Truncated, or followed by garbage
+            &DER[..4],
+            &[0x30, 0x03, 0x02, 0x01, 0x01, 0x00],
+            &DER_LONG[..0x103],
+            // Not a SEQUENCE
+            &[0x02, 0x01, 0x01],
+            // Indefinite length, which DER does not allow
+            &[0x30, 0x80, 0x00, 0x00],
+        ] {
+            assert!(
+                matches!(
+                    load_ca(data, &mut [[0; 512]; 3]),
+                    Err(MqttTlsError::Malformed)
+                ),
+                "{data:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn client_identity_is_loaded() {
+        let storage = MockStorage(&[("ca", PEM), ("cert", DER), ("key", PEM)]);
+        let mut bufs = [[0; 512]; 3];
+
+        let certificates = load(
+            &configuration("mqtts://broker", Some("ca"), Some(("cert", "key"))),
+            &storage,
+            &mut bufs,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            certificates.ca_certificate,
+            Some(MqttCertificate::Pem(_))
+        ));
+        assert_eq!(
+            certificates.client_certificate,
+            Some(MqttCertificate::Der(DER))
+        );
+        assert!(matches!(
+            certificates.client_key,
+            Some(MqttCertificate::Pem(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        let storage = MockStorage(&[("ca", PEM), ("cert", PEM), ("key", PEM)]);
+        let mut bufs = [[0; 512]; 3];
+
+        assert!(matches!(
+            load(
+                &configuration("mqtt://broker", Some("ca"), None),
+                &storage,
+                &mut bufs
+            ),
+            Err(MqttTlsError::NotTls)
+        ));
+
+        let mut configuration = configuration("mqtts://broker", None, Some(("cert", "key")));
+        configuration.client_key = None;
+
+        assert!(matches!(
+            load(&configuration, &storage, &mut bufs),
+            Err(MqttTlsError::IncompleteClientIdentity)
+        ));
+
+        assert!(matches!(
+            load(&with_ca("missing"), &storage, &mut bufs),
+            Err(MqttTlsError::NotFound)
+        ));
+
+        let storage = MockStorage(&[("ca", &DER_LONG[..])]);
+        let mut client_cert = [0; 512];
+        let mut client_key = [0; 512];
+
+        assert!(matches!(
+            MqttCertificates::load(
+                &with_ca("ca"),
+                &storage,
+                &mut [0; 0x104],
+                &mut client_cert,
+                &mut client_key
+            ),
+            Err(MqttTlsError::BufferTooSmall)
+        ));
+    }
+
+    fn with_ca(name: &str) -> MqttConfiguration {
+        configuration("mqtts://broker", Some(name), None)
+    }
+}
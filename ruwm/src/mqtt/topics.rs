@@ -0,0 +1,154 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use super::{MqttConfiguration, MqttTopics};
+
+/// Resolves the `MqttTopics` template of a configuration into concrete topics,
+/// and routes received topics back to command names.
+#[derive(Clone, Debug)]
+pub struct TopicLayout<'a> {
+    topics: &'a MqttTopics,
+    device_id: &'a str,
+}
+
+impl<'a> TopicLayout<'a> {
+    pub fn new(configuration: &'a MqttConfiguration) -> Self {
+        let device_id = configuration.topics.device_id.trim_matches('/');
+
+        Self {
+            topics: &configuration.topics,
+            device_id: if device_id.is_empty() {
+                &configuration.client_id
+            } else {
+                device_id
+            },
+        }
+    }
+
+    pub fn topics(&self) -> &'a MqttTopics {
+        self.topics
+    }
+
+    pub fn topic<const L: usize>(
+        &self,
+        entity: &str,
+        attribute: Option<&str>,
+    ) -> Option<String<L>> {
+        let mut topic = self.base::<L>()?;
+
+        write!(&mut topic, "/{}", entity).ok()?;
+
+        if let Some(attribute) = attribute {
+            write!(&mut topic, "/{}", attribute).ok()?;
+        }
+
+        Some(topic)
+    }
+
+    pub fn commands_filter<const L: usize>(&self) -> Option<String<L>> {
+        self.topic(self.topics.commands(), Some("#"))
+    }
+
+    /// Returns the command name if - and only if - `topic` is exactly
+    /// `[<prefix>/]<device id>/<commands>/<command>`
+    pub fn command<'t>(&self, topic: &'t str) -> Option<&'t str> {
+        let prefix = self.topics.prefix();
+
+        let topic = if prefix.is_empty() {
+            topic
+        } else {
+            topic.strip_prefix(prefix)?.strip_prefix('/')?
+        };
+
+        let command = topic
+            .strip_prefix(self.device_id)?
+            .strip_prefix('/')?
+            .strip_prefix(self.topics.commands())?
+            .strip_prefix('/')?;
+
+        (!command.is_empty() && !command.contains(['/', '+', '#'])).then_some(command)
+    }
+
+    fn base<const L: usize>(&self) -> Option<String<L>> {
+        let mut topic = String::new();
+
+        let prefix = self.topics.prefix();
+
+        if !prefix.is_empty() {
+            write!(&mut topic, "{}/", prefix).ok()?;
+        }
+
+        topic.push_str(self.device_id).ok()?;
+
+        Some(topic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_topics(prefix: &str, device_id: &str) -> MqttConfiguration {
+        let mut configuration = MqttConfiguration::new();
+
+        configuration.client_id.push_str("ruwm").unwrap();
+        configuration.topics.prefix.push_str(prefix).unwrap();
+        configuration.topics.device_id.push_str(device_id).unwrap();
+
+        configuration
+    }
+
+    #[test]
+    fn device_id_defaults_to_the_client_id() {
+        let configuration = with_topics("", "");
+        let layout = TopicLayout::new(&configuration);
+
+        assert_eq!(
+            layout.topic::<64>("valve", None).as_deref(),
+            Some("ruwm/valve")
+        );
+        assert_eq!(
+            layout.commands_filter::<64>().as_deref(),
+            Some("ruwm/commands/#")
+        );
+
+        let configuration = with_topics("/home/", "/cellar/");
+        let layout = TopicLayout::new(&configuration);
+
+        assert_eq!(
+            layout.topic::<64>("meter", Some("edges_count")).as_deref(),
+            Some("home/cellar/meter/edges_count")
+        );
+    }
+
+    #[test]
+    fn only_command_topics_are_routed() {
+        let configuration = with_topics("home", "cellar");
+        let layout = TopicLayout::new(&configuration);
+
+        assert_eq!(layout.command("home/cellar/commands/valve"), Some("valve"));
+
+        for topic in [
+            "cellar/commands/valve",
+            "home/ruwm/commands/valve",
+            "home/cellar/commands",
+            "home/cellar/commands/",
+            "home/cellar/commands/valve/open",
+            "home/cellar/commands/#",
+            "home/cellar/valve",
+            "homecellar/commands/valve",
+        ] {
+            assert_eq!(layout.command(topic), None, "{topic}");
+        }
+    }
+
+    #[test]
+    fn topics_too_long_are_not_built() {
+        let configuration = with_topics("home", "cellar");
+        let layout = TopicLayout::new(&configuration);
+
+        assert_eq!(layout.topic::<8>("valve", None), None);
+        assert_eq!(layout.commands_filter::<8>(), None);
+    }
+}
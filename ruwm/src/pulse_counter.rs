@@ -4,9 +4,11 @@ use core::fmt::Debug;
 use embedded_hal::digital::{ErrorType, InputPin};
 use embedded_hal_async::digital::Wait;
 
-use embassy_time::Duration;
+use embassy_time::{Duration, Instant, Timer};
 
 use crate::button::{self, PressedLevel};
+use crate::command::CommandSource;
+use crate::wm::{self, PulseTraceEntry, PulseTraceEvent, WaterMeterCommand};
 
 pub trait PulseCounter {
     type Error: Debug;
@@ -86,6 +88,55 @@ where
     }
 }
 
+/// Feeds a recorded pulse trace back through the water meter, keeping its timing:
+/// the edges are returned as pulses, while arming and disarming are issued as water meter commands.
+///
+/// The first entry is replayed as soon as pulses are first taken. Once the trace is exhausted, no more pulses arrive.
+pub struct ReplayPulseCounter<I> {
+    entries: I,
+    start: Option<(Instant, u32)>,
+}
+
+impl<I> ReplayPulseCounter<I>
+where
+    I: Iterator<Item = PulseTraceEntry>,
+{
+    pub fn new(entries: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            entries: entries.into_iter(),
+            start: None,
+        }
+    }
+}
+
+impl<I> PulseCounter for ReplayPulseCounter<I>
+where
+    I: Iterator<Item = PulseTraceEntry>,
+{
+    type Error = Infallible;
+
+    async fn take_pulses(&mut self) -> Result<u64, Self::Error> {
+        for entry in self.entries.by_ref() {
+            let (start, start_ms) = *self.start.get_or_insert((Instant::now(), entry.time_ms));
+
+            Timer::at(start + Duration::from_millis(entry.time_ms.wrapping_sub(start_ms) as _))
+                .await;
+
+            match entry.event {
+                PulseTraceEvent::Edges(edges) => return Ok(edges as _),
+                PulseTraceEvent::Armed => {
+                    wm::command(WaterMeterCommand::Arm, CommandSource::Replay)
+                }
+                PulseTraceEvent::Disarmed => {
+                    wm::command(WaterMeterCommand::Disarm, CommandSource::Replay)
+                }
+            }
+        }
+
+        core::future::pending().await
+    }
+}
+
 impl PulseWakeup for () {
     type Error = Infallible;
 
@@ -1,5 +1,6 @@
 use core::cell::RefCell;
 use core::fmt::Debug;
+use core::pin::pin;
 
 use serde::{Deserialize, Serialize};
 
@@ -7,7 +8,7 @@ use log::trace;
 
 use enumset::{enum_set, EnumSet, EnumSetType};
 
-use embassy_futures::select::select_slice;
+use embassy_futures::select::{select, select4, select_slice, Either, Either4};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 
@@ -15,7 +16,9 @@ use gfx_xtra::draw_target::Flushable;
 
 use channel_bridge::notification::Notification;
 
+use crate::audit::{self, AuditLog, AUDIT_LOG_SIZE};
 use crate::battery::{self, BatteryState};
+use crate::emergency::{self, LockoutState};
 use crate::keepalive::{self, RemainingTime};
 use crate::screen::shapes::util::clear;
 use crate::valve::{self, ValveState};
@@ -23,16 +26,17 @@ use crate::wm::{self, WaterMeterState};
 
 pub use shapes::Color;
 
-use self::pages::{Battery, Summary};
+use self::pages::{Battery, Events, Summary};
 use self::shapes::Action;
 
-mod pages;
-mod shapes;
+pub mod pages;
+pub mod shapes;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 enum Page {
     Summary = 0,
     Battery = 1,
+    Events = 2,
 }
 
 impl Page {
@@ -42,22 +46,24 @@ impl Page {
 
     pub fn prev(&self) -> Self {
         match self {
-            Self::Summary => Self::Battery,
+            Self::Summary => Self::Events,
             Self::Battery => Self::Summary,
+            Self::Events => Self::Battery,
         }
     }
 
     pub fn next(&self) -> Self {
         match self {
             Self::Summary => Self::Battery,
-            Self::Battery => Self::Summary,
+            Self::Battery => Self::Events,
+            Self::Events => Self::Summary,
         }
     }
 
     pub fn actions(&self) -> EnumSet<Action> {
         let actions = match self {
             Self::Summary => Action::OpenValve | Action::CloseValve | Action::Arm | Action::Disarm,
-            Self::Battery => EnumSet::empty(),
+            Self::Battery | Self::Events => EnumSet::empty(),
         };
 
         let mut actions = actions.intersection(Action::active());
@@ -84,6 +90,8 @@ pub enum DataSource {
     WMStats,
     Battery,
     RemainingTime,
+    Events,
+    Lockout,
 }
 
 #[derive(Default, Clone, Debug, Eq, PartialEq)]
@@ -103,6 +111,8 @@ impl ScreenState {
                     | DataSource::WMStats
                     | DataSource::Battery
                     | DataSource::RemainingTime
+                    | DataSource::Events
+                    | DataSource::Lockout
             ),
             active_page: Page::new(),
             page_actions: None,
@@ -129,6 +139,16 @@ impl ScreenState {
             .then(|| keepalive::STATE.get())
     }
 
+    pub fn events(&self) -> Option<AuditLog<AUDIT_LOG_SIZE>> {
+        self.changed([DataSource::Events, DataSource::Page])
+            .then(audit::get)
+    }
+
+    pub fn lockout(&self) -> Option<Option<LockoutState>> {
+        self.changed([DataSource::Lockout, DataSource::Page])
+            .then(|| emergency::STATE.get())
+    }
+
     fn changed<const N: usize>(&self, changes: [DataSource; N]) -> bool {
         changes
             .iter()
@@ -139,91 +159,127 @@ impl ScreenState {
 pub(crate) static BUTTON1_PRESSED_NOTIF: Notification = Notification::new();
 pub(crate) static BUTTON2_PRESSED_NOTIF: Notification = Notification::new();
 pub(crate) static BUTTON3_PRESSED_NOTIF: Notification = Notification::new();
-pub(crate) static VALVE_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WM_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WM_STATS_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static BATTERY_STATE_NOTIF: Notification = Notification::new();
 pub(crate) static MQTT_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WIFI_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static REMAINING_TIME_NOTIF: Notification = Notification::new();
+pub(crate) static AUDIT_LOG_NOTIF: Notification = Notification::new();
 
 static DRAW_REQUEST_NOTIF: Notification = Notification::new();
 
 static STATE: Mutex<CriticalSectionRawMutex, RefCell<ScreenState>> =
     Mutex::new(RefCell::new(ScreenState::new()));
 
+pub(crate) fn reset() {
+    STATE.lock(|state| *state.borrow_mut() = ScreenState::new());
+
+    crate::state::clear(&[
+        &BUTTON1_PRESSED_NOTIF,
+        &BUTTON2_PRESSED_NOTIF,
+        &BUTTON3_PRESSED_NOTIF,
+        &MQTT_STATE_NOTIF,
+        &AUDIT_LOG_NOTIF,
+        &DRAW_REQUEST_NOTIF,
+    ]);
+}
+
 pub async fn process() {
     let mut notifs = [
         BUTTON1_PRESSED_NOTIF.wait(),
         BUTTON2_PRESSED_NOTIF.wait(),
         BUTTON3_PRESSED_NOTIF.wait(),
-        VALVE_STATE_NOTIF.wait(),
-        WM_STATE_NOTIF.wait(),
-        BATTERY_STATE_NOTIF.wait(),
-        REMAINING_TIME_NOTIF.wait(),
+        AUDIT_LOG_NOTIF.wait(),
     ];
 
+    let mut valve_revision = valve::STATE.revision();
+    let mut wm_revision = wm::STATE.revision();
+    let mut battery_revision = battery::STATE.revision();
+    let mut remaining_time_revision = keepalive::STATE.revision();
+    let mut lockout_revision = emergency::STATE.revision();
+
     loop {
-        let (_, index) = select_slice(&mut notifs).await;
-
-        {
-            STATE.lock(|screen_state| {
-                let mut screen_state = screen_state.borrow_mut();
-
-                match index {
-                    0 => {
-                        if let Some((actions, action)) = screen_state.page_actions {
-                            screen_state.page_actions =
-                                action.prev(&actions).map(|action| (actions, action));
-                        } else {
-                            screen_state.active_page = screen_state.active_page.prev();
-                        }
-
-                        screen_state.changeset.insert(DataSource::Page);
-                    }
-                    1 => {
-                        if let Some((actions, action)) = screen_state.page_actions {
-                            screen_state.page_actions =
-                                action.next(&actions).map(|action| (actions, action));
-                        } else {
-                            screen_state.active_page = screen_state.active_page.next();
-                        }
-
-                        screen_state.changeset.insert(DataSource::Page);
-                    }
-                    2 => {
-                        if let Some((_, action)) = screen_state.page_actions {
-                            screen_state.page_actions = None;
-                            action.trigger();
-                        } else {
-                            let actions = screen_state.active_page.actions();
-                            screen_state.page_actions =
-                                Action::first(&actions).map(|action| (actions, action));
-                        }
-
-                        screen_state.changeset.insert(DataSource::Page);
-                    }
-                    3 => {
-                        screen_state.changeset.insert(DataSource::Valve);
-                    }
-                    4 => {
-                        screen_state.changeset.insert(DataSource::WM);
-                    }
-                    5 => {
-                        screen_state.changeset.insert(DataSource::Battery);
-                    }
-                    6 => {
-                        screen_state.changeset.insert(DataSource::RemainingTime);
-                    }
-                    _ => unreachable!(),
-                }
-            });
-        }
+        // Pinned in place, as the futures of all the sources are too large to be moved around
+        let changed = pin!(select(
+            select_slice(&mut notifs),
+            select4(
+                valve::STATE.wait_changed(valve_revision),
+                wm::STATE.wait_changed(wm_revision),
+                battery::STATE.wait_changed(battery_revision),
+                select(
+                    keepalive::STATE.wait_changed(remaining_time_revision),
+                    emergency::STATE.wait_changed(lockout_revision),
+                ),
+            ),
+        ));
+
+        let data_source = match changed.await {
+            Either::First((_, 3)) => DataSource::Events,
+            Either::First((_, button)) => {
+                STATE.lock(|screen_state| press(&mut screen_state.borrow_mut(), button));
+
+                DataSource::Page
+            }
+            Either::Second(Either4::First(update)) => {
+                valve_revision = update.revision;
+
+                DataSource::Valve
+            }
+            Either::Second(Either4::Second(update)) => {
+                wm_revision = update.revision;
+
+                DataSource::WM
+            }
+            Either::Second(Either4::Third(update)) => {
+                battery_revision = update.revision;
+
+                DataSource::Battery
+            }
+            Either::Second(Either4::Fourth(Either::First(update))) => {
+                remaining_time_revision = update.revision;
+
+                DataSource::RemainingTime
+            }
+            Either::Second(Either4::Fourth(Either::Second(update))) => {
+                lockout_revision = update.revision;
+
+                DataSource::Lockout
+            }
+        };
+
+        STATE.lock(|screen_state| {
+            screen_state.borrow_mut().changeset.insert(data_source);
+        });
 
         DRAW_REQUEST_NOTIF.notify();
     }
 }
 
+fn press(screen_state: &mut ScreenState, button: usize) {
+    match button {
+        0 => {
+            if let Some((actions, action)) = screen_state.page_actions {
+                screen_state.page_actions = action.prev(&actions).map(|action| (actions, action));
+            } else {
+                screen_state.active_page = screen_state.active_page.prev();
+            }
+        }
+        1 => {
+            if let Some((actions, action)) = screen_state.page_actions {
+                screen_state.page_actions = action.next(&actions).map(|action| (actions, action));
+            } else {
+                screen_state.active_page = screen_state.active_page.next();
+            }
+        }
+        2 => {
+            if let Some((_, action)) = screen_state.page_actions {
+                screen_state.page_actions = None;
+                action.trigger();
+            } else {
+                let actions = screen_state.active_page.actions();
+                screen_state.page_actions = Action::first(&actions).map(|action| (actions, action));
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
 // pub async fn unblock_run_draw<U, D>(unblocker: U, mut display: D)
 // where
 //     U: Unblocker,
@@ -297,8 +353,10 @@ where
             screen_state.wm().as_ref(),
             screen_state.battery().as_ref(),
             screen_state.remaining_time().as_ref(),
+            screen_state.lockout().as_ref(),
         )?,
         Page::Battery => Battery::draw(display, page_changed, screen_state.battery().as_ref())?,
+        Page::Events => Events::draw(display, page_changed, screen_state.events().as_ref())?,
     }
 
     if let Some((actions, action)) = screen_state.page_actions {
@@ -4,12 +4,14 @@ use embedded_graphics::{
     prelude::{DrawTarget, DrawTargetExt, Size},
     primitives::Rectangle,
 };
+pub use events::*;
 pub use summary::*;
 
 use super::{shapes::Textbox, Color};
 
 pub mod actions;
 mod battery;
+mod events;
 mod summary;
 
 pub fn with_title<'a, T>(
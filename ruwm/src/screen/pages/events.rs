@@ -0,0 +1,79 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::prelude::{Dimensions, Point, Size};
+
+use crate::audit::{AuditLog, AUDIT_LOG_SIZE};
+use crate::screen::shapes::util::{clear, text};
+use crate::screen::shapes::Color;
+
+use super::with_title;
+
+pub struct Events;
+
+impl Events {
+    /// Draws the most recent audit events, newest first.
+    /// Events recorded before the current boot show no time, as it would be meaningless.
+    pub fn draw<T>(
+        target: &mut T,
+        page_changed: bool,
+        log: Option<&AuditLog<AUDIT_LOG_SIZE>>,
+    ) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Color = Color>,
+    {
+        let mut target = with_title(target, page_changed, "Events")?;
+
+        if let Some(log) = log {
+            let bbox = target.bounding_box();
+
+            clear(&bbox, &mut target)?;
+
+            let Size { width, height } = bbox.size;
+
+            let font = if width <= 128 {
+                profont::PROFONT_7_POINT
+            } else {
+                profont::PROFONT_12_POINT
+            };
+
+            let lines = (height / font.character_size.height) as usize;
+            let max_chars = (width / font.character_size.width) as usize;
+
+            for (line, entry) in log.iter().rev().take(lines).enumerate() {
+                let mut entry_text = String::<64>::new();
+
+                if entry.boot == log.boot() {
+                    let minutes = entry.time_secs / 60;
+
+                    write!(
+                        &mut entry_text,
+                        "{:02}:{:02} {}",
+                        minutes / 60 % 100,
+                        minutes % 60,
+                        entry.event
+                    )
+                } else {
+                    write!(&mut entry_text, "--:-- {}", entry.event)
+                }
+                .ok();
+
+                let entry_text = &entry_text[..entry_text.len().min(max_chars)];
+
+                text(
+                    &font,
+                    &mut target,
+                    bbox.top_left
+                        + Point::new(0, (font.character_size.height * line as u32) as i32),
+                    entry_text,
+                    Color::White,
+                    None,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use embedded_graphics::{
 use gfx_xtra::draw_target::{DrawTargetExt2, RotateAngle};
 
 use crate::battery::BatteryState;
+use crate::emergency::LockoutState;
 use crate::keepalive::RemainingTime;
 use crate::screen::shapes::{self, BatteryChargedText, Color};
 use crate::valve::ValveState;
@@ -24,6 +25,7 @@ impl Summary {
         wm_state: Option<&WaterMeterState>,
         battery_state: Option<&BatteryState>,
         remaining_time_state: Option<&RemainingTime>,
+        lockout_state: Option<&Option<LockoutState>>,
     ) -> Result<(), D::Error>
     where
         D: DrawTarget<Color = Color>,
@@ -31,7 +33,8 @@ impl Summary {
         let bbox = target.bounding_box();
 
         let top_height = Self::draw_top_status_line(target, battery_state)?;
-        let bottom_height = Self::draw_bottom_status_line(target, remaining_time_state)?;
+        let bottom_height =
+            Self::draw_bottom_status_line(target, remaining_time_state, lockout_state)?;
 
         let content_rect = Rectangle::new(
             bbox.top_left + Size::new(0, top_height + 5),
@@ -211,6 +214,7 @@ impl Summary {
     fn draw_bottom_status_line<D>(
         target: &mut D,
         remaining_time: Option<&RemainingTime>,
+        lockout: Option<&Option<LockoutState>>,
     ) -> Result<u32, D::Error>
     where
         D: DrawTarget<Color = Color>,
@@ -254,6 +258,38 @@ impl Summary {
             )))?;
         }
 
+        if let Some(lockout) = lockout {
+            // Red while the emergency lasts, yellow once it only awaits acknowledgement
+            let status_lockout = shapes::Textbox {
+                text: if lockout.is_some() {
+                    "LOCKOUT"
+                } else {
+                    "       "
+                },
+                color: if lockout.is_some_and(|lockout| lockout.condition_present) {
+                    Color::Red
+                } else {
+                    Color::Yellow
+                },
+                font: status_font,
+                padding: 1,
+                outline: 0,
+                strikethrough: false,
+                ..Default::default()
+            };
+
+            let status_lockout_size = status_lockout.preferred_size();
+
+            status_lockout.draw(&mut target.cropped(&Rectangle::new(
+                bbox.top_left
+                    + Size::new(
+                        bbox.size.width - status_lockout_size.width,
+                        bbox.size.height - status_lockout_size.height,
+                    ),
+                status_lockout_size,
+            )))?;
+        }
+
         Ok(status_height)
     }
 }
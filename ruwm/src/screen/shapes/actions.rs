@@ -7,8 +7,9 @@ use embedded_graphics::primitives::Rectangle;
 use enumset::{EnumSet, EnumSetType};
 use valve::{ValveCommand, ValveState};
 
+use crate::command::CommandSource;
 use crate::dto::water_meter::WaterMeterCommand;
-use crate::{valve, wm};
+use crate::{emergency, valve, wm};
 
 use super::util::{clear_cropped, fill, text};
 use super::Color;
@@ -90,7 +91,8 @@ impl Action {
         if !matches!(
             valve_state,
             Some(ValveState::Open) | Some(ValveState::Opening(_))
-        ) {
+        ) && !emergency::is_lockout()
+        {
             actions |= Action::OpenValve;
         }
 
@@ -114,10 +116,10 @@ impl Action {
 
     pub fn trigger(&self) {
         match self {
-            Self::OpenValve => valve::COMMAND.signal(ValveCommand::Open),
-            Self::CloseValve => valve::COMMAND.signal(ValveCommand::Close),
-            Self::Arm => wm::COMMAND.signal(WaterMeterCommand::Arm),
-            Self::Disarm => wm::COMMAND.signal(WaterMeterCommand::Disarm),
+            Self::OpenValve => valve::command(ValveCommand::Open, CommandSource::Button),
+            Self::CloseValve => valve::command(ValveCommand::Close, CommandSource::Button),
+            Self::Arm => wm::command(WaterMeterCommand::Arm, CommandSource::Button),
+            Self::Disarm => wm::command(WaterMeterCommand::Disarm, CommandSource::Button),
             // Self::CheckForUpdate => "Check for Update",
             // Self::Update => "Update",
             // Self::Pair => "Pair",
@@ -5,6 +5,7 @@ use embedded_hal_async::digital::Wait;
 
 use embedded_svc::mqtt::client::asynch::{Client, Connection, Publish};
 use embedded_svc::wifi::asynch::Wifi;
+use embedded_svc::wifi::Configuration;
 use embedded_svc::ws::asynch::server::Acceptor;
 
 use gfx_xtra::draw_target::Flushable;
@@ -16,13 +17,17 @@ use channel_bridge::asynch::*;
 use valve::ValveState;
 use wm_stats::WaterMeterStatsState;
 
+use crate::audit::{AuditLog, AUDIT_LOG_SIZE};
 use crate::battery::Adc;
 use crate::button::{self, PressedLevel};
+use crate::emergency::LockoutState;
+use crate::mqtt::{MqttConfiguration, MqttOutbox};
 use crate::pulse_counter::{PulseCounter, PulseWakeup};
 use crate::screen::Color;
+use crate::users::{Users, USERS_MAX};
 use crate::web::{self, WebEvent, WebRequest};
 use crate::wm::{self, WaterMeterState};
-use crate::{battery, emergency, keepalive, mqtt, screen, wm_stats, ws};
+use crate::{audit, battery, emergency, keepalive, mqtt, screen, users, wm_stats, ws};
 use crate::{valve, wifi};
 
 #[allow(clippy::too_many_arguments)]
@@ -32,6 +37,7 @@ pub fn high_prio<'a, const C: usize>(
     valve_open_pin: impl OutputPin<Error = impl Debug + 'a> + 'a,
     valve_close_pin: impl OutputPin<Error = impl Debug + 'a> + 'a,
     valve_persister: impl FnMut(Option<ValveState>) + 'a,
+    lockout_persister: impl FnMut(Option<LockoutState>) + 'a,
     pulse_counter: impl PulseCounter + 'a,
     pulse_wakeup: impl PulseWakeup + 'a,
     wm_persister: impl FnMut(WaterMeterState) + 'a,
@@ -75,6 +81,10 @@ pub fn high_prio<'a, const C: usize>(
 
     executor.spawn(emergency::process()).detach();
 
+    executor
+        .spawn(emergency::persist(lockout_persister))
+        .detach();
+
     executor.spawn(keepalive::process()).detach();
 
     // if roller {
@@ -129,25 +139,56 @@ fn low_prio_common<'a, const C: usize>(
     executor.spawn(wm::flash(wm_flash)).detach();
 }
 
-pub fn wifi<'a, const C: usize>(executor: &LocalExecutor<'a, C>, wifi: impl Wifi + 'a) {
+pub fn wifi<'a, const C: usize>(
+    executor: &LocalExecutor<'a, C>,
+    wifi: impl Wifi + 'a,
+    wifi_configuration_persister: impl FnMut(Option<Configuration>) + 'a,
+) {
     executor.spawn(wifi::process(wifi)).detach();
+
+    executor
+        .spawn(wifi::persist_configuration(wifi_configuration_persister))
+        .detach();
 }
 
-pub fn mqtt_send<'a, const L: usize, const C: usize>(
+pub fn mqtt<'a, const L: usize, const P: usize, const C: usize, F, MC, MN, E>(
     executor: &LocalExecutor<'a, C>,
-    mqtt_topic_prefix: &'a str,
-    mqtt_client: impl Client + Publish + 'a,
-) {
+    mqtt_factory: F,
+    mqtt_configuration_persister: impl FnMut(Option<MqttConfiguration>) + 'a,
+    mqtt_outbox_persister: impl FnMut(MqttOutbox) + 'a,
+) where
+    F: FnMut(&MqttConfiguration) -> Result<(MC, MN), E> + 'a,
+    MC: Client + Publish + 'a,
+    MN: Connection + 'a,
+    E: Debug + 'a,
+{
     executor
-        .spawn(mqtt::send::<L>(mqtt_topic_prefix, mqtt_client))
+        .spawn(mqtt::process::<L, P, _, _, _, _>(mqtt_factory))
+        .detach();
+
+    executor
+        .spawn(mqtt::persist_configuration(mqtt_configuration_persister))
+        .detach();
+
+    executor
+        .spawn(mqtt::persist(mqtt_outbox_persister))
         .detach();
 }
 
-pub fn mqtt_receive<'a, const C: usize>(
+pub fn users<'a, const C: usize>(
     executor: &LocalExecutor<'a, C>,
-    mqtt_conn: impl Connection + 'a,
+    users_persister: impl FnMut(Users<USERS_MAX>) + 'a,
 ) {
-    executor.spawn(mqtt::receive(mqtt_conn)).detach();
+    executor.spawn(users::persist(users_persister)).detach();
+}
+
+pub fn audit<'a, const C: usize>(
+    executor: &LocalExecutor<'a, C>,
+    audit_persister: impl FnMut(AuditLog<AUDIT_LOG_SIZE>) + 'a,
+) {
+    executor.spawn(audit::process()).detach();
+
+    executor.spawn(audit::persist(audit_persister)).detach();
 }
 
 pub fn web<'a, const C: usize, S, R>(executor: &LocalExecutor<'a, C>, sender: S, receiver: R)
@@ -0,0 +1,267 @@
+use core::marker::PhantomData;
+
+use log::info;
+
+use heapless::String;
+
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, RawMutex};
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Timer};
+
+use embedded_io_async::Write;
+
+use embedded_svc::http::server::asynch::{Connection, Request, Response};
+
+use channel_bridge::asynch::*;
+
+use crate::api;
+use crate::users;
+use crate::utils::select::EitherUnwrap;
+use crate::web::{self, SessionToken, UserRole, WebEvent, WebRequest};
+
+pub const EVENTS_PATH: &str = "/events";
+
+const EVENT_MAX_LEN: usize = 2048;
+pub const KEEPALIVE_PERIOD: Duration = Duration::from_secs(15);
+
+/// Streams the same events as the web UI receives over the WebSocket, as Server-Sent Events.
+///
+/// Each event is named after its `WebEvent` variant, and carries the state as JSON, e.g.
+/// `event: ValveState` / `data: "Open"`. The WiFi settings and the audit log are not streamed.
+///
+/// Clients authenticate with a session token, either as `Authorization: Bearer <token>`,
+/// or - as browsers cannot set headers for an `EventSource` - as a `token` query parameter.
+pub async fn handle<C>(request: Request<C>) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    let token = request
+        .header("Authorization")
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .and_then(|token| token.trim().parse::<SessionToken>().ok())
+        .or_else(|| query_token(request.uri()));
+
+    let Some((token, user_role)) =
+        token.and_then(|token| users::resume_session(&token).map(|(_, role)| (token, role)))
+    else {
+        return api::respond_denied(request, None).await;
+    };
+
+    info!("[SSE] Starting event stream; role: {:?}", user_role);
+
+    let response = request
+        .into_response(
+            200,
+            None,
+            &[
+                ("Content-Type", "text/event-stream"),
+                ("Cache-Control", "no-store"),
+            ],
+        )
+        .await?;
+
+    let response = Mutex::<NoopRawMutex, _>::new(response);
+
+    select(
+        web::handle(SseSender::new(&response, token), SseReceiver::new(token)),
+        keepalive(&response),
+    )
+    .await
+    .unwrap()
+}
+
+/// Periodic comments let both ends notice a dropped connection while no state changes
+async fn keepalive<C>(response: &Mutex<impl RawMutex, Response<C>>) -> Result<(), C::Error>
+where
+    C: Connection,
+{
+    loop {
+        Timer::after(KEEPALIVE_PERIOD).await;
+
+        let response = &mut *response.lock().await;
+
+        response.write_all(b": keepalive\n\n").await?;
+        response.flush().await?;
+    }
+}
+
+struct SseSender<'a, M, C>
+where
+    M: RawMutex,
+    C: Connection,
+{
+    response: &'a Mutex<M, Response<C>>,
+    token: SessionToken,
+}
+
+impl<'a, M, C> SseSender<'a, M, C>
+where
+    M: RawMutex,
+    C: Connection,
+{
+    const fn new(response: &'a Mutex<M, Response<C>>, token: SessionToken) -> Self {
+        Self { response, token }
+    }
+}
+
+impl<'a, M, C> Sender for SseSender<'a, M, C>
+where
+    M: RawMutex,
+    C: Connection,
+{
+    type Error = C::Error;
+
+    type Data = WebEvent;
+
+    async fn send(&mut self, data: Self::Data) -> Result<(), Self::Error> {
+        // Nothing more is streamed once the session is gone; `SseReceiver` ends the stream shortly after
+        if users::resume_session(&self.token).is_none() {
+            return Ok(());
+        }
+
+        let mut buf = [0; EVENT_MAX_LEN];
+
+        if let Some(len) = encode(&data, &mut buf) {
+            let response = &mut *self.response.lock().await;
+
+            response.write_all(&buf[..len]).await?;
+            response.flush().await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Resumes the session, as SSE clients cannot send requests, then re-checks it every `KEEPALIVE_PERIOD`.
+///
+/// The stream ends once the session is gone - due to a logout, an expiry, a password change or the removal
+/// of the account - and the session is resumed again when the role of the user changes.
+struct SseReceiver<E> {
+    token: SessionToken,
+    role: Option<UserRole>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<E> SseReceiver<E> {
+    const fn new(token: SessionToken) -> Self {
+        Self {
+            token,
+            role: None,
+            _error: PhantomData,
+        }
+    }
+}
+
+impl<E> Receiver for SseReceiver<E>
+where
+    E: core::fmt::Debug,
+{
+    type Error = E;
+
+    type Data = Option<WebRequest>;
+
+    async fn recv(&mut self) -> Result<Self::Data, Self::Error> {
+        loop {
+            if self.role.is_some() {
+                Timer::after(KEEPALIVE_PERIOD).await;
+            }
+
+            match users::resume_session(&self.token) {
+                Some((_, role)) if Some(role) == self.role => continue,
+                Some((_, role)) => {
+                    self.role = Some(role);
+
+                    break Ok(Some(WebRequest::Resume(self.token)));
+                }
+                None => {
+                    info!("[SSE] Session ended, closing the event stream");
+
+                    break Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Renders the event in the `text/event-stream` format; `None` for events which are not streamed
+fn encode(event: &WebEvent, buf: &mut [u8]) -> Option<usize> {
+    let name = match event {
+        WebEvent::NoPermissions => "NoPermissions",
+        WebEvent::AuthenticationFailed => "AuthenticationFailed",
+        WebEvent::SetupRequired => "SetupRequired",
+        WebEvent::RequestFailed => "RequestFailed",
+        WebEvent::RoleState(_, _) => "RoleState",
+        WebEvent::ValveState(_) => "ValveState",
+        WebEvent::ValveSourceState(_) => "ValveSourceState",
+        WebEvent::LockoutState(_) => "LockoutState",
+        WebEvent::WaterMeterState(_) => "WaterMeterState",
+        WebEvent::WaterMeterStatsState(_) => "WaterMeterStatsState",
+        WebEvent::BatteryState(_) => "BatteryState",
+        WebEvent::RemainingTimeState(_) => "RemainingTimeState",
+        WebEvent::MqttState(_) => "MqttState",
+        WebEvent::WifiState(_) => "WifiState",
+        WebEvent::WifiSettingsState(_) | WebEvent::AuditLog(_) => return None,
+    };
+
+    let mut len = 0;
+
+    for part in ["event: ", name, "\ndata: "] {
+        len += put(&mut buf[len..], part.as_bytes())?;
+    }
+
+    // The session token of `RoleState` is left out, as the client already has it
+    len += match event {
+        WebEvent::RoleState(role, _) => serde_json_core::to_slice(role, &mut buf[len..]),
+        WebEvent::ValveState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::ValveSourceState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::LockoutState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::WaterMeterState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::WaterMeterStatsState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::BatteryState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::RemainingTimeState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::MqttState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        WebEvent::WifiState(state) => serde_json_core::to_slice(state, &mut buf[len..]),
+        _ => serde_json_core::to_slice(&(), &mut buf[len..]),
+    }
+    .ok()?;
+
+    len += put(&mut buf[len..], b"\n\n")?;
+
+    Some(len)
+}
+
+fn put(buf: &mut [u8], data: &[u8]) -> Option<usize> {
+    buf.get_mut(..data.len())?.copy_from_slice(data);
+
+    Some(data.len())
+}
+
+/// Extracts the `token` query parameter; the colons of the token may be percent-encoded
+fn query_token(uri: &str) -> Option<SessionToken> {
+    let (_, query) = uri.split_once('?')?;
+
+    let value = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("token="))?;
+
+    let mut token = String::<128>::new();
+
+    let mut rest = value;
+    while !rest.is_empty() {
+        if let Some(after) = rest
+            .strip_prefix("%3A")
+            .or_else(|| rest.strip_prefix("%3a"))
+        {
+            token.push(':').ok()?;
+            rest = after;
+        } else {
+            let c = rest.chars().next()?;
+
+            token.push(c).ok()?;
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    token.parse().ok()
+}
@@ -1,28 +1,56 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::fmt::Debug;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::{Context, Poll};
 
 use log::info;
 
+use futures::task::noop_waker_ref;
+
+use heapless::Vec;
+
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_time::Instant;
 
 use channel_bridge::notification::Notification;
 
+/// The maximum number of runtime subscribers of a single state: the water meter state - the most
+/// observed one - has four within the firmware, which leaves two for plugins and tests
+pub const SUBSCRIBERS_MAX: usize = 6;
+
+/// The maximum number of tasks waiting in `State::wait_changed` on a single state at the same time:
+/// one per web connection and one for the global web channel, plus the MQTT publisher, the screen
+/// and a spare. Any more and all waiters get woken up spuriously whenever another one registers.
+pub const WAITERS_MAX: usize = crate::ws::WS_MAX_CONNECTIONS + 4;
+
 pub struct State<'a, T> {
     name: &'a str,
     state: Mutex<CriticalSectionRawMutex, RefCell<T>>,
+    revision: Mutex<CriticalSectionRawMutex, Cell<u32>>,
+    changed: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>>,
+    waiters: Mutex<CriticalSectionRawMutex, RefCell<MultiWakerRegistration<WAITERS_MAX>>>,
     notifications: &'a [&'a Notification],
+    subscribers: Mutex<CriticalSectionRawMutex, RefCell<Vec<&'a Subscriber<T>, SUBSCRIBERS_MAX>>>,
 }
 
 impl<'a, T> State<'a, T>
 where
     T: Clone,
 {
+    /// `notifications` are for the module owning the state, e.g. to persist it;
+    /// other modules observe the state with `subscribe` or `wait_changed` instead.
     pub const fn new(name: &'a str, data: T, notifications: &'a [&'a Notification]) -> Self {
         Self {
             name,
             state: Mutex::new(RefCell::new(data)),
+            revision: Mutex::new(Cell::new(0)),
+            changed: Mutex::new(Cell::new(None)),
+            waiters: Mutex::new(RefCell::new(MultiWakerRegistration::new())),
             notifications,
+            subscribers: Mutex::new(RefCell::new(Vec::new())),
         }
     }
 
@@ -30,40 +58,115 @@ where
         self.state.lock(|state| state.borrow().clone())
     }
 
-    pub fn set(&self, data: T) -> (T, T) {
-        self.state.lock(|state| {
-            let old = state.borrow().clone();
+    /// The number of changes made by `update_with` or `set` so far
+    pub fn revision(&self) -> u32 {
+        self.revision.lock(Cell::get)
+    }
 
-            *state.borrow_mut() = data.clone();
+    /// When `update_with` last published a change, if ever
+    pub fn changed_at(&self) -> Option<Instant> {
+        self.changed.lock(Cell::get)
+    }
+
+    /// The current value, together with its revision
+    pub fn get_revision(&self) -> (u32, T) {
+        self.state
+            .lock(|state| (self.revision(), state.borrow().clone()))
+    }
+
+    /// Waits until the state has changed since revision `since`, and returns its current value.
+    ///
+    /// Returns immediately if it already has, so a consumer which keeps the revision of what it last
+    /// sent - e.g. across a reconnection - catches up with a single call. Several intermediate changes
+    /// are coalesced into the returned one, and are reported as `Update::missed`.
+    pub async fn wait_changed(&self, since: u32) -> Update<T> {
+        poll_fn(|cx| {
+            let (revision, value) = self.get_revision();
 
-            (old, data)
+            if revision != since {
+                Poll::Ready(Update {
+                    revision,
+                    value,
+                    missed: revision.wrapping_sub(since).wrapping_sub(1),
+                })
+            } else {
+                self.waiters
+                    .lock(|waiters| waiters.borrow_mut().register(cx.waker()));
+
+                Poll::Pending
+            }
         })
+        .await
     }
 
+    /// Sets the state without notifying anyone, e.g. when restoring it at boot.
+    ///
+    /// The revision is still bumped, so that consumers of `wait_changed` - which might have taken
+    /// the revision before the state was restored - do not miss the value.
+    pub fn set(&self, data: T) -> (T, T) {
+        self.set_update(move |_| data)
+    }
+
+    /// Like `set`, but with the new value computed from the old one
     pub fn set_update(&self, updater: impl FnOnce(T) -> T) -> (T, T) {
-        self.state.lock(|state| {
+        let values = self.state.lock(|state| {
             let old = state.borrow().clone();
             let new = updater(old.clone());
 
             *state.borrow_mut() = new.clone();
 
+            self.revision
+                .lock(|revision| revision.set(revision.get().wrapping_add(1)));
+
             (old, new)
-        })
+        });
+
+        self.waiters.lock(|waiters| waiters.borrow_mut().wake());
+
+        values
     }
 
     pub fn update_with(&self, updater: impl FnOnce(T) -> T) -> bool
     where
         T: PartialEq + Debug,
     {
-        let (old, new) = self.set_update(updater);
+        // The revision is bumped under the same lock as the value, so that `get_revision` is consistent
+        let changed = self.state.lock(|state| {
+            let old = state.borrow().clone();
+            let new = updater(old.clone());
+
+            if old != new {
+                *state.borrow_mut() = new.clone();
+
+                let revision = self.revision.lock(|revision| {
+                    revision.set(revision.get().wrapping_add(1));
+                    revision.get()
+                });
+
+                self.changed
+                    .lock(|changed| changed.set(Some(Instant::now())));
+
+                Some((revision, new))
+            } else {
+                None
+            }
+        });
 
-        if old != new {
-            info!("[{} STATE]: {:?}", self.name, new);
+        if let Some((revision, new)) = changed {
+            info!("[{} STATE] #{}: {:?}", self.name, revision, new);
 
             for notification in self.notifications {
                 notification.notify();
             }
 
+            self.subscribers.lock(|subscribers| {
+                for subscriber in subscribers.borrow().iter() {
+                    subscriber.publish(revision, new.clone());
+                }
+            });
+
+            self.waiters.lock(|waiters| waiters.borrow_mut().wake());
+
             true
         } else {
             false
@@ -76,4 +179,235 @@ where
     {
         self.update_with(move |_| data)
     }
+
+    /// Registers a subscriber, which receives every subsequent change of the state.
+    /// Returns `false` if the subscriber is already registered, or if `SUBSCRIBERS_MAX` subscribers are.
+    pub fn subscribe(&self, subscriber: &'a Subscriber<T>) -> bool {
+        self.subscribers.lock(|subscribers| {
+            let mut subscribers = subscribers.borrow_mut();
+
+            if subscribers
+                .iter()
+                .any(|registered| core::ptr::eq(*registered, subscriber))
+            {
+                return false;
+            }
+
+            subscriber.reset(self.revision());
+
+            subscribers.push(subscriber).is_ok()
+        })
+    }
+
+    /// Returns `false` if the subscriber was not registered.
+    pub fn unsubscribe(&self, subscriber: &Subscriber<T>) -> bool {
+        self.subscribers.lock(|subscribers| {
+            let mut subscribers = subscribers.borrow_mut();
+
+            if let Some(index) = subscribers
+                .iter()
+                .position(|registered| core::ptr::eq(*registered, subscriber))
+            {
+                subscribers.swap_remove(index);
+
+                true
+            } else {
+                false
+            }
+        })
+    }
+}
+
+/// A change of a state, as received by a subscriber
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Update<T> {
+    pub revision: u32,
+    pub value: T,
+    /// How many changes were overwritten by this one before the subscriber received them
+    pub missed: u32,
+}
+
+/// A statically allocated subscription slot, to be registered with `State::subscribe`.
+///
+/// Only the most recent change is kept; changes which happen before the subscriber gets
+/// to receive the previous one are coalesced, and are reported as `Update::missed`.
+pub struct Subscriber<T> {
+    pending: Mutex<CriticalSectionRawMutex, RefCell<Option<(u32, T)>>>,
+    received: Mutex<CriticalSectionRawMutex, Cell<u32>>,
+    notification: Notification,
+}
+
+impl<T> Subscriber<T> {
+    pub const fn new() -> Self {
+        Self {
+            pending: Mutex::new(RefCell::new(None)),
+            received: Mutex::new(Cell::new(0)),
+            notification: Notification::new(),
+        }
+    }
+
+    /// Waits for the next change of the state
+    pub async fn next(&self) -> Update<T> {
+        loop {
+            if let Some(update) = self.try_next() {
+                break update;
+            }
+
+            self.notification.wait().await;
+        }
+    }
+
+    /// Notified whenever a change is published to the subscriber.
+    ///
+    /// Lets a consumer wait on the subscribers of states of different types at once, e.g. with `select_slice`,
+    /// and then take the changes with `try_next`.
+    pub fn notification(&self) -> &Notification {
+        &self.notification
+    }
+
+    /// The change received since the last call, if any
+    pub fn try_next(&self) -> Option<Update<T>> {
+        let (revision, value) = self.pending.lock(|pending| pending.borrow_mut().take())?;
+
+        let received = self.received.lock(|received| received.replace(revision));
+
+        Some(Update {
+            revision,
+            value,
+            missed: revision.wrapping_sub(received).wrapping_sub(1),
+        })
+    }
+
+    fn publish(&self, revision: u32, value: T) {
+        self.pending
+            .lock(|pending| *pending.borrow_mut() = Some((revision, value)));
+
+        self.notification.notify();
+    }
+
+    fn reset(&self, revision: u32) {
+        self.pending.lock(|pending| *pending.borrow_mut() = None);
+        self.received.lock(|received| received.set(revision));
+
+        clear(&[&self.notification]);
+    }
+}
+
+impl<T> Default for Subscriber<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drops the pending notifications, e.g. those left behind by tasks which are gone
+pub(crate) fn clear(notifications: &[&Notification]) {
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    for notification in notifications {
+        let _ = pin!(notification.wait()).poll(&mut cx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscriber_receives_changes() {
+        let subscriber = Subscriber::new();
+        let state = State::new("TEST", 0_u32, &[]);
+
+        assert!(state.subscribe(&subscriber));
+        assert_eq!(subscriber.try_next(), None);
+
+        state.update(1);
+
+        assert_eq!(
+            subscriber.try_next(),
+            Some(Update {
+                revision: 1,
+                value: 1,
+                missed: 0
+            })
+        );
+        assert_eq!(subscriber.try_next(), None);
+
+        // Neither unchanged values, nor restored ones are published
+        state.update(1);
+        state.set(2);
+
+        assert_eq!(subscriber.try_next(), None);
+    }
+
+    #[test]
+    fn subscriber_reports_missed_changes() {
+        let subscriber = Subscriber::new();
+        let state = State::new("TEST", 0_u32, &[]);
+
+        state.update(1);
+
+        assert!(state.subscribe(&subscriber));
+
+        state.update(2);
+        state.update(3);
+        state.update(4);
+
+        assert_eq!(
+            subscriber.try_next(),
+            Some(Update {
+                revision: 4,
+                value: 4,
+                missed: 2
+            })
+        );
+
+        state.update(5);
+
+        assert_eq!(subscriber.try_next().map(|update| update.missed), Some(0));
+    }
+
+    #[test]
+    fn subscriber_waits_for_next_change() {
+        let subscriber = Subscriber::new();
+        let state = State::new("TEST", 0_u32, &[]);
+
+        assert!(state.subscribe(&subscriber));
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut next = pin!(subscriber.next());
+
+        assert!(next.as_mut().poll(&mut cx).is_pending());
+
+        state.update(1);
+
+        assert_eq!(
+            next.as_mut().poll(&mut cx).map(|update| update.value),
+            Poll::Ready(1)
+        );
+    }
+
+    #[test]
+    fn subscribers_are_bounded_and_unique() {
+        let subscribers: [Subscriber<u32>; SUBSCRIBERS_MAX + 1] =
+            core::array::from_fn(|_| Subscriber::new());
+        let state = State::new("TEST", 0_u32, &[]);
+
+        for subscriber in &subscribers[..SUBSCRIBERS_MAX] {
+            assert!(state.subscribe(subscriber));
+        }
+
+        assert!(!state.subscribe(&subscribers[0]));
+        assert!(!state.subscribe(&subscribers[SUBSCRIBERS_MAX]));
+
+        assert!(state.unsubscribe(&subscribers[0]));
+        assert!(!state.unsubscribe(&subscribers[0]));
+        assert!(state.subscribe(&subscribers[SUBSCRIBERS_MAX]));
+
+        state.update(1);
+
+        assert_eq!(subscribers[0].try_next(), None);
+        assert!(subscribers[1..]
+            .iter()
+            .all(|subscriber| subscriber.try_next().is_some()));
+    }
 }
@@ -0,0 +1,455 @@
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Debug};
+use core::hint::black_box;
+
+use serde::{Deserialize, Serialize};
+
+use heapless::{String, Vec};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use hmac_sha256::{Hash, HMAC};
+
+use channel_bridge::notification::Notification;
+
+use crate::dto::web::{UserRole, PASSWORD_MAX_LEN, USERNAME_MAX_LEN};
+use crate::state::{self, State};
+
+pub use session::*;
+
+mod session;
+
+pub const USERS_MAX: usize = 8;
+
+pub const MAX_FAILED_ATTEMPTS: u8 = 5;
+pub const LOCKOUT_DURATION: Duration = Duration::from_secs(60);
+
+/// Failed attempts are tracked for this many usernames at most, as usernames which do not exist are tracked too
+const LOCKOUTS_MAX: usize = USERS_MAX * 2;
+
+const HASH_ITERATIONS: u32 = 1000;
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserAccount {
+    pub username: String<USERNAME_MAX_LEN>,
+    pub role: UserRole,
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl UserAccount {
+    pub fn new(username: &str, password: &str, role: UserRole) -> Option<Self> {
+        if username.is_empty() || password.is_empty() || password.len() > PASSWORD_MAX_LEN {
+            return None;
+        }
+
+        let salt = salt(username);
+
+        Some(Self {
+            username: username.try_into().ok()?,
+            role,
+            salt,
+            hash: hash_password(password, &salt),
+        })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        constant_time_eq(&hash_password(password, &self.salt), &self.hash)
+    }
+
+    pub fn set_password(&mut self, password: &str) -> bool {
+        if password.is_empty() || password.len() > PASSWORD_MAX_LEN {
+            return false;
+        }
+
+        self.salt = salt(&self.username);
+        self.hash = hash_password(password, &self.salt);
+
+        true
+    }
+}
+
+impl Debug for UserAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserAccount")
+            .field("username", &self.username)
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Users<const N: usize> {
+    accounts: Vec<UserAccount, N>,
+}
+
+impl<const N: usize> Users<N> {
+    pub const fn new() -> Self {
+        Self {
+            accounts: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &UserAccount> {
+        self.accounts.iter()
+    }
+
+    /// A digest of the secrets of all accounts; never leaves the device.
+    pub(crate) fn digest(&self) -> [u8; 32] {
+        let mut hash = Hash::new();
+
+        for account in &self.accounts {
+            hash.update(account.salt);
+            hash.update(account.hash);
+        }
+
+        hash.finalize()
+    }
+
+    pub fn get(&self, username: &str) -> Option<&UserAccount> {
+        self.accounts
+            .iter()
+            .find(|account| account.username == username)
+    }
+
+    pub fn get_mut(&mut self, username: &str) -> Option<&mut UserAccount> {
+        self.accounts
+            .iter_mut()
+            .find(|account| account.username == username)
+    }
+
+    /// Adds a new account or replaces an existing one with the same username.
+    /// Fails if the store is full, or if the change would leave the store without an admin.
+    pub fn add(&mut self, account: UserAccount) -> bool {
+        if let Some(existing_role) = self.get(&account.username).map(|existing| existing.role) {
+            let last_admin = existing_role == UserRole::Admin
+                && account.role != UserRole::Admin
+                && self.admins() == 1;
+
+            if last_admin {
+                return false;
+            }
+
+            let username = account.username.clone();
+
+            *self.get_mut(&username).unwrap() = account;
+
+            true
+        } else {
+            self.accounts.push(account).is_ok()
+        }
+    }
+
+    /// Removes an account. The last admin account cannot be removed.
+    pub fn remove(&mut self, username: &str) -> bool {
+        let Some(index) = self
+            .accounts
+            .iter()
+            .position(|account| account.username == username)
+        else {
+            return false;
+        };
+
+        if self.accounts[index].role == UserRole::Admin && self.admins() == 1 {
+            return false;
+        }
+
+        self.accounts.swap_remove(index);
+
+        true
+    }
+
+    fn admins(&self) -> usize {
+        self.accounts
+            .iter()
+            .filter(|account| account.role == UserRole::Admin)
+            .count()
+    }
+}
+
+impl<const N: usize> Default for Users<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Lockout {
+    username: String<USERNAME_MAX_LEN>,
+    failed_attempts: u8,
+    locked_until: Option<Instant>,
+}
+
+impl Lockout {
+    fn is_locked(&self, now: Instant) -> bool {
+        self.locked_until
+            .map(|locked_until| now < locked_until)
+            .unwrap_or(false)
+    }
+}
+
+pub static STATE: State<Users<USERS_MAX>> = State::new("USERS", Users::new(), &[&PERSIST_NOTIFY]);
+
+static PERSIST_NOTIFY: Notification = Notification::new();
+
+static LOCKOUTS: Mutex<CriticalSectionRawMutex, RefCell<Vec<Lockout, LOCKOUTS_MAX>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+pub fn is_setup_required() -> bool {
+    STATE.get().is_empty()
+}
+
+/// Creates the first admin account; fails if any account already exists.
+pub fn setup_admin(username: &str, password: &str) -> bool {
+    let Some(account) = UserAccount::new(username, password, UserRole::Admin) else {
+        return false;
+    };
+
+    let mut created = false;
+
+    STATE.update_with(|mut users| {
+        if users.is_empty() {
+            created = users.add(account);
+        }
+
+        users
+    });
+
+    created
+}
+
+/// Checks the credentials against the stored accounts.
+///
+/// After `MAX_FAILED_ATTEMPTS` consecutive failures for a username, all attempts for that username -
+/// including ones with valid credentials - are rejected for `LOCKOUT_DURATION`.
+pub fn authenticate(username: &str, password: &str) -> Option<UserRole> {
+    let now = Instant::now();
+
+    if is_locked_out(username, now) {
+        return None;
+    }
+
+    let users = STATE.get();
+
+    let role = match users.get(username) {
+        Some(account) => account.verify(password).then_some(account.role),
+        None => {
+            // Takes as long as verifying an existing account, so that the timing does not tell which usernames exist
+            black_box(hash_password(password, &[0; 16]));
+
+            None
+        }
+    };
+
+    record_attempt(&users, username, role.is_some(), now);
+
+    role
+}
+
+pub(crate) fn reset() {
+    LOCKOUTS.lock(|lockouts| lockouts.borrow_mut().clear());
+
+    session::reset();
+
+    state::clear(&[&PERSIST_NOTIFY]);
+}
+
+fn is_locked_out(username: &str, now: Instant) -> bool {
+    LOCKOUTS.lock(|lockouts| {
+        lockouts
+            .borrow()
+            .iter()
+            .any(|lockout| lockout.username == username && lockout.is_locked(now))
+    })
+}
+
+fn record_attempt(users: &Users<USERS_MAX>, username: &str, succeeded: bool, now: Instant) {
+    LOCKOUTS.lock(|lockouts| {
+        let mut lockouts = lockouts.borrow_mut();
+
+        let index = lockouts
+            .iter()
+            .position(|lockout| lockout.username == username);
+
+        if succeeded {
+            if let Some(index) = index {
+                lockouts.swap_remove(index);
+            }
+
+            return;
+        }
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                // Such a username cannot exist anyway
+                let Ok(username) = username.try_into() else {
+                    return;
+                };
+
+                if lockouts.is_full() {
+                    // The failed attempts of existing accounts are never forgotten, or else guessing with
+                    // junk usernames in between would reset them. As `LOCKOUTS_MAX` exceeds `USERS_MAX`,
+                    // there always is a username without an account to forget instead - preferably one
+                    // which is not locked out
+                    let without_account =
+                        |lockout: &Lockout| users.get(&lockout.username).is_none();
+
+                    let evicted = lockouts
+                        .iter()
+                        .position(|lockout| without_account(lockout) && !lockout.is_locked(now))
+                        .or_else(|| lockouts.iter().position(without_account));
+
+                    let Some(evicted) = evicted else {
+                        return;
+                    };
+
+                    lockouts.remove(evicted);
+                }
+
+                lockouts
+                    .push(Lockout {
+                        username,
+                        failed_attempts: 0,
+                        locked_until: None,
+                    })
+                    .unwrap();
+
+                lockouts.len() - 1
+            }
+        };
+
+        let lockout = &mut lockouts[index];
+
+        lockout.failed_attempts = lockout.failed_attempts.saturating_add(1);
+
+        if lockout.failed_attempts >= MAX_FAILED_ATTEMPTS {
+            lockout.failed_attempts = 0;
+            lockout.locked_until = Some(now + LOCKOUT_DURATION);
+        }
+    });
+}
+
+pub fn change_password(username: &str, old_password: &str, new_password: &str) -> bool {
+    if authenticate(username, old_password).is_none() {
+        return false;
+    }
+
+    let mut changed = false;
+
+    STATE.update_with(|mut users| {
+        if let Some(account) = users.get_mut(username) {
+            changed = account.set_password(new_password);
+        }
+
+        users
+    });
+
+    if changed {
+        revoke_sessions(username);
+    }
+
+    changed
+}
+
+pub fn add(username: &str, password: &str, role: UserRole) -> bool {
+    let Some(account) = UserAccount::new(username, password, role) else {
+        return false;
+    };
+
+    let mut added = false;
+
+    STATE.update_with(|mut users| {
+        added = users.add(account);
+        users
+    });
+
+    if added {
+        // The account might have been replaced
+        revoke_sessions(username);
+    }
+
+    added
+}
+
+pub fn remove(username: &str) -> bool {
+    let mut removed = false;
+
+    STATE.update_with(|mut users| {
+        removed = users.remove(username);
+        users
+    });
+
+    if removed {
+        revoke_sessions(username);
+    }
+
+    removed
+}
+
+pub async fn persist(mut persister: impl FnMut(Users<USERS_MAX>)) {
+    loop {
+        PERSIST_NOTIFY.wait().await;
+
+        persister(STATE.get());
+    }
+}
+
+fn salt(username: &str) -> [u8; 16] {
+    let mut salt = [0; 16];
+    salt.copy_from_slice(&unique_bytes(username.as_bytes())[..16]);
+
+    salt
+}
+
+/// Salts need to be unique rather than secret, so they are derived from a seed,
+/// the current time and a counter, as there is no portable source of randomness.
+fn unique_bytes(seed: &[u8]) -> [u8; 32] {
+    static COUNTER: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+    let counter = COUNTER.lock(|counter| {
+        let value = counter.get().wrapping_add(1);
+        counter.set(value);
+
+        value
+    });
+
+    let mut hash = Hash::new();
+
+    hash.update(seed);
+    hash.update(Instant::now().as_ticks().to_le_bytes());
+    hash.update(counter.to_le_bytes());
+
+    hash.finalize()
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// PBKDF2-HMAC-SHA256, producing a single output block
+fn hash_password(password: &str, salt: &[u8]) -> [u8; 32] {
+    let mut mac = HMAC::new(password);
+
+    mac.update(salt);
+    mac.update(1_u32.to_be_bytes());
+
+    let mut block = mac.finalize();
+    let mut result = block;
+
+    for _ in 1..HASH_ITERATIONS {
+        block = HMAC::mac(block, password);
+
+        for (result, block) in result.iter_mut().zip(block.iter()) {
+            *result ^= block;
+        }
+    }
+
+    result
+}
@@ -0,0 +1,173 @@
+use core::cell::RefCell;
+
+use heapless::{String, Vec};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use hmac_sha256::HMAC;
+
+use crate::dto::web::{SessionToken, UserRole, USERNAME_MAX_LEN};
+
+use super::{constant_time_eq, STATE};
+
+pub const SESSIONS_MAX: usize = 8;
+pub const SESSION_DURATION: Duration = Duration::from_secs(60 * 60 * 24);
+
+#[derive(Clone, Debug)]
+struct Session {
+    id: u32,
+    username: String<USERNAME_MAX_LEN>,
+    expires_secs: u64,
+}
+
+struct Sessions {
+    key: Option<[u8; 32]>,
+    next_id: u32,
+    sessions: Vec<Session, SESSIONS_MAX>,
+}
+
+impl Sessions {
+    fn sign(key: &[u8; 32], id: u32, expires_secs: u64) -> [u8; 32] {
+        let mut mac = HMAC::new(key);
+
+        mac.update(id.to_le_bytes());
+        mac.update(expires_secs.to_le_bytes());
+
+        mac.finalize()
+    }
+
+    fn prune(&mut self, now_secs: u64) {
+        self.sessions
+            .retain(|session| session.expires_secs > now_secs);
+    }
+}
+
+static SESSIONS: Mutex<CriticalSectionRawMutex, RefCell<Sessions>> =
+    Mutex::new(RefCell::new(Sessions {
+        key: None,
+        next_id: 0,
+        sessions: Vec::new(),
+    }));
+
+pub(crate) fn reset() {
+    SESSIONS.lock(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+
+        sessions.key = None;
+        sessions.next_id = 0;
+        sessions.sessions.clear();
+    });
+}
+
+/// Sets the key session tokens are signed with, ideally coming from a hardware RNG.
+///
+/// Without it, a key is derived from the secrets of all accounts and the time the first session is issued.
+/// Either way, the key is not persisted, so all sessions end when the device restarts.
+pub fn set_session_key(key: [u8; 32]) {
+    SESSIONS.lock(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+
+        sessions.key = Some(key);
+        sessions.sessions.clear();
+    });
+}
+
+/// Starts a new session for an already authenticated user.
+/// When all session slots are taken, the session closest to expiry is dropped.
+pub fn issue_session(username: &str) -> Option<SessionToken> {
+    let now_secs = Instant::now().as_secs();
+    let derived_key = HMAC::mac(
+        Instant::now().as_ticks().to_le_bytes(),
+        STATE.get().digest(),
+    );
+
+    SESSIONS.lock(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+
+        sessions.prune(now_secs);
+
+        if sessions.sessions.is_full() {
+            let oldest = sessions
+                .sessions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, session)| session.expires_secs)
+                .map(|(index, _)| index)?;
+
+            sessions.sessions.swap_remove(oldest);
+        }
+
+        let key = *sessions.key.get_or_insert(derived_key);
+
+        let id = sessions.next_id;
+        sessions.next_id = id.wrapping_add(1);
+
+        let expires_secs = now_secs + SESSION_DURATION.as_secs();
+
+        sessions
+            .sessions
+            .push(Session {
+                id,
+                username: username.try_into().ok()?,
+                expires_secs,
+            })
+            .ok()?;
+
+        Some(SessionToken {
+            id,
+            expires_secs,
+            mac: Sessions::sign(&key, id, expires_secs),
+        })
+    })
+}
+
+/// Returns the user and their current role if the token is genuine and its session is still active.
+pub fn resume_session(token: &SessionToken) -> Option<(String<USERNAME_MAX_LEN>, UserRole)> {
+    let now_secs = Instant::now().as_secs();
+
+    let username = SESSIONS.lock(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+
+        sessions.prune(now_secs);
+
+        let key = sessions.key?;
+
+        if !constant_time_eq(
+            &Sessions::sign(&key, token.id, token.expires_secs),
+            &token.mac,
+        ) {
+            return None;
+        }
+
+        sessions
+            .sessions
+            .iter()
+            .find(|session| session.id == token.id && session.expires_secs == token.expires_secs)
+            .map(|session| session.username.clone())
+    })?;
+
+    let role = STATE.get().get(&username)?.role;
+
+    Some((username, role))
+}
+
+pub fn revoke_session(token: &SessionToken) {
+    SESSIONS.lock(|sessions| {
+        sessions
+            .borrow_mut()
+            .sessions
+            .retain(|session| session.id != token.id);
+    });
+}
+
+/// Ends all sessions of a user, e.g. when their password changes or their account is removed.
+pub fn revoke_sessions(username: &str) {
+    SESSIONS.lock(|sessions| {
+        sessions
+            .borrow_mut()
+            .sessions
+            .retain(|session| session.username != username);
+    });
+}
@@ -1,6 +1,8 @@
 use core::fmt::Debug;
 use core::future::pending;
 
+use log::warn;
+
 use embassy_time::{Duration, Timer};
 
 use embassy_futures::select::{select, Either};
@@ -12,33 +14,38 @@ use embedded_hal::digital::OutputPin;
 
 use channel_bridge::notification::Notification;
 
-use crate::state::State;
+use crate::audit::{self, AuditEvent};
+use crate::command::{Command, CommandPriority, CommandQueue, CommandSource};
+use crate::emergency;
+use crate::state::{self, State};
 
 pub use crate::dto::valve::*;
 
 pub const TURN_TICKS: usize = 20;
 pub const TICK_DELAY: Duration = Duration::from_secs(1);
 
-pub static STATE: State<Option<ValveState>> = State::new(
-    "VALVE",
-    None,
-    &[
-        &crate::keepalive::NOTIF,
-        &crate::emergency::VALVE_STATE_NOTIF,
-        &crate::screen::VALVE_STATE_NOTIF,
-        &crate::mqtt::VALVE_STATE_NOTIF,
-        &crate::web::VALVE_STATE_NOTIF,
-        &STATE_PERSIST_NOTIFY,
-    ],
-);
+const COMMAND_QUEUE_SIZE: usize = 4;
+
+pub static STATE: State<Option<ValveState>> = State::new("VALVE", None, &[&STATE_PERSIST_NOTIFY]);
 
 static STATE_PERSIST_NOTIFY: Notification = Notification::new();
 
-pub(crate) static COMMAND: Signal<CriticalSectionRawMutex, ValveCommand> = Signal::new();
+/// The source of the last command which moved the valve; `None` if unknown, e.g. after a restart
+pub static SOURCE_STATE: State<Option<CommandSource>> = State::new("VALVE SOURCE", None, &[]);
+
+static COMMAND: CommandQueue<ValveCommand, COMMAND_QUEUE_SIZE> = CommandQueue::new();
 
 static SPIN_COMMAND: Signal<CriticalSectionRawMutex, ValveCommand> = Signal::new();
 static SPIN_WORKING: Signal<CriticalSectionRawMutex, Option<u8>> = Signal::new();
 
+pub(crate) fn reset() {
+    COMMAND.clear();
+    SPIN_COMMAND.reset();
+    SPIN_WORKING.reset();
+
+    state::clear(&[&STATE_PERSIST_NOTIFY]);
+}
+
 pub fn emergency_close(
     power_pin: &mut impl OutputPin<Error = impl Debug>,
     open_pin: &mut impl OutputPin<Error = impl Debug>,
@@ -56,29 +63,64 @@ pub fn emergency_close(
     log::error!("End: emergency closing valve due to ULP wakeup");
 }
 
+/// Issues a command to the valve; it is recorded in the audit log once `process` accepts or refuses it
+pub fn command(command: ValveCommand, source: CommandSource) {
+    COMMAND.send(Command::new(command, source));
+}
+
 pub async fn process() {
     loop {
         let current_state = {
-            match select(COMMAND.wait(), SPIN_WORKING.wait()).await {
-                Either::First(command) => match command {
+            match select(COMMAND.receive(), SPIN_WORKING.wait()).await {
+                Either::First(command) => match command.command {
                     ValveCommand::Open => {
                         let state = STATE.get();
 
-                        if !matches!(state, Some(ValveState::Open) | Some(ValveState::Opening(_))) {
-                            SPIN_COMMAND.signal(ValveCommand::Open);
-                            Some(ValveState::Opening(0))
-                        } else {
+                        if command.priority() < CommandPriority::Emergency
+                            && emergency::is_lockout()
+                        {
+                            warn!(
+                                "Refusing to open the valve during an emergency lockout: {:?}",
+                                command
+                            );
+
+                            audit::record(AuditEvent::ValveCommandRefused(
+                                command.command,
+                                command.source,
+                            ));
+
                             state
+                        } else {
+                            audit::record(AuditEvent::ValveCommand(
+                                command.command,
+                                command.source,
+                            ));
+
+                            if !matches!(
+                                state,
+                                Some(ValveState::Open) | Some(ValveState::Opening(_))
+                            ) {
+                                SPIN_COMMAND.signal(ValveCommand::Open);
+                                SOURCE_STATE.update(Some(command.source));
+
+                                Some(ValveState::Opening(0))
+                            } else {
+                                state
+                            }
                         }
                     }
                     ValveCommand::Close => {
                         let state = STATE.get();
 
+                        audit::record(AuditEvent::ValveCommand(command.command, command.source));
+
                         if !matches!(
                             state,
                             Some(ValveState::Closed) | Some(ValveState::Closing(_))
                         ) {
                             SPIN_COMMAND.signal(ValveCommand::Close);
+                            SOURCE_STATE.update(Some(command.source));
+
                             Some(ValveState::Closing(0))
                         } else {
                             state
@@ -1,11 +1,8 @@
 use core::cell::Cell;
 
 use channel_bridge::asynch::*;
-use channel_bridge::notification::Notification;
 
-use edge_frame::dto::Role;
-
-use embassy_futures::select::{select, select4};
+use embassy_futures::select::{select, select3, select4};
 use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex, RawMutex};
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::mutex::Mutex as AsyncMutex;
@@ -14,36 +11,68 @@ use embassy_sync::signal::Signal;
 use futures::FutureExt;
 use log::info;
 
+use heapless::String;
+
+use embedded_svc::wifi::Configuration;
+
+use crate::audit;
 use crate::battery;
+use crate::command::CommandSource;
+use crate::emergency;
+use crate::keepalive::{self, RemainingTime};
+use crate::mqtt;
 use crate::state::State;
+use crate::users;
 use crate::utils::select::EitherUnwrap;
 use crate::valve;
+use crate::wifi;
 use crate::wm;
+use crate::wm_stats;
 
 pub use crate::dto::web::*;
 
-pub(crate) static VALVE_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WM_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WM_STATS_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static BATTERY_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static REMAINING_TIME_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static MQTT_STATE_NOTIF: Notification = Notification::new();
-pub(crate) static WIFI_STATE_NOTIF: Notification = Notification::new();
+struct Login {
+    username: String<USERNAME_MAX_LEN>,
+    role: UserRole,
+    session: Option<SessionToken>,
+}
+
+impl Login {
+    fn new(username: String<USERNAME_MAX_LEN>, role: UserRole) -> Self {
+        let session = users::issue_session(&username);
+
+        Self {
+            username,
+            role,
+            session,
+        }
+    }
+
+    fn auth_event(&self) -> AuthEvent {
+        AuthEvent::Authenticated(self.role, self.session)
+    }
+
+    fn logout(self) {
+        if let Some(session) = self.session {
+            users::revoke_session(&session);
+        }
+    }
+}
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum AuthEvent {
     Connected,
-    Authenticated(Role),
+    Authenticated(UserRole, Option<SessionToken>),
     AuthenticationFailed,
     LoggedOut,
 }
 
 impl AuthEvent {
-    pub fn role(&self) -> Role {
-        if let Self::Authenticated(role) = self {
-            *role
+    pub fn role(&self) -> Option<UserRole> {
+        if let Self::Authenticated(role, _) = self {
+            Some(*role)
         } else {
-            Role::None
+            None
         }
     }
 }
@@ -53,94 +82,204 @@ where
     S: Sender<Data = WebEvent>,
     R: Receiver<Data = Option<WebRequest>, Error = S::Error>,
 {
-    handle(
-        sender,
-        receiver,
-        &VALVE_STATE_NOTIF,
-        &WM_STATE_NOTIF,
-        &BATTERY_STATE_NOTIF,
-    )
-    .await
-    .unwrap();
+    handle(sender, receiver).await.unwrap();
 }
 
-pub async fn handle<S, R>(
-    sender: S,
-    receiver: R,
-    valve_state_notif: &Notification,
-    wm_state_notif: &Notification,
-    battery_state_notif: &Notification,
-) -> Result<(), R::Error>
+pub async fn handle<S, R>(sender: S, receiver: R) -> Result<(), R::Error>
 where
     S: Sender<Data = WebEvent>,
     R: Receiver<Data = Option<WebRequest>, Error = S::Error>,
 {
-    let role = Mutex::<NoopRawMutex, _>::new(Cell::new(Role::None));
+    let role = Mutex::<NoopRawMutex, _>::new(Cell::new(None));
     let auth_signal = Signal::<CriticalSectionRawMutex, _>::new();
 
     let sender = AsyncMutex::<NoopRawMutex, _>::new(sender);
 
     auth_signal.signal(AuthEvent::Connected);
 
-    select(
-        receive(receiver, &role, &auth_signal),
+    select3(
+        receive(&sender, receiver, &role, &auth_signal),
         select4(
             process_auth_event(&sender, &auth_signal),
-            process_state_update(&sender, &role, &valve::STATE, valve_state_notif, |state| {
-                WebEvent::ValveState(state)
-            }),
-            process_state_update(&sender, &role, &wm::STATE, wm_state_notif, |state| {
-                WebEvent::WaterMeterState(state)
-            }),
+            select3(
+                process_state_update(&sender, &role, &valve::STATE, WebEvent::ValveState),
+                process_state_update(
+                    &sender,
+                    &role,
+                    &valve::SOURCE_STATE,
+                    WebEvent::ValveSourceState,
+                ),
+                process_state_update(&sender, &role, &emergency::STATE, WebEvent::LockoutState),
+            )
+            .map(EitherUnwrap::unwrap),
+            process_state_update(&sender, &role, &wm::STATE, WebEvent::WaterMeterState),
             process_state_update(
                 &sender,
                 &role,
-                &battery::STATE,
-                battery_state_notif,
-                WebEvent::BatteryState,
+                &wm_stats::STATE,
+                WebEvent::WaterMeterStatsState,
             ),
         )
         .map(EitherUnwrap::unwrap),
+        select4(
+            process_state_update(&sender, &role, &battery::STATE, WebEvent::BatteryState),
+            process_state_update(&sender, &role, &keepalive::STATE, remaining_time_event),
+            process_state_update(&sender, &role, &mqtt::STATE, WebEvent::MqttState),
+            select(
+                process_state_update(&sender, &role, &wifi::STATE, WebEvent::WifiState),
+                process_state_update(&sender, &role, &wifi::CONFIGURATION, wifi_settings_event),
+            )
+            .map(EitherUnwrap::unwrap),
+        )
+        .map(EitherUnwrap::unwrap),
     )
     .await
     .unwrap()
 }
 
-async fn receive<R>(
+async fn receive<S, R>(
+    sender: &AsyncMutex<impl RawMutex, S>,
     mut receiver: R,
-    role: &Mutex<impl RawMutex, Cell<Role>>,
+    role: &Mutex<impl RawMutex, Cell<Option<UserRole>>>,
     auth_signal: &Signal<CriticalSectionRawMutex, AuthEvent>,
 ) -> Result<(), R::Error>
 where
-    R: Receiver<Data = Option<WebRequest>>,
+    S: Sender<Data = WebEvent>,
+    R: Receiver<Data = Option<WebRequest>, Error = S::Error>,
 {
+    let mut login: Option<Login> = None;
+
     loop {
         let request = receiver.recv().await?;
         info!("[WEB RECEIVE] {:?}", request);
 
         if let Some(request) = request {
-            let new_auth_event = if request.role() <= role.lock(Cell::get) {
+            let new_auth_event = if request.is_permitted(login.as_ref().map(|login| login.role)) {
                 match request {
                     WebRequest::ValveCommand(command) => {
-                        valve::COMMAND.signal(command);
+                        valve::command(command, CommandSource::Web);
                         None
                     }
                     WebRequest::WaterMeterCommand(command) => {
-                        wm::COMMAND.signal(command);
+                        wm::command(command, CommandSource::Web);
+                        None
+                    }
+                    WebRequest::AcknowledgeLockout => {
+                        if !emergency::acknowledge(CommandSource::Web) {
+                            send_event(sender, WebEvent::RequestFailed, None).await?;
+                        }
+
+                        None
+                    }
+                    WebRequest::GetEvents => {
+                        send_event(
+                            sender,
+                            WebEvent::AuditLog(audit::get()),
+                            role.lock(Cell::get),
+                        )
+                        .await?;
+                        None
+                    }
+                    WebRequest::WifiSettingsUpdate(configuration) => {
+                        wifi::configure(keep_wifi_password(configuration));
                         None
                     }
                     WebRequest::Authenticate(username, password) => {
-                        if let Some(new_role) = authenticate(&username, &password) {
-                            info!("[S] Authenticated; role: {}", new_role);
+                        if let Some(user_role) = users::authenticate(&username, &password) {
+                            info!("[WS] Authenticated; role: {:?}", user_role);
 
-                            Some(AuthEvent::Authenticated(new_role))
+                            let new_login = Login::new(username, user_role);
+                            let auth_event = new_login.auth_event();
+
+                            login = Some(new_login);
+
+                            Some(auth_event)
                         } else {
                             info!("[WS] Authentication failed");
 
+                            login = None;
+
                             Some(AuthEvent::AuthenticationFailed)
                         }
                     }
-                    WebRequest::Logout => Some(AuthEvent::LoggedOut),
+                    WebRequest::Resume(token) => {
+                        if let Some((username, user_role)) = users::resume_session(&token) {
+                            info!("[WS] Session resumed; role: {:?}", user_role);
+
+                            let resumed = Login {
+                                username,
+                                role: user_role,
+                                session: Some(token),
+                            };
+                            let auth_event = resumed.auth_event();
+
+                            login = Some(resumed);
+
+                            Some(auth_event)
+                        } else {
+                            info!("[WS] Session expired or revoked");
+
+                            login = None;
+
+                            Some(AuthEvent::LoggedOut)
+                        }
+                    }
+                    WebRequest::SetupAdmin(username, password) => {
+                        if users::setup_admin(&username, &password) {
+                            info!("[WS] Admin account created");
+
+                            let new_login = Login::new(username, UserRole::Admin);
+                            let auth_event = new_login.auth_event();
+
+                            login = Some(new_login);
+
+                            Some(auth_event)
+                        } else {
+                            send_event(sender, WebEvent::RequestFailed, None).await?;
+                            None
+                        }
+                    }
+                    WebRequest::ChangePassword(old_password, new_password) => {
+                        let changed = login.as_ref().is_some_and(|login| {
+                            users::change_password(&login.username, &old_password, &new_password)
+                        });
+
+                        if changed {
+                            // All sessions of the user were revoked; start a fresh one for this connection
+                            let current = login.take().unwrap();
+                            let new_login = Login::new(current.username, current.role);
+                            let auth_event = new_login.auth_event();
+
+                            login = Some(new_login);
+
+                            Some(auth_event)
+                        } else {
+                            send_event(sender, WebEvent::RequestFailed, None).await?;
+
+                            None
+                        }
+                    }
+                    WebRequest::AddUser(username, password, user_role) => {
+                        if !users::add(&username, &password, user_role) {
+                            send_event(sender, WebEvent::RequestFailed, None).await?;
+                        }
+
+                        None
+                    }
+                    WebRequest::RemoveUser(username) => {
+                        if !users::remove(&username) {
+                            send_event(sender, WebEvent::RequestFailed, None).await?;
+                        }
+
+                        None
+                    }
+                    WebRequest::Logout => {
+                        if let Some(login) = login.take() {
+                            login.logout();
+                        }
+
+                        Some(AuthEvent::LoggedOut)
+                    }
                 }
             } else {
                 None
@@ -169,58 +308,59 @@ where
         let event = auth_signal.wait().await;
 
         let web_event = match event {
-            AuthEvent::Authenticated(role) => WebEvent::RoleState(role),
+            AuthEvent::Authenticated(role, session) => WebEvent::RoleState(Some(role), session),
             AuthEvent::AuthenticationFailed => WebEvent::AuthenticationFailed,
-            _ => WebEvent::RoleState(Role::None),
+            _ => WebEvent::RoleState(None, None),
         };
 
         send_event(sender, web_event, event.role()).await?;
 
-        send_event(
-            sender,
-            WebEvent::ValveState(valve::STATE.get()),
-            event.role(),
-        )
-        .await?;
+        if users::is_setup_required() {
+            send_event(sender, WebEvent::SetupRequired, event.role()).await?;
+        }
 
-        send_event(
-            sender,
+        for state_event in [
+            WebEvent::ValveState(valve::STATE.get()),
+            WebEvent::ValveSourceState(valve::SOURCE_STATE.get()),
+            WebEvent::LockoutState(emergency::STATE.get()),
             WebEvent::WaterMeterState(wm::STATE.get()),
-            event.role(),
-        )
-        .await?;
-
-        send_event(
-            sender,
+            WebEvent::WaterMeterStatsState(wm_stats::STATE.get()),
             WebEvent::BatteryState(battery::STATE.get()),
-            event.role(),
-        )
-        .await?;
+            remaining_time_event(keepalive::STATE.get()),
+            WebEvent::MqttState(mqtt::STATE.get()),
+            WebEvent::WifiState(wifi::STATE.get()),
+            wifi_settings_event(wifi::CONFIGURATION.get()),
+        ] {
+            send_event(sender, state_event, event.role()).await?;
+        }
     }
 }
 
 async fn process_state_update<'a, S, T>(
     sender: &AsyncMutex<impl RawMutex, S>,
-    role: &Mutex<impl RawMutex, Cell<Role>>,
+    role: &Mutex<impl RawMutex, Cell<Option<UserRole>>>,
     state: &State<'a, T>,
-    state_notif: &Notification,
     to_web_event: impl Fn(T) -> WebEvent,
 ) -> Result<(), S::Error>
 where
     S: Sender<Data = WebEvent>,
     T: Clone,
 {
+    let mut revision = state.revision();
+
     loop {
-        state_notif.wait().await;
+        let update = state.wait_changed(revision).await;
+
+        revision = update.revision;
 
-        send_event(sender, to_web_event(state.get()), role.lock(Cell::get)).await?;
+        send_event(sender, to_web_event(update.value), role.lock(Cell::get)).await?;
     }
 }
 
 async fn send_event<S>(
     sender: &AsyncMutex<impl RawMutex, S>,
     event: WebEvent,
-    role: Role,
+    role: Option<UserRole>,
 ) -> Result<(), S::Error>
 where
     S: Sender<Data = WebEvent>,
@@ -236,6 +376,29 @@ where
     }
 }
 
-fn authenticate(_username: &str, _password: &str) -> Option<Role> {
-    Some(Role::Admin) // TODO
+fn remaining_time_event(remaining_time: RemainingTime) -> WebEvent {
+    WebEvent::RemainingTimeState(match remaining_time {
+        RemainingTime::Indefinite => None,
+        RemainingTime::Duration(duration) => Some(duration.as_secs() as u32),
+    })
+}
+
+fn wifi_settings_event(configuration: Option<Configuration>) -> WebEvent {
+    WebEvent::WifiSettingsState(configuration.as_ref().and_then(WifiSettings::new))
+}
+
+/// The UI never gets the password, so it sends an empty one when only the other settings are changed
+fn keep_wifi_password(mut configuration: Configuration) -> Configuration {
+    if let (
+        Configuration::Client(conf) | Configuration::Mixed(conf, _),
+        Some(Configuration::Client(current) | Configuration::Mixed(current, _)),
+    ) = (&mut configuration, wifi::CONFIGURATION.get())
+    {
+        if conf.password.is_empty() && conf.ssid == current.ssid {
+            conf.password = current.password;
+            conf.auth_method = current.auth_method;
+        }
+    }
+
+    configuration
 }
@@ -2,96 +2,184 @@ use core::fmt::Debug;
 
 use embassy_futures::select::{select, Either};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-use embassy_time::{Duration, Timer};
-use log::info;
+use embassy_time::{with_timeout, Duration, Timer};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
-use embedded_svc::wifi::{asynch::Wifi, AuthMethod, Configuration};
+use embedded_svc::wifi::{asynch::Wifi, AccessPointConfiguration, AuthMethod, Configuration};
 
-use crate::state::State;
+use channel_bridge::notification::Notification;
+
+use crate::state::{self, State};
 
 #[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub enum WifiCommand {
     SetConfiguration(Configuration),
 }
 
-pub static STATE: State<Option<bool>> = State::new(
-    "WIFI",
-    None,
-    &[
-        &crate::keepalive::NOTIF,
-        &crate::screen::WIFI_STATE_NOTIF,
-        &crate::mqtt::WIFI_STATE_NOTIF,
-        &crate::web::WIFI_STATE_NOTIF,
-    ],
-);
+pub static STATE: State<Option<bool>> = State::new("WIFI", None, &[]);
+
+/// The configuration the device runs with, i.e. the last one which was verified to work.
+/// Updating it persists the new configuration; new configurations are applied with `configure`.
+pub static CONFIGURATION: State<Option<Configuration>> =
+    State::new("WIFI CONFIGURATION", None, &[&CONFIGURATION_PERSIST_NOTIFY]);
+
+static CONFIGURATION_PERSIST_NOTIFY: Notification = Notification::new();
 
 pub static COMMAND: Signal<CriticalSectionRawMutex, WifiCommand> = Signal::new();
 
+pub(crate) fn reset() {
+    COMMAND.reset();
+
+    state::clear(&[&CONFIGURATION_PERSIST_NOTIFY]);
+}
+
+/// How long a client configuration gets to connect before it is considered broken
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The open access point the device falls back to when it has no working configuration,
+/// so that it can still be set up via the UI
+pub fn access_point() -> Configuration {
+    Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: "ruwm".try_into().unwrap(),
+        auth_method: AuthMethod::None,
+        ..Default::default()
+    })
+}
+
+/// Applies a new configuration tentatively.
+///
+/// The configuration becomes the `CONFIGURATION` (and is persisted) only once it connects;
+/// otherwise the device reverts to the current configuration.
+pub fn configure(conf: Configuration) {
+    COMMAND.signal(WifiCommand::SetConfiguration(conf));
+}
+
 pub async fn process<W: Wifi>(mut wifi: W) -> Result<(), W::Error> {
-    let mut stay_connected = false;
+    let conf = CONFIGURATION.get().unwrap_or_else(access_point);
+
+    // The persisted configuration was verified when it was set, so it is kept even if it cannot connect right now
+    apply(&mut wifi, &conf).await?;
+
+    let mut stay_connected = connects(&conf);
 
     loop {
         let result = select(COMMAND.wait(), Timer::after(Duration::from_secs(1))).await;
 
         match result {
-            Either::First(command) => {
-                if wifi.is_started().await? {
-                    let _ = wifi.stop().await?;
+            Either::First(WifiCommand::SetConfiguration(conf)) => {
+                let connected = match apply(&mut wifi, &conf).await {
+                    Ok(connected) => connected,
+                    Err(e) => {
+                        warn!("Wifi configuration failed: {:?}", e);
+                        false
+                    }
+                };
+
+                if connected {
+                    stay_connected = connects(&conf);
+                    CONFIGURATION.update(Some(conf));
+                } else {
+                    let previous = CONFIGURATION.get().unwrap_or_else(access_point);
+
+                    warn!("Wifi configuration rejected, reverting to the previous one");
+
+                    apply(&mut wifi, &previous).await?;
+
+                    stay_connected = connects(&previous);
                 }
+            }
+            Either::Second(_) => {
+                if stay_connected && !wifi.is_connected().await? {
+                    info!("Wifi disconnection detected, reconnecting...");
 
-                stay_connected = false;
+                    STATE.update(Some(false));
 
-                match command {
-                    WifiCommand::SetConfiguration(conf) => {
-                        info!("Got configuration: {:?}", conf);
+                    // Not waiting indefinitely, so that a new configuration can still be applied meanwhile
+                    connect(&mut wifi).await;
+                }
+            }
+        }
+    }
+}
 
-                        wifi.set_configuration(&conf).await?;
+/// Returns `false` if the configuration should connect as a client but it did not do so within `CONNECT_TIMEOUT`
+async fn apply<W: Wifi>(wifi: &mut W, conf: &Configuration) -> Result<bool, W::Error> {
+    if wifi.is_started().await? {
+        let _ = wifi.stop().await?;
+    }
 
-                        if !matches!(conf, Configuration::None) {
-                            wifi.start().await?;
+    STATE.update(None);
 
-                            while !wifi.is_started().await? {
-                                Timer::after(Duration::from_millis(100)).await;
-                            }
+    info!("Got configuration: {:?}", conf);
 
-                            info!("Wifi started");
-                        }
+    wifi.set_configuration(conf).await?;
 
-                        match &conf {
-                            Configuration::Client(conf) | Configuration::Mixed(conf, _) => {
-                                if conf.auth_method != AuthMethod::None {
-                                    wifi.connect().await?;
+    if !matches!(conf, Configuration::None) {
+        wifi.start().await?;
 
-                                    while !wifi.is_connected().await? {
-                                        Timer::after(Duration::from_millis(100)).await;
-                                    }
+        while !wifi.is_started().await? {
+            Timer::after(Duration::from_millis(100)).await;
+        }
 
-                                    stay_connected = true;
+        STATE.update(Some(false));
 
-                                    info!("Wifi connected");
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-            }
-            Either::Second(_) => {
-                if stay_connected {
-                    if !wifi.is_connected().await? {
-                        info!("Wifi disconnection detected, reconnecting...");
+        info!("Wifi started");
+    }
 
-                        wifi.connect().await?;
+    if connects(conf) {
+        Ok(connect(wifi).await)
+    } else {
+        Ok(true)
+    }
+}
 
-                        while !wifi.is_connected().await? {
-                            Timer::after(Duration::from_millis(100)).await;
-                        }
+async fn connect<W: Wifi>(wifi: &mut W) -> bool {
+    let connected = with_timeout(CONNECT_TIMEOUT, async {
+        wifi.connect().await?;
 
-                        info!("Wifi connected");
-                    }
-                }
-            }
+        while !wifi.is_connected().await? {
+            Timer::after(Duration::from_millis(100)).await;
+        }
+
+        Ok::<_, W::Error>(())
+    })
+    .await;
+
+    match connected {
+        Ok(Ok(())) => {
+            STATE.update(Some(true));
+
+            info!("Wifi connected");
+
+            true
         }
+        Ok(Err(e)) => {
+            warn!("Wifi connection failed: {:?}", e);
+
+            false
+        }
+        Err(_) => {
+            warn!("Wifi connection timed out");
+
+            false
+        }
+    }
+}
+
+fn connects(conf: &Configuration) -> bool {
+    match conf {
+        Configuration::Client(conf) | Configuration::Mixed(conf, _) => {
+            conf.auth_method != AuthMethod::None
+        }
+        _ => false,
+    }
+}
+
+pub async fn persist_configuration(mut persister: impl FnMut(Option<Configuration>)) {
+    loop {
+        CONFIGURATION_PERSIST_NOTIFY.wait().await;
+
+        persister(CONFIGURATION.get());
     }
 }
@@ -1,35 +1,84 @@
+use core::cell::{Cell, RefCell};
+
 use embassy_futures::select::select;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
 
 use channel_bridge::notification::Notification;
 
+use crate::audit::{self, AuditEvent};
+use crate::command::{Command, CommandQueue, CommandSource};
 use crate::pulse_counter::{PulseCounter, PulseWakeup};
-use crate::state::State;
+use crate::state::{self, State};
 
+pub use crate::dto::pulse_trace::*;
 pub use crate::dto::water_meter::*;
 
 pub const FLASH_WRITE_CYCLE: usize = 20;
 
+const COMMAND_QUEUE_SIZE: usize = 4;
+
 pub static STATE: State<WaterMeterState> = State::new(
     "WM",
     WaterMeterState::new(),
-    &[
-        &crate::keepalive::NOTIF,
-        &crate::emergency::WM_STATE_NOTIF,
-        &crate::wm_stats::WM_STATE_NOTIF,
-        &crate::screen::WM_STATE_NOTIF,
-        &crate::mqtt::WM_STATE_NOTIF,
-        &crate::web::WM_STATE_NOTIF,
-        &STATE_PERSIST_NOTIFY,
-        &STATE_FLASH_NOTIFY,
-    ],
+    &[&STATE_PERSIST_NOTIFY, &STATE_FLASH_NOTIFY],
 );
 
 static STATE_PERSIST_NOTIFY: Notification = Notification::new();
 static STATE_FLASH_NOTIFY: Notification = Notification::new();
 
-pub(crate) static COMMAND: Signal<CriticalSectionRawMutex, WaterMeterCommand> = Signal::new();
+/// Whether `process` records the pulse timeline into `TRACE`; off by default, as it is only needed to reproduce field issues
+pub static TRACING: State<bool> = State::new("WM TRACING", false, &[]);
+
+pub static TRACE: Mutex<CriticalSectionRawMutex, RefCell<PulseTrace<PULSE_TRACE_SIZE>>> =
+    Mutex::new(RefCell::new(PulseTrace::new()));
+
+/// Added to `Instant::now()` by `trace_time_ms`, so that a trace restored after a deep sleep keeps its timeline
+static TRACE_TIME_OFFSET_MS: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+static COMMAND: CommandQueue<WaterMeterCommand, COMMAND_QUEUE_SIZE> = CommandQueue::new();
+
+pub(crate) fn reset() {
+    COMMAND.clear();
+    TRACE_TIME_OFFSET_MS.lock(|offset| offset.set(0));
+
+    state::clear(&[&STATE_PERSIST_NOTIFY, &STATE_FLASH_NOTIFY]);
+}
+
+/// Issues a command to the water meter, recording it in the audit log
+pub fn command(command: WaterMeterCommand, source: CommandSource) {
+    audit::record(AuditEvent::WaterMeterCommand(command, source));
+    COMMAND.send(Command::new(command, source));
+}
+
+/// Enables or disables the recording of the pulse timeline; enabling it starts a fresh trace
+pub fn set_tracing(enabled: bool) {
+    if TRACING.update(enabled) && enabled {
+        TRACE.lock(|trace| trace.borrow_mut().clear());
+    }
+}
+
+pub fn trace() -> PulseTrace<PULSE_TRACE_SIZE> {
+    TRACE.lock(|trace| trace.borrow().clone())
+}
+
+/// The time on the clock of the pulse trace entries, in milliseconds
+pub fn trace_time_ms() -> u32 {
+    (Instant::now().as_millis() as u32).wrapping_add(TRACE_TIME_OFFSET_MS.lock(Cell::get))
+}
+
+/// Restores the tracing state saved before a deep sleep - e.g. in RTC memory - which the RAM does not survive.
+///
+/// As `Instant` starts from zero again after the wakeup, `time_ms` is the current time on the clock of the trace:
+/// `trace_time_ms` when the trace was saved, plus the time slept since.
+pub fn restore_trace(tracing: bool, trace: PulseTrace<PULSE_TRACE_SIZE>, time_ms: u32) {
+    TRACE_TIME_OFFSET_MS
+        .lock(|offset| offset.set(time_ms.wrapping_sub(Instant::now().as_millis() as u32)));
+
+    TRACE.lock(|current| *current.borrow_mut() = trace);
+    TRACING.set(tracing);
+}
 
 pub async fn process(pulse_counter: impl PulseCounter, pulse_wakeup: impl PulseWakeup) {
     select(
@@ -44,6 +93,10 @@ async fn process_pulses(mut pulse_counter: impl PulseCounter) {
         let pulses = pulse_counter.take_pulses().await.unwrap();
 
         if pulses > 0 {
+            if TRACING.get() {
+                TRACE.lock(|trace| trace.borrow_mut().push_edges(trace_time_ms(), pulses));
+            }
+
             STATE.update_with(|state| WaterMeterState {
                 edges_count: state.edges_count + pulses,
                 armed: state.armed,
@@ -55,14 +108,28 @@ async fn process_pulses(mut pulse_counter: impl PulseCounter) {
 
 async fn process_commands(mut pulse_wakeup: impl PulseWakeup) {
     loop {
-        let armed = COMMAND.wait().await == WaterMeterCommand::Arm;
+        let armed = COMMAND.receive().await.command == WaterMeterCommand::Arm;
 
         pulse_wakeup.set_enabled(armed).unwrap();
 
+        if TRACING.get() {
+            TRACE.lock(|trace| {
+                trace.borrow_mut().push(
+                    trace_time_ms(),
+                    if armed {
+                        PulseTraceEvent::Armed
+                    } else {
+                        PulseTraceEvent::Disarmed
+                    },
+                )
+            });
+        }
+
+        // Disarming also clears the leak flag, so that a leak lockout can be acknowledged
         STATE.update_with(|state| WaterMeterState {
             edges_count: state.edges_count,
             armed,
-            leaking: state.leaking,
+            leaking: state.leaking && armed,
         });
     }
 }
@@ -4,34 +4,40 @@ use embassy_futures::select::{select, Either};
 
 use channel_bridge::notification::Notification;
 
-use crate::{state::*, wm};
+use crate::state::*;
+use crate::wm::{self, WaterMeterState};
 
 pub use crate::dto::water_meter_stats::*;
 
 pub static STATE: State<WaterMeterStatsState> = State::new(
     "WM STATS",
     WaterMeterStatsState::new(),
-    &[
-        &crate::keepalive::NOTIF,
-        &crate::screen::WM_STATS_STATE_NOTIF,
-        &crate::web::WM_STATS_STATE_NOTIF,
-        &STATE_PERSIST_NOTIFY,
-    ],
+    &[&STATE_PERSIST_NOTIFY],
 );
 
-pub(crate) static WM_STATE_NOTIF: Notification = Notification::new();
-
 static STATE_PERSIST_NOTIFY: Notification = Notification::new();
 
+static WM_STATE: Subscriber<WaterMeterState> = Subscriber::new();
+
+pub(crate) fn reset() {
+    wm::STATE.unsubscribe(&WM_STATE);
+
+    clear(&[&STATE_PERSIST_NOTIFY]);
+}
+
 pub async fn process() {
+    if !wm::STATE.subscribe(&WM_STATE) {
+        panic!("Too many water meter state subscribers");
+    }
+
     loop {
         let edges_count = match select(
-            WM_STATE_NOTIF.wait(),
+            WM_STATE.next(),
             Timer::after(Duration::from_secs(10) /*Duration::from_millis(200)*/),
         )
         .await
         {
-            Either::First(_) => wm::STATE.get().edges_count,
+            Either::First(update) => update.value.edges_count,
             Either::Second(_) => STATE.get().most_recent.edges_count,
         };
 
@@ -0,0 +1,407 @@
+use embassy_time::Duration;
+
+use embedded_svc::http::Method;
+
+use ruwm::emergency::{self, LockoutReason, LockoutState};
+use ruwm::mqtt;
+use ruwm::users::{self, LOCKOUT_DURATION, MAX_FAILED_ATTEMPTS};
+use ruwm::valve::{self, ValveState, TICK_DELAY, TURN_TICKS};
+use ruwm::web::UserRole;
+use ruwm::wm;
+use ruwm::wm_stats::{self, WaterMeterStatsState};
+
+use harness::{Harness, MockResponse};
+
+mod harness;
+
+const ADMIN: (&str, &str) = ("admin", "secret");
+const USER: (&str, &str) = ("user", "user");
+const VIEWER: (&str, &str) = ("viewer", "viewer");
+
+const OPEN: &str = r#"{"command": "Open"}"#;
+const ARM: &str = r#"{"armed": true}"#;
+const START_TRACING: &str = r#"{"enabled": true}"#;
+const MQTT: &str = r#"{
+    "protocol_311": false,
+    "url": "mqtts://broker.local:8883",
+    "client_id": "water-meter",
+    "username": "meter",
+    "password": "secret",
+    "ca_certificate": "mqtt_ca",
+    "client_certificate": null,
+    "client_key": null,
+    "topics": {
+        "prefix": "home",
+        "device_id": "",
+        "valve": "",
+        "meter": "",
+        "battery": "",
+        "powered": "",
+        "commands": "",
+        "events": ""
+    }
+}"#;
+
+const TURN_DURATION: Duration = Duration::from_secs(TICK_DELAY.as_secs() * TURN_TICKS as u64);
+
+fn setup() -> Harness {
+    let harness = Harness::new();
+
+    assert!(users::add(ADMIN.0, ADMIN.1, UserRole::Admin));
+    assert!(users::add(USER.0, USER.1, UserRole::User));
+    assert!(users::add(VIEWER.0, VIEWER.1, UserRole::Viewer));
+
+    harness
+}
+
+/// The `Authorization` header value of HTTP Basic authentication
+fn basic((username, password): (&str, &str)) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let credentials = format!("{username}:{password}");
+
+    let mut encoded = String::from("Basic ");
+
+    for chunk in credentials.as_bytes().chunks(3) {
+        let value = chunk.iter().enumerate().fold(0, |value, (index, byte)| {
+            value | ((*byte as u32) << (16 - 8 * index))
+        });
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[((value >> (18 - 6 * index)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+fn get(harness: &Harness, uri: &str, authorization: Option<&str>) -> MockResponse {
+    request(harness, Method::Get, uri, authorization, b"")
+}
+
+fn post(harness: &Harness, uri: &str, authorization: Option<&str>, body: &str) -> MockResponse {
+    request(harness, Method::Post, uri, authorization, body.as_bytes())
+}
+
+fn request(
+    harness: &Harness,
+    method: Method,
+    uri: &str,
+    authorization: Option<&str>,
+    body: &[u8],
+) -> MockResponse {
+    let headers = authorization
+        .map(|authorization| vec![("Authorization", authorization)])
+        .unwrap_or_default();
+
+    harness.api_request(method, uri, &headers, body)
+}
+
+fn is_challenge(response: &MockResponse) -> bool {
+    response.status == 401 && response.header("WWW-Authenticate").is_some()
+}
+
+#[test]
+fn unknown_endpoints_and_methods_are_rejected() {
+    let harness = setup();
+    let admin = basic(ADMIN);
+
+    assert_eq!(get(&harness, "/api/nothing", Some(&admin)).status, 404);
+    assert_eq!(get(&harness, "/api/valve", Some(&admin)).status, 405);
+    assert_eq!(post(&harness, "/api/state", Some(&admin), "").status, 405);
+}
+
+#[test]
+fn missing_or_wrong_credentials_are_challenged() {
+    let harness = setup();
+
+    assert!(is_challenge(&get(&harness, "/api/state", None)));
+    assert!(is_challenge(&get(
+        &harness,
+        "/api/state",
+        Some(&basic((ADMIN.0, "wrong")))
+    )));
+    assert!(is_challenge(&get(
+        &harness,
+        "/api/state",
+        Some(&basic(("nobody", ADMIN.1)))
+    )));
+    assert!(is_challenge(&get(
+        &harness,
+        "/api/state",
+        Some("Basic !!!!")
+    )));
+    assert!(is_challenge(&get(
+        &harness,
+        "/api/state",
+        Some("Bearer nonsense")
+    )));
+    assert!(is_challenge(&get(
+        &harness,
+        "/api/state",
+        Some("Digest nonsense")
+    )));
+
+    // Nothing was done on behalf of the anonymous client
+    assert!(is_challenge(&post(&harness, "/api/valve", None, OPEN)));
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(valve::STATE.get(), None);
+}
+
+#[test]
+fn failed_credentials_count_towards_the_lockout() {
+    let harness = setup();
+
+    for _ in 0..MAX_FAILED_ATTEMPTS {
+        assert!(is_challenge(&get(
+            &harness,
+            "/api/state",
+            Some(&basic((ADMIN.0, "wrong")))
+        )));
+    }
+
+    // Locked out, even with the right password
+    assert!(is_challenge(&get(
+        &harness,
+        "/api/state",
+        Some(&basic(ADMIN))
+    )));
+
+    harness.advance(LOCKOUT_DURATION + Duration::from_secs(1));
+
+    assert_eq!(get(&harness, "/api/state", Some(&basic(ADMIN))).status, 200);
+}
+
+#[test]
+fn sessions_authenticate_as_bearer_tokens() {
+    let harness = setup();
+
+    let token = users::issue_session(VIEWER.0).unwrap();
+    let bearer = format!("Bearer {token}");
+
+    assert_eq!(get(&harness, "/api/state", Some(&bearer)).status, 200);
+
+    // A viewer session cannot command the valve
+    assert_eq!(
+        post(&harness, "/api/valve", Some(&bearer), OPEN).status,
+        403
+    );
+
+    users::revoke_session(&token);
+
+    assert!(is_challenge(&get(&harness, "/api/state", Some(&bearer))));
+}
+
+#[test]
+fn roles_are_enforced() {
+    let harness = setup();
+
+    let admin = basic(ADMIN);
+    let user = basic(USER);
+    let viewer = basic(VIEWER);
+
+    // Viewers see the state, but cannot change it
+    assert_eq!(get(&harness, "/api/state", Some(&viewer)).status, 200);
+    assert_eq!(get(&harness, "/api/stats", Some(&viewer)).status, 200);
+    assert_eq!(
+        post(&harness, "/api/meter/arm", Some(&viewer), ARM).status,
+        403
+    );
+    assert!(!wm::STATE.get().armed);
+
+    // Users can
+    assert_eq!(
+        post(&harness, "/api/meter/arm", Some(&user), ARM).status,
+        202
+    );
+    assert!(wm::STATE.get().armed);
+
+    assert_eq!(post(&harness, "/api/valve", Some(&user), OPEN).status, 202);
+    assert!(harness
+        .advance_until(TURN_DURATION + TICK_DELAY, || {
+            valve::STATE.get() == Some(ValveState::Open)
+        })
+        .is_some());
+
+    // But the pulse trace is for admins only
+    assert_eq!(get(&harness, "/api/meter/trace", Some(&user)).status, 403);
+    assert_eq!(
+        post(&harness, "/api/meter/tracing", Some(&user), START_TRACING).status,
+        403
+    );
+    assert!(!wm::TRACING.get());
+
+    assert_eq!(
+        post(&harness, "/api/meter/tracing", Some(&admin), START_TRACING).status,
+        204
+    );
+    assert!(wm::TRACING.get());
+
+    let response = get(&harness, "/api/meter/trace", Some(&admin));
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("application/octet-stream")
+    );
+}
+
+#[test]
+fn malformed_bodies_are_rejected() {
+    let harness = setup();
+    let admin = basic(ADMIN);
+
+    for body in [
+        "",
+        "open",
+        r#"{"command": "Sideways"}"#,
+        r#"{"command": "Open""#,
+        ARM,
+    ] {
+        assert_eq!(
+            post(&harness, "/api/valve", Some(&admin), body).status,
+            400,
+            "{body}"
+        );
+    }
+
+    // Bodies longer than any valid one are not even parsed
+    let padded = format!(r#"{{"command": "Open"{}}}"#, " ".repeat(64));
+    assert_eq!(
+        post(&harness, "/api/valve", Some(&admin), &padded).status,
+        400
+    );
+
+    assert_eq!(
+        post(
+            &harness,
+            "/api/meter/arm",
+            Some(&admin),
+            r#"{"armed": "yes"}"#
+        )
+        .status,
+        400
+    );
+    assert_eq!(
+        post(&harness, "/api/meter/tracing", Some(&admin), "{}").status,
+        400
+    );
+
+    harness.advance(Duration::from_secs(1));
+
+    assert_eq!(valve::STATE.get(), None);
+    assert!(!wm::STATE.get().armed);
+    assert!(!wm::TRACING.get());
+}
+
+#[test]
+fn states_are_served_as_json() {
+    let harness = setup();
+    let viewer = basic(VIEWER);
+
+    let response = get(&harness, "/api/stats", Some(&viewer));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert_eq!(
+        response.header("Content-Length"),
+        Some(response.body.len().to_string().as_str())
+    );
+
+    let (stats, _) = serde_json_core::from_slice::<WaterMeterStatsState>(&response.body).unwrap();
+    assert_eq!(stats, wm_stats::STATE.get());
+
+    let response = get(&harness, "/api/state", Some(&viewer));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+
+    let body = std::str::from_utf8(&response.body).unwrap();
+    let mut buf = [0; 128];
+    let len = serde_json_core::to_slice(&wm::STATE.get(), &mut buf).unwrap();
+    let water_meter = std::str::from_utf8(&buf[..len]).unwrap();
+
+    assert!(body.starts_with('{') && body.ends_with('}'), "{body}");
+    assert!(
+        body.contains(&format!(r#""water_meter":{water_meter}"#)),
+        "{body}"
+    );
+    assert!(body.contains(r#""valve":null"#), "{body}");
+
+    // Errors carry no body
+    let response = post(&harness, "/api/valve", Some(&viewer), "{}");
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("Content-Length"), Some("0"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn lockout_is_acknowledged_by_admins_once_the_condition_cleared() {
+    let harness = setup();
+
+    let admin = basic(ADMIN);
+    let user = basic(USER);
+
+    assert_eq!(
+        post(&harness, "/api/lockout/ack", Some(&admin), "").status,
+        409
+    );
+
+    emergency::STATE.set(Some(LockoutState {
+        reason: LockoutReason::Leak,
+        condition_present: true,
+    }));
+
+    assert_eq!(
+        post(&harness, "/api/lockout/ack", Some(&admin), "").status,
+        409
+    );
+
+    emergency::STATE.set(Some(LockoutState {
+        reason: LockoutReason::Leak,
+        condition_present: false,
+    }));
+
+    assert_eq!(
+        post(&harness, "/api/lockout/ack", Some(&user), "").status,
+        403
+    );
+    assert!(emergency::STATE.get().is_some());
+
+    assert_eq!(
+        post(&harness, "/api/lockout/ack", Some(&admin), "").status,
+        204
+    );
+    assert_eq!(emergency::STATE.get(), None);
+}
+
+#[test]
+fn mqtt_configuration_is_set_by_admins() {
+    let harness = setup();
+
+    let admin = basic(ADMIN);
+    let user = basic(USER);
+
+    assert_eq!(post(&harness, "/api/mqtt", Some(&user), MQTT).status, 403);
+    assert_eq!(
+        post(
+            &harness,
+            "/api/mqtt",
+            Some(&admin),
+            r#"{"url": "mqtt://broker.local"}"#
+        )
+        .status,
+        400
+    );
+    assert_eq!(mqtt::CONFIGURATION.get(), None);
+
+    assert_eq!(post(&harness, "/api/mqtt", Some(&admin), MQTT).status, 204);
+
+    let configuration = mqtt::CONFIGURATION.get().unwrap();
+    assert_eq!(configuration.url, "mqtts://broker.local:8883");
+    assert_eq!(configuration.password, "secret");
+    assert_eq!(configuration.ca_certificate.as_deref(), Some("mqtt_ca"));
+    assert_eq!(configuration.topics.prefix(), "home");
+    assert!(configuration.is_tls());
+}
@@ -0,0 +1,157 @@
+//! Compares display snapshots against the PNG goldens checked in under `tests/goldens`.
+//!
+//! With `UPDATE_GOLDENS=1`, the goldens are (re)written from what is rendered instead, e.g. after an intended change of the screen.
+//! A missing golden is written as well, but fails the test, so that it gets reviewed before being checked in.
+//! On a mismatch, what was rendered is written next to the golden, as `<name>.actual.png`.
+//! All the snapshots of a test are checked before it fails, so that one run reports every mismatch.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics::prelude::Size;
+
+use ruwm::screen::Color;
+
+use ruwm_mock::Snapshot;
+
+/// Checks the snapshots of a test against their goldens, and collects the mismatches, so that a single run
+/// reports - and writes out - all of them; `assert` then fails the test if there were any.
+#[derive(Default)]
+pub struct Goldens {
+    failures: Vec<String>,
+}
+
+impl Goldens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, name: &str, snapshot: &Snapshot<Color>) {
+        if let Err(failure) = check(name, snapshot) {
+            self.failures.push(failure);
+        }
+    }
+
+    pub fn assert(self) {
+        assert!(
+            self.failures.is_empty(),
+            "{} golden(s) failed:\n{}",
+            self.failures.len(),
+            self.failures.join("\n")
+        );
+    }
+}
+
+fn check(name: &str, snapshot: &Snapshot<Color>) -> Result<(), String> {
+    let rendered = Snapshot::from_pixels(
+        snapshot.size(),
+        snapshot
+            .pixels()
+            .iter()
+            .map(|color| Rgb888::from(*color))
+            .collect(),
+    )
+    .unwrap();
+
+    let golden_path = path(name, "png");
+    let actual_path = path(name, "actual.png");
+
+    if env::var_os("UPDATE_GOLDENS").is_some() {
+        write(&golden_path, &rendered);
+        let _ = fs::remove_file(&actual_path);
+
+        return Ok(());
+    }
+
+    let Some(golden) = read(&golden_path) else {
+        write(&golden_path, &rendered);
+
+        return Err(format!(
+            "{name}: golden missing, written to {}; review it and run again",
+            golden_path.display()
+        ));
+    };
+
+    if golden.size() != rendered.size() {
+        write(&actual_path, &rendered);
+
+        return Err(format!(
+            "{name}: rendered at {:?}, while the golden is {:?}; rendered to {}",
+            rendered.size(),
+            golden.size(),
+            actual_path.display()
+        ));
+    }
+
+    let diff = golden.diff(&rendered).unwrap();
+
+    if diff.is_empty() {
+        let _ = fs::remove_file(&actual_path);
+
+        Ok(())
+    } else {
+        write(&actual_path, &rendered);
+
+        Err(format!(
+            "{name}: {} pixel(s) differ from the golden, within {:?}; rendered to {}",
+            diff.len(),
+            golden.diff_bounds(&rendered).unwrap(),
+            actual_path.display()
+        ))
+    }
+}
+
+fn path(name: &str, extension: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("goldens")
+        .join(format!("{name}.{extension}"))
+}
+
+fn read(path: &Path) -> Option<Snapshot<Rgb888>> {
+    let file = File::open(path).ok()?;
+
+    let mut reader = png::Decoder::new(BufReader::new(file)).read_info().unwrap();
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+
+    assert_eq!(
+        (info.color_type, info.bit_depth),
+        (png::ColorType::Rgb, png::BitDepth::Eight),
+        "{}: not an 8-bit RGB PNG",
+        path.display()
+    );
+
+    let pixels = buf[..info.buffer_size()]
+        .chunks_exact(3)
+        .map(|rgb| Rgb888::new(rgb[0], rgb[1], rgb[2]))
+        .collect();
+
+    Snapshot::from_pixels(Size::new(info.width, info.height), pixels)
+}
+
+fn write(path: &Path, snapshot: &Snapshot<Rgb888>) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+    let Size { width, height } = snapshot.size();
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data = snapshot
+        .pixels()
+        .iter()
+        .flat_map(|rgb| [rgb.r(), rgb.g(), rgb.b()])
+        .collect::<Vec<_>>();
+
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&data)
+        .unwrap();
+}
@@ -0,0 +1,303 @@
+//! A host-side harness running the whole task graph of `ruwm` against mock peripherals.
+//!
+//! Time is driven by the embassy-time mock driver: nothing happens until the test calls `advance`,
+//! which moves the clock forward in `STEP`s and runs the executor until it stalls after each of them.
+
+#![allow(dead_code)]
+
+use std::sync::{Mutex, MutexGuard};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, MockDriver};
+
+use embedded_graphics::prelude::Size;
+
+use embedded_svc::http::server::asynch::Request;
+use embedded_svc::http::Method;
+
+use edge_executor::LocalExecutor;
+
+use channel_bridge::asynch::Mapper;
+
+use ruwm::api;
+use ruwm::battery::{self, BatteryState};
+use ruwm::metrics;
+use ruwm::pulse_counter::{PulseCounter, ReplayPulseCounter};
+use ruwm::screen::Color;
+use ruwm::spawn;
+use ruwm::sse;
+use ruwm::users::{self, Users};
+use ruwm::valve;
+use ruwm::web::{WebEvent, WebRequest};
+use ruwm::wm::{self, PulseTraceEntry, WaterMeterState};
+use ruwm::wm_stats::{self, WaterMeterStatsState};
+use ruwm::{emergency, keepalive, mqtt, wifi};
+
+pub use ruwm_mock::*;
+
+/// The granularity with which `advance` moves the clock
+pub const STEP: Duration = Duration::from_millis(10);
+
+pub const DISPLAY_SIZE: Size = Size::new(128, 128);
+
+/// The number of web clients connected to the system, each with its own web handler
+pub const WEB_CLIENTS: usize = 2;
+
+const WEB_QUEUE_SIZE: usize = 32;
+
+type WebEvents = Channel<CriticalSectionRawMutex, WebEvent, WEB_QUEUE_SIZE>;
+type WebRequests = Channel<CriticalSectionRawMutex, WebRequest, WEB_QUEUE_SIZE>;
+
+// The system state lives in statics, so scenarios cannot run concurrently
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[allow(clippy::declare_interior_mutable_const)]
+const WEB_EVENTS_CHANNEL: WebEvents = Channel::new();
+#[allow(clippy::declare_interior_mutable_const)]
+const WEB_REQUESTS_CHANNEL: WebRequests = Channel::new();
+
+static WEB_EVENTS: [WebEvents; WEB_CLIENTS] = [WEB_EVENTS_CHANNEL; WEB_CLIENTS];
+static WEB_REQUESTS: [WebRequests; WEB_CLIENTS] = [WEB_REQUESTS_CHANNEL; WEB_CLIENTS];
+
+pub struct Harness {
+    executor: LocalExecutor<'static, 64>,
+    pub valve_power: MockOutputPin,
+    pub valve_open: MockOutputPin,
+    pub valve_close: MockOutputPin,
+    pub pulses: MockPulseCounter,
+    pub battery_voltage: MockAdc,
+    pub power: MockInputPin,
+    pub button1: MockInputPin,
+    pub button2: MockInputPin,
+    pub button3: MockInputPin,
+    pub display: MockDisplay,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    /// Resets the system state and spawns the high and low priority tasks, as well as a web handler per client.
+    /// The system is started on mains power, with a full battery.
+    pub fn new() -> Self {
+        let harness = Self::create();
+
+        harness.spawn(harness.pulses.clone());
+
+        harness
+    }
+
+    /// Like `new`, but the pulses - and the arming and disarming of the water meter - come from replaying
+    /// a recorded pulse trace rather than from `pulses`. The replay starts right away.
+    pub fn replay<T>(trace: T) -> Self
+    where
+        T: IntoIterator<Item = PulseTraceEntry>,
+        T::IntoIter: 'static,
+    {
+        let harness = Self::create();
+
+        harness.spawn(ReplayPulseCounter::new(trace));
+
+        harness
+    }
+
+    fn create() -> Self {
+        let lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        valve::STATE.set(None);
+        valve::SOURCE_STATE.set(None);
+        emergency::STATE.set(None);
+        wm::STATE.set(WaterMeterState::new());
+        wm_stats::STATE.set(WaterMeterStatsState::new());
+        battery::STATE.set(BatteryState::new());
+        keepalive::STATE.set(keepalive::RemainingTime::Indefinite);
+        mqtt::STATE.set(None);
+        mqtt::CONFIGURATION.set(None);
+        wifi::STATE.set(None);
+        wifi::CONFIGURATION.set(None);
+        users::STATE.set(Users::new());
+        wm::TRACING.set(false);
+        wm::TRACE.lock(|trace| trace.borrow_mut().clear());
+
+        // Whatever the tasks of the previous scenario left behind
+        ruwm::reset();
+
+        for (events, requests) in WEB_EVENTS.iter().zip(&WEB_REQUESTS) {
+            while events.try_receive().is_ok() {}
+            while requests.try_receive().is_ok() {}
+        }
+
+        Self {
+            executor: LocalExecutor::new(),
+            valve_power: MockOutputPin::new(),
+            valve_open: MockOutputPin::new(),
+            valve_close: MockOutputPin::new(),
+            pulses: MockPulseCounter::new(),
+            battery_voltage: MockAdc::new(BatteryState::MAX_VOLTAGE),
+            power: MockInputPin::new(true),
+            button1: MockInputPin::new(true),
+            button2: MockInputPin::new(true),
+            button3: MockInputPin::new(true),
+            display: MockDisplay::new(DISPLAY_SIZE, Color::Black),
+            _lock: lock,
+        }
+    }
+
+    fn spawn(&self, pulse_counter: impl PulseCounter + 'static) {
+        spawn::high_prio(
+            &self.executor,
+            self.valve_power.clone(),
+            self.valve_open.clone(),
+            self.valve_close.clone(),
+            |_| (),
+            |_| (),
+            pulse_counter,
+            self.pulses.clone(),
+            |_| (),
+            |_| (),
+            self.battery_voltage.clone(),
+            self.power.clone(),
+            false,
+            self.button1.clone(),
+            self.button2.clone(),
+            self.button3.clone(),
+        );
+
+        spawn::low_prio_owned(&self.executor, self.display.clone(), |_| ());
+
+        for (events, requests) in WEB_EVENTS.iter().zip(&WEB_REQUESTS) {
+            spawn::web(
+                &self.executor,
+                events.sender(),
+                Mapper::new(requests.receiver(), |data| Some(Some(data))),
+            );
+        }
+
+        self.run_until_stalled();
+    }
+
+    /// Polls the spawned tasks until none of them can make progress without the clock moving
+    pub fn run_until_stalled(&self) {
+        while self.executor.try_tick() {}
+    }
+
+    /// Moves the clock forward by `duration`, running the tasks after each `STEP`
+    pub fn advance(&self, duration: Duration) {
+        let mut remaining = duration;
+
+        while remaining > Duration::from_ticks(0) {
+            let step = if remaining < STEP { remaining } else { STEP };
+
+            MockDriver::get().advance(step);
+            self.run_until_stalled();
+
+            remaining -= step;
+        }
+    }
+
+    /// Advances the clock until `condition` holds, for at most `timeout`.
+    /// Returns the time it took, or `None` if the condition still does not hold.
+    pub fn advance_until(
+        &self,
+        timeout: Duration,
+        condition: impl Fn() -> bool,
+    ) -> Option<Duration> {
+        let mut elapsed = Duration::from_ticks(0);
+
+        loop {
+            if condition() {
+                break Some(elapsed);
+            }
+
+            if elapsed >= timeout {
+                break None;
+            }
+
+            self.advance(STEP);
+            elapsed += STEP;
+        }
+    }
+
+    /// Advances the clock by `ticks` valve ticks
+    pub fn ticks(&self, ticks: u32) {
+        self.advance(valve::TICK_DELAY * ticks);
+    }
+
+    /// Presses and releases a button, keeping it pressed for `duration`
+    pub fn press(&self, button: &MockInputPin, duration: Duration) {
+        button.set_level(false);
+        self.advance(duration);
+
+        button.set_level(true);
+        self.advance(Duration::from_millis(100));
+    }
+
+    /// Sends a request as the first web client
+    pub fn web_request(&self, request: WebRequest) {
+        self.web_request_from(0, request);
+    }
+
+    /// Drains the events the web handler of the first web client sent so far
+    pub fn web_events(&self) -> Vec<WebEvent> {
+        self.web_events_of(0)
+    }
+
+    pub fn web_request_from(&self, client: usize, request: WebRequest) {
+        WEB_REQUESTS[client].try_send(request).unwrap();
+        self.run_until_stalled();
+    }
+
+    /// Drains the events the web handler of `client` sent so far
+    pub fn web_events_of(&self, client: usize) -> Vec<WebEvent> {
+        let mut events = Vec::new();
+
+        while let Ok(event) = WEB_EVENTS[client].try_receive() {
+            events.push(event);
+        }
+
+        self.run_until_stalled();
+
+        events
+    }
+
+    /// Serves an API request, as the HTTP server would, and returns the response
+    pub fn api_request(
+        &self,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> MockResponse {
+        let mut connection = MockConnection::new(method, uri, headers, body);
+
+        embassy_futures::block_on(api::handle(Request::wrap(&mut connection))).unwrap();
+        self.run_until_stalled();
+
+        connection.response().unwrap()
+    }
+
+    /// Serves a scrape of the metrics, as the HTTP server would, and returns the response
+    pub fn metrics_request(&self, headers: &[(&str, &str)]) -> MockResponse {
+        let mut connection = MockConnection::new(Method::Get, metrics::METRICS_PATH, headers, b"");
+
+        embassy_futures::block_on(metrics::handle(Request::wrap(&mut connection))).unwrap();
+        self.run_until_stalled();
+
+        connection.response().unwrap()
+    }
+
+    /// Opens an event stream, as the HTTP server would; the handler keeps streaming as the clock advances
+    pub fn sse_request(&self, uri: &str, headers: &[(&str, &str)]) -> MockResponseHandle {
+        let mut connection = MockConnection::new(Method::Get, uri, headers, b"");
+        let response = connection.response_handle();
+
+        self.executor
+            .spawn(async move {
+                let _ = sse::handle(Request::wrap(&mut connection)).await;
+            })
+            .detach();
+
+        self.run_until_stalled();
+
+        response
+    }
+}
@@ -0,0 +1,120 @@
+use ruwm::metrics;
+use ruwm::users;
+use ruwm::valve::{self, ValveState};
+use ruwm::web::UserRole;
+use ruwm::wm::{self, WaterMeterState};
+
+use harness::Harness;
+
+mod harness;
+
+/// `viewer:viewer`, as HTTP Basic authentication
+const VIEWER: &str = "Basic dmlld2VyOnZpZXdlcg==";
+
+fn setup() -> Harness {
+    let harness = Harness::new();
+
+    assert!(users::add("admin", "secret", UserRole::Admin));
+    assert!(users::add("viewer", "viewer", UserRole::Viewer));
+
+    harness
+}
+
+fn rendered() -> String {
+    let mut out = String::new();
+    metrics::render(&mut out).unwrap();
+
+    out
+}
+
+/// The sample of a metric without labels, if rendered
+fn sample(metrics: &str, name: &str) -> Option<u64> {
+    metrics
+        .lines()
+        .filter_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .map(|value| value.parse().unwrap())
+        .next()
+}
+
+#[test]
+fn metrics_are_in_the_text_exposition_format() {
+    let _harness = setup();
+
+    let metrics = rendered();
+
+    let mut described = Vec::new();
+
+    for line in metrics.lines() {
+        if let Some(help) = line.strip_prefix("# HELP ") {
+            let (name, _) = help.split_once(' ').unwrap();
+            described.push(name.to_string());
+        } else if let Some(kind) = line.strip_prefix("# TYPE ") {
+            let (name, kind) = kind.split_once(' ').unwrap();
+
+            assert_eq!(described.last().map(String::as_str), Some(name), "{line}");
+            assert!(kind == "counter" || kind == "gauge", "{line}");
+        } else {
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            let name = series.split('{').next().unwrap();
+
+            assert_eq!(described.last().map(String::as_str), Some(name), "{line}");
+            value.parse::<u64>().unwrap();
+        }
+    }
+
+    assert!(described.contains(&"ruwm_water_meter_edges_total".to_string()));
+    assert!(described.contains(&"ruwm_uptime_seconds".to_string()));
+}
+
+#[test]
+fn metrics_follow_the_states() {
+    let _harness = setup();
+
+    wm::STATE.set(WaterMeterState {
+        edges_count: 42,
+        armed: true,
+        leaking: false,
+    });
+
+    let metrics = rendered();
+
+    assert_eq!(sample(&metrics, "ruwm_water_meter_edges_total"), Some(42));
+    assert_eq!(sample(&metrics, "ruwm_water_meter_armed"), Some(1));
+    assert_eq!(sample(&metrics, "ruwm_water_meter_leaking"), Some(0));
+
+    // Unknown states are not rendered, rather than rendered as zero
+    assert!(!metrics.contains("ruwm_valve_open_percent"));
+    assert!(!metrics.contains("ruwm_free_heap_bytes"));
+
+    valve::STATE.set(Some(ValveState::Closing(25)));
+    metrics::set_free_heap_hook(|| 1234);
+
+    let metrics = rendered();
+
+    assert_eq!(sample(&metrics, "ruwm_valve_open_percent"), Some(75));
+    assert_eq!(sample(&metrics, "ruwm_free_heap_bytes"), Some(1234));
+}
+
+#[test]
+fn metrics_are_served_to_viewers() {
+    let harness = setup();
+
+    let response = harness.metrics_request(&[]);
+    assert_eq!(response.status, 401);
+    assert!(response.header("WWW-Authenticate").is_some());
+
+    let response = harness.metrics_request(&[("Authorization", VIEWER)]);
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/plain; version=0.0.4")
+    );
+    assert_eq!(
+        response.header("Content-Length"),
+        Some(response.body.len().to_string().as_str())
+    );
+
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.starts_with("# HELP ruwm_water_meter_edges_total "));
+    assert_eq!(sample(&body, "ruwm_water_meter_edges_total"), Some(0));
+}
@@ -0,0 +1,442 @@
+use embassy_time::Duration;
+
+use ruwm::audit::{self, AuditEvent};
+use ruwm::button::BUTTON3_HOLD_DURATION;
+use ruwm::command::CommandSource;
+use ruwm::emergency;
+use ruwm::valve::{self, ValveCommand, ValveState, TICK_DELAY, TURN_TICKS};
+use ruwm::web::{UserRole, WebEvent, WebRequest};
+use ruwm::wm::{
+    self, PulseTraceEntry, PulseTraceEvent, WaterMeterCommand, PULSE_TRACE_ENCODED_MAX_LEN,
+};
+use ruwm::wm_stats;
+
+use harness::{Harness, STEP};
+
+mod harness;
+
+const TURN_DURATION: Duration = Duration::from_secs(TICK_DELAY.as_secs() * TURN_TICKS as u64);
+
+fn open_valve(harness: &Harness) {
+    valve::command(ValveCommand::Open, CommandSource::Web);
+
+    assert!(harness
+        .advance_until(TURN_DURATION + TICK_DELAY, || {
+            valve::STATE.get() == Some(ValveState::Open)
+        })
+        .is_some());
+}
+
+fn leak(harness: &Harness, pulses: u64) {
+    wm::command(WaterMeterCommand::Arm, CommandSource::Web);
+    harness.run_until_stalled();
+
+    for _ in 0..pulses {
+        harness.pulses.add_pulses(1);
+        harness.advance(STEP);
+    }
+}
+
+/// The valve commands the valve accepted, oldest first
+fn valve_commands() -> Vec<(ValveCommand, CommandSource)> {
+    audit::get()
+        .iter()
+        .filter_map(|entry| match entry.event {
+            AuditEvent::ValveCommand(command, source) => Some((command, source)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The arming and disarming the water meter went through, as recorded in its pulse trace
+fn wm_commands() -> Vec<PulseTraceEvent> {
+    wm::trace()
+        .iter()
+        .map(|entry| entry.event)
+        .filter(|event| !matches!(event, PulseTraceEvent::Edges(_)))
+        .collect()
+}
+
+#[test]
+fn armed_pulses_close_the_valve() {
+    let harness = Harness::new();
+
+    open_valve(&harness);
+
+    // The valve is powered for one more tick after it turned
+    assert!(harness.valve_power.level());
+    harness.ticks(1);
+
+    assert!(harness.valve_open.transitions().contains(&true));
+    assert!(!harness.valve_power.level());
+
+    leak(&harness, 3);
+
+    assert_eq!(harness.pulses.wakeup(), Some(true));
+    assert!(wm::STATE.get().leaking);
+    assert_eq!(wm::STATE.get().edges_count, 3);
+    assert!(emergency::is_lockout());
+
+    assert!(harness.valve_close.level());
+    assert!(harness.valve_power.level());
+
+    assert!(harness
+        .advance_until(TURN_DURATION, || valve::STATE.get()
+            == Some(ValveState::Closed))
+        .is_some());
+
+    assert_eq!(valve::SOURCE_STATE.get(), Some(CommandSource::Emergency));
+
+    harness.ticks(1);
+    assert!(!harness.valve_power.level());
+    assert!(!harness.valve_close.level());
+}
+
+#[test]
+fn disarmed_pulses_do_not_close_the_valve() {
+    let harness = Harness::new();
+
+    open_valve(&harness);
+
+    harness.pulses.add_pulses(3);
+    harness.ticks(2);
+
+    assert_eq!(wm::STATE.get().edges_count, 3);
+    assert!(!wm::STATE.get().leaking);
+    assert!(!emergency::is_lockout());
+    assert_eq!(valve::STATE.get(), Some(ValveState::Open));
+}
+
+#[test]
+fn replayed_pulses_are_counted() {
+    let harness = Harness::new();
+
+    harness.pulses.replay([
+        (Duration::from_secs(1), 1),
+        (Duration::from_secs(2), 2),
+        (Duration::from_secs(5), 1),
+    ]);
+
+    harness.advance(Duration::from_secs(3));
+    assert_eq!(wm::STATE.get().edges_count, 3);
+    assert!(!harness.pulses.is_replayed());
+
+    harness.advance(Duration::from_secs(3));
+    assert_eq!(wm::STATE.get().edges_count, 4);
+    assert!(harness.pulses.is_replayed());
+}
+
+#[test]
+fn recorded_pulse_trace_replays_the_leak() {
+    let harness = Harness::new();
+
+    wm::set_tracing(true);
+
+    harness.pulses.add_pulses(2);
+    harness.advance(Duration::from_secs(1));
+
+    wm::command(WaterMeterCommand::Arm, CommandSource::Web);
+    harness.advance(Duration::from_secs(2));
+
+    harness.pulses.add_pulses(1);
+    harness.advance(STEP);
+
+    assert!(wm::STATE.get().leaking);
+    assert!(emergency::is_lockout());
+
+    let recorded = wm::STATE.get();
+
+    let trace = wm::trace();
+    assert_eq!(
+        trace.iter().map(|entry| entry.event).collect::<Vec<_>>(),
+        [
+            PulseTraceEvent::Edges(2),
+            PulseTraceEvent::Armed,
+            PulseTraceEvent::Edges(1)
+        ]
+    );
+
+    // Replay what a download would return
+    let mut buf = [0; PULSE_TRACE_ENCODED_MAX_LEN];
+    let len = trace.encode(&mut buf);
+
+    let entries = PulseTraceEntry::decode_all(&buf[..len])
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(entries, trace.iter().copied().collect::<Vec<_>>());
+
+    drop(harness);
+
+    let harness = Harness::replay(entries);
+
+    harness.advance(Duration::from_secs(2));
+    assert_eq!(wm::STATE.get().edges_count, 2);
+    assert!(wm::STATE.get().armed);
+    assert!(!wm::STATE.get().leaking);
+
+    harness.advance(Duration::from_secs(2));
+    assert_eq!(wm::STATE.get(), recorded);
+    assert_eq!(
+        wm_stats::STATE.get().most_recent.edges_count,
+        recorded.edges_count
+    );
+    assert!(emergency::is_lockout());
+}
+
+#[test]
+fn restored_pulse_trace_continues_its_timeline() {
+    let harness = Harness::new();
+
+    wm::set_tracing(true);
+
+    harness.pulses.add_pulses(2);
+    harness.advance(Duration::from_secs(1));
+
+    // What a deep sleep of a minute leaves in RTC memory
+    let trace = wm::trace();
+    let slept_ms = 60_000;
+    let time_ms = wm::trace_time_ms() + slept_ms;
+
+    drop(harness);
+
+    let harness = Harness::new();
+
+    wm::restore_trace(true, trace, time_ms);
+    assert!(wm::TRACING.get());
+
+    harness.pulses.add_pulses(1);
+    harness.advance(STEP);
+
+    let entries = wm::trace().iter().copied().collect::<Vec<_>>();
+    assert_eq!(
+        entries.iter().map(|entry| entry.event).collect::<Vec<_>>(),
+        [PulseTraceEvent::Edges(2), PulseTraceEvent::Edges(1)]
+    );
+    assert!(entries[1].time_ms - entries[0].time_ms >= slept_ms);
+}
+
+#[test]
+fn lockout_is_acknowledged_by_holding_button3() {
+    let harness = Harness::new();
+
+    open_valve(&harness);
+    leak(&harness, 1);
+
+    harness.advance(TURN_DURATION + TICK_DELAY);
+    assert_eq!(valve::STATE.get(), Some(ValveState::Closed));
+
+    // Remote commands cannot reopen the valve during the lockout
+    valve::command(ValveCommand::Open, CommandSource::Web);
+    harness.ticks(2);
+    assert_eq!(valve::STATE.get(), Some(ValveState::Closed));
+
+    // Leave the summary page, so that button 3 does not trigger its actions
+    harness.press(&harness.button2, Duration::from_millis(200));
+
+    // Nor can the lockout be acknowledged while the leak is still flagged
+    harness.press(&harness.button3, BUTTON3_HOLD_DURATION + STEP);
+    assert!(emergency::is_lockout());
+
+    wm::command(WaterMeterCommand::Disarm, CommandSource::Web);
+    harness.run_until_stalled();
+    assert!(emergency::STATE.get().unwrap().can_acknowledge());
+
+    // A short press is not enough
+    harness.press(&harness.button3, Duration::from_millis(200));
+    assert!(emergency::is_lockout());
+
+    harness.press(&harness.button3, BUTTON3_HOLD_DURATION + STEP);
+    assert!(!emergency::is_lockout());
+
+    open_valve(&harness);
+}
+
+#[test]
+fn web_admin_arms_the_water_meter() {
+    let harness = Harness::new();
+
+    harness.web_request(WebRequest::SetupAdmin(
+        "admin".try_into().unwrap(),
+        "secret".try_into().unwrap(),
+    ));
+    assert!(harness
+        .web_events()
+        .iter()
+        .any(|event| matches!(event, WebEvent::RoleState(Some(UserRole::Admin), Some(_)))));
+
+    harness.web_request(WebRequest::WaterMeterCommand(WaterMeterCommand::Arm));
+    harness.web_events();
+
+    assert!(wm::STATE.get().armed);
+}
+
+#[test]
+fn button_press_redraws_the_screen() {
+    let harness = Harness::new();
+
+    // The button tasks only take presses once they have seen the buttons released for the debounce duration
+    harness.advance(Duration::from_millis(100));
+
+    let flushes = harness.display.flushes();
+
+    harness.press(&harness.button2, Duration::from_millis(200));
+
+    assert!(harness.display.flushes() > flushes);
+}
+
+#[test]
+fn interleaved_arm_and_disarm_apply_in_order() {
+    let harness = Harness::new();
+
+    wm::set_tracing(true);
+
+    wm::command(WaterMeterCommand::Arm, CommandSource::Button);
+    wm::command(WaterMeterCommand::Disarm, CommandSource::Web);
+    harness.run_until_stalled();
+
+    assert!(!wm::STATE.get().armed);
+    assert_eq!(harness.pulses.wakeup(), Some(false));
+
+    wm::command(WaterMeterCommand::Arm, CommandSource::Mqtt);
+    wm::command(WaterMeterCommand::Disarm, CommandSource::Button);
+    wm::command(WaterMeterCommand::Arm, CommandSource::Web);
+    harness.run_until_stalled();
+
+    assert!(wm::STATE.get().armed);
+    assert_eq!(harness.pulses.wakeup(), Some(true));
+    assert_eq!(
+        wm_commands(),
+        [
+            PulseTraceEvent::Armed,
+            PulseTraceEvent::Disarmed,
+            PulseTraceEvent::Armed,
+            PulseTraceEvent::Disarmed,
+            PulseTraceEvent::Armed,
+        ]
+    );
+}
+
+#[test]
+fn interleaved_open_and_close_apply_in_order() {
+    let harness = Harness::new();
+
+    valve::command(ValveCommand::Open, CommandSource::Button);
+    valve::command(ValveCommand::Close, CommandSource::Mqtt);
+
+    assert!(harness
+        .advance_until(TURN_DURATION + TICK_DELAY * 2, || {
+            valve::STATE.get() == Some(ValveState::Closed)
+        })
+        .is_some());
+    assert_eq!(valve::SOURCE_STATE.get(), Some(CommandSource::Mqtt));
+
+    valve::command(ValveCommand::Close, CommandSource::Web);
+    valve::command(ValveCommand::Open, CommandSource::Button);
+
+    assert!(harness
+        .advance_until(TURN_DURATION + TICK_DELAY * 2, || {
+            valve::STATE.get() == Some(ValveState::Open)
+        })
+        .is_some());
+    assert_eq!(valve::SOURCE_STATE.get(), Some(CommandSource::Button));
+
+    assert_eq!(
+        valve_commands(),
+        [
+            (ValveCommand::Open, CommandSource::Button),
+            (ValveCommand::Close, CommandSource::Mqtt),
+            (ValveCommand::Close, CommandSource::Web),
+            (ValveCommand::Open, CommandSource::Button),
+        ]
+    );
+}
+
+#[test]
+fn repeated_commands_are_coalesced() {
+    let harness = Harness::new();
+
+    wm::set_tracing(true);
+
+    wm::command(WaterMeterCommand::Arm, CommandSource::Web);
+    wm::command(WaterMeterCommand::Arm, CommandSource::Web);
+    // Not identical, as the source differs
+    wm::command(WaterMeterCommand::Arm, CommandSource::Mqtt);
+    harness.run_until_stalled();
+
+    assert_eq!(
+        wm_commands(),
+        [PulseTraceEvent::Armed, PulseTraceEvent::Armed]
+    );
+
+    // Only pending commands are coalesced
+    wm::command(WaterMeterCommand::Arm, CommandSource::Mqtt);
+    harness.run_until_stalled();
+
+    assert_eq!(
+        wm_commands(),
+        [
+            PulseTraceEvent::Armed,
+            PulseTraceEvent::Armed,
+            PulseTraceEvent::Armed
+        ]
+    );
+}
+
+// The command queues hold 4 commands
+
+#[test]
+fn full_queue_evicts_the_oldest_lower_priority_command() {
+    let harness = Harness::new();
+
+    wm::set_tracing(true);
+
+    wm::command(WaterMeterCommand::Arm, CommandSource::Web);
+    wm::command(WaterMeterCommand::Disarm, CommandSource::Web);
+    wm::command(WaterMeterCommand::Arm, CommandSource::Mqtt);
+    wm::command(WaterMeterCommand::Disarm, CommandSource::Mqtt);
+
+    // Dropped, as all queued commands are of the same priority
+    wm::command(WaterMeterCommand::Arm, CommandSource::Api);
+
+    // Evicts the first remote command
+    wm::command(WaterMeterCommand::Arm, CommandSource::Button);
+
+    harness.run_until_stalled();
+
+    assert!(wm::STATE.get().armed);
+    assert_eq!(
+        wm_commands(),
+        [
+            PulseTraceEvent::Disarmed,
+            PulseTraceEvent::Armed,
+            PulseTraceEvent::Disarmed,
+            PulseTraceEvent::Armed,
+        ]
+    );
+}
+
+#[test]
+fn full_queue_keeps_the_emergency_close() {
+    let harness = Harness::new();
+
+    valve::command(ValveCommand::Close, CommandSource::Emergency);
+    valve::command(ValveCommand::Open, CommandSource::Web);
+    valve::command(ValveCommand::Close, CommandSource::Mqtt);
+    valve::command(ValveCommand::Open, CommandSource::Api);
+
+    // Neither evicts the emergency close
+    valve::command(ValveCommand::Close, CommandSource::Web);
+    valve::command(ValveCommand::Open, CommandSource::Button);
+
+    harness.run_until_stalled();
+
+    assert_eq!(
+        valve_commands(),
+        [
+            (ValveCommand::Close, CommandSource::Emergency),
+            (ValveCommand::Close, CommandSource::Mqtt),
+            (ValveCommand::Open, CommandSource::Api),
+            (ValveCommand::Open, CommandSource::Button),
+        ]
+    );
+}
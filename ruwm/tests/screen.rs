@@ -0,0 +1,287 @@
+use embassy_time::Duration;
+
+use embedded_graphics::prelude::Size;
+
+use ruwm::battery::BatteryState;
+use ruwm::emergency::{LockoutReason, LockoutState};
+use ruwm::keepalive::RemainingTime;
+use ruwm::screen::pages::{Battery, Summary};
+use ruwm::screen::shapes::{self, Action};
+use ruwm::screen::Color;
+use ruwm::valve::ValveState;
+use ruwm::wm::WaterMeterState;
+
+use golden::Goldens;
+use harness::{Harness, MockDisplay, Snapshot};
+
+mod golden;
+mod harness;
+
+const SIZES: [Size; 2] = [Size::new(128, 128), Size::new(320, 240)];
+
+const FULL_POWERED: BatteryState = BatteryState {
+    voltage: Some(BatteryState::MAX_VOLTAGE),
+    powered: Some(true),
+};
+
+const LOW: BatteryState = BatteryState {
+    voltage: Some(BatteryState::LOW_VOLTAGE),
+    powered: Some(false),
+};
+
+struct SummaryCase {
+    name: &'static str,
+    valve: Option<ValveState>,
+    wm: WaterMeterState,
+    battery: BatteryState,
+    remaining_time: RemainingTime,
+    lockout: Option<LockoutState>,
+}
+
+fn render(size: Size, draw: impl FnOnce(&mut MockDisplay)) -> Snapshot<Color> {
+    let mut display = MockDisplay::new(size, Color::Black);
+
+    draw(&mut display);
+
+    display.drawn()
+}
+
+fn name(prefix: &str, case: &str, size: Size) -> String {
+    format!("{prefix}_{case}_{}x{}", size.width, size.height)
+}
+
+#[test]
+fn summary_page() {
+    let mut goldens = Goldens::new();
+
+    let cases = [
+        SummaryCase {
+            name: "open",
+            valve: Some(ValveState::Open),
+            wm: WaterMeterState {
+                edges_count: 1234,
+                armed: false,
+                leaking: false,
+            },
+            battery: FULL_POWERED,
+            remaining_time: RemainingTime::Indefinite,
+            lockout: None,
+        },
+        SummaryCase {
+            name: "closing_40",
+            valve: Some(ValveState::Closing(40)),
+            wm: WaterMeterState {
+                edges_count: 1234,
+                armed: true,
+                leaking: false,
+            },
+            battery: FULL_POWERED,
+            remaining_time: RemainingTime::Indefinite,
+            lockout: None,
+        },
+        SummaryCase {
+            name: "closed_leaking",
+            valve: Some(ValveState::Closed),
+            wm: WaterMeterState {
+                edges_count: 1240,
+                armed: true,
+                leaking: true,
+            },
+            battery: FULL_POWERED,
+            remaining_time: RemainingTime::Indefinite,
+            lockout: Some(LockoutState {
+                reason: LockoutReason::Leak,
+                condition_present: true,
+            }),
+        },
+        SummaryCase {
+            name: "unknown_battery_low",
+            valve: None,
+            wm: WaterMeterState::new(),
+            battery: LOW,
+            remaining_time: RemainingTime::Duration(Duration::from_secs(45)),
+            lockout: Some(LockoutState {
+                reason: LockoutReason::BatteryLow,
+                condition_present: false,
+            }),
+        },
+    ];
+
+    for size in SIZES {
+        for case in &cases {
+            let snapshot = render(size, |display| {
+                Summary::draw(
+                    display,
+                    true,
+                    Some(&case.valve),
+                    Some(&case.wm),
+                    Some(&case.battery),
+                    Some(&case.remaining_time),
+                    Some(&case.lockout),
+                )
+                .unwrap()
+            });
+
+            goldens.check(&name("page_summary", case.name, size), &snapshot);
+        }
+    }
+
+    goldens.assert();
+}
+
+#[test]
+fn battery_page() {
+    let mut goldens = Goldens::new();
+
+    let cases = [
+        ("full_powered", FULL_POWERED),
+        (
+            "half",
+            BatteryState {
+                voltage: Some((BatteryState::LOW_VOLTAGE + BatteryState::MAX_VOLTAGE) / 2),
+                powered: Some(false),
+            },
+        ),
+        ("low", LOW),
+        ("unmeasured", BatteryState::new()),
+    ];
+
+    for size in SIZES {
+        for (case, battery) in &cases {
+            let snapshot = render(size, |display| {
+                Battery::draw(display, true, Some(battery)).unwrap()
+            });
+
+            goldens.check(&name("page_battery", case, size), &snapshot);
+        }
+    }
+
+    goldens.assert();
+}
+
+#[test]
+fn shapes() {
+    let mut goldens = Goldens::new();
+
+    for (case, open_percentage) in [
+        ("unknown", None),
+        ("closed", Some(0)),
+        ("40", Some(40)),
+        ("open", Some(100)),
+    ] {
+        let size = Size::new(64, 64);
+
+        let snapshot = render(size, |display| {
+            shapes::Valve {
+                open_percentage,
+                ..Default::default()
+            }
+            .draw(display)
+            .unwrap()
+        });
+
+        goldens.check(&name("shape_valve", case, size), &snapshot);
+    }
+
+    for (case, charged_percentage) in [
+        ("unknown", None),
+        ("low", Some(5)),
+        ("half", Some(50)),
+        ("full", Some(100)),
+    ] {
+        let size = Size::new(80, 128);
+
+        let snapshot = render(size, |display| {
+            shapes::Battery {
+                charged_percentage,
+                ..Default::default()
+            }
+            .draw(display)
+            .unwrap()
+        });
+
+        goldens.check(&name("shape_battery", case, size), &snapshot);
+    }
+
+    for (case, edges_count) in [("unknown", None), ("counted", Some(12345678))] {
+        let wm = shapes::WaterMeterClassic::<8> {
+            edges_count,
+            ..Default::default()
+        };
+
+        let size = wm.preferred_size();
+
+        let snapshot = render(size, |display| wm.draw(display).unwrap());
+
+        goldens.check(&name("shape_wm", case, size), &snapshot);
+    }
+
+    for (case, strength) in [("unknown", None), ("60", Some(60))] {
+        let size = Size::new(24, 32);
+
+        let snapshot = render(size, |display| {
+            shapes::Wifi {
+                strength,
+                ..Default::default()
+            }
+            .draw(display)
+            .unwrap()
+        });
+
+        goldens.check(&name("shape_wifi", case, size), &snapshot);
+    }
+
+    for (case, strikethrough) in [("plain", false), ("strikethrough", true)] {
+        let textbox = shapes::Textbox {
+            text: "LOCKOUT",
+            color: Color::Red,
+            strikethrough,
+            ..Default::default()
+        };
+
+        let size = textbox.preferred_size();
+
+        let snapshot = render(size, |display| textbox.draw(display).unwrap());
+
+        goldens.check(&name("shape_textbox", case, size), &snapshot);
+    }
+
+    let actions = shapes::Actions {
+        enabled: Action::OpenValve | Action::CloseValve | Action::Dismiss,
+        selected: Action::CloseValve,
+        ..Default::default()
+    };
+
+    // As laid out by the actions page, which only takes the height from the shape
+    let size = Size::new(118, actions.preferred_size().height);
+
+    let snapshot = render(size, |display| actions.draw(display).unwrap());
+
+    goldens.check(&name("shape_actions", "valve", size), &snapshot);
+
+    goldens.assert();
+}
+
+/// The whole of `screen::draw`, as driven by the running system
+#[test]
+fn screen() {
+    let harness = Harness::new();
+    let mut goldens = Goldens::new();
+
+    // Nothing is drawn before the first change of a state, i.e. the first reading of the battery
+    harness.advance(Duration::from_secs(3));
+
+    goldens.check(
+        &name("screen", "boot", harness::DISPLAY_SIZE),
+        &harness.display.flushed(),
+    );
+
+    harness.press(&harness.button2, Duration::from_millis(200));
+
+    goldens.check(
+        &name("screen", "battery", harness::DISPLAY_SIZE),
+        &harness.display.flushed(),
+    );
+
+    goldens.assert();
+}
@@ -0,0 +1,123 @@
+use embassy_time::Duration;
+
+use ruwm::sse::KEEPALIVE_PERIOD;
+use ruwm::users;
+use ruwm::valve::{self, ValveState};
+use ruwm::web::UserRole;
+
+use harness::{Harness, MockResponseHandle};
+
+mod harness;
+
+const VIEWER: (&str, &str) = ("viewer", "viewer");
+
+fn setup() -> (Harness, String) {
+    let harness = Harness::new();
+
+    assert!(users::add("admin", "secret", UserRole::Admin));
+    assert!(users::add(VIEWER.0, VIEWER.1, UserRole::Viewer));
+
+    let token = users::issue_session(VIEWER.0).unwrap().to_string();
+
+    (harness, token)
+}
+
+/// The body streamed so far
+fn streamed(response: &MockResponseHandle) -> String {
+    String::from_utf8(response.get().unwrap().body).unwrap()
+}
+
+#[test]
+fn clients_without_a_session_are_challenged() {
+    let (harness, token) = setup();
+
+    for authorization in ["", "Bearer nonsense", "Basic dmlld2VyOnZpZXdlcg=="] {
+        let headers = [("Authorization", authorization)];
+        let headers = if authorization.is_empty() {
+            &headers[..0]
+        } else {
+            &headers[..]
+        };
+
+        let response = harness.sse_request("/events", headers).get().unwrap();
+
+        assert_eq!(response.status, 401, "{authorization}");
+        assert!(response.header("WWW-Authenticate").is_some());
+    }
+
+    users::revoke_session(&token.parse().unwrap());
+
+    let response = harness
+        .sse_request(&format!("/events?token={token}"), &[])
+        .get()
+        .unwrap();
+    assert_eq!(response.status, 401);
+}
+
+#[test]
+fn states_are_streamed_as_events() {
+    let (harness, token) = setup();
+
+    let response = harness.sse_request("/events", &[("Authorization", &format!("Bearer {token}"))]);
+
+    let head = response.get().unwrap();
+    assert_eq!(head.status, 200);
+    assert_eq!(head.header("Content-Type"), Some("text/event-stream"));
+
+    let body = streamed(&response);
+    assert!(
+        body.starts_with("event: RoleState\ndata: \"Viewer\"\n\n"),
+        "{body}"
+    );
+    assert!(body.contains("event: ValveState\ndata: null\n\n"), "{body}");
+
+    // Neither the session token, nor the settings are streamed
+    assert!(!body.contains(&token), "{body}");
+    assert!(!body.contains("WifiSettingsState"), "{body}");
+
+    let len = body.len();
+
+    valve::STATE.set(Some(ValveState::Open));
+    harness.run_until_stalled();
+
+    let body = streamed(&response);
+    assert!(
+        body[len..].contains("event: ValveState\ndata: \"Open\"\n\n"),
+        "{body}"
+    );
+}
+
+#[test]
+fn token_is_accepted_as_a_query_parameter() {
+    let (harness, token) = setup();
+
+    for uri in [
+        format!("/events?token={token}"),
+        format!("/events?x=1&token={}", token.replace(':', "%3A")),
+    ] {
+        let response = harness.sse_request(&uri, &[]);
+
+        assert_eq!(response.get().unwrap().status, 200, "{uri}");
+        assert!(streamed(&response).starts_with("event: RoleState\n"));
+    }
+}
+
+#[test]
+fn stream_is_kept_alive_and_ends_with_the_session() {
+    let (harness, token) = setup();
+
+    let response = harness.sse_request(&format!("/events?token={token}"), &[]);
+
+    harness.advance(KEEPALIVE_PERIOD + Duration::from_secs(1));
+    assert!(streamed(&response).ends_with("\n\n: keepalive\n\n"));
+
+    users::revoke_session(&token.parse().unwrap());
+    harness.advance(KEEPALIVE_PERIOD + Duration::from_secs(1));
+
+    let len = streamed(&response).len();
+
+    valve::STATE.set(Some(ValveState::Closed));
+    harness.advance(KEEPALIVE_PERIOD + Duration::from_secs(1));
+
+    assert_eq!(streamed(&response).len(), len);
+}
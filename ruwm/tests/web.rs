@@ -0,0 +1,329 @@
+use core::mem::discriminant;
+
+use embassy_time::Duration;
+
+use embedded_svc::wifi::{ClientConfiguration, Configuration};
+
+use ruwm::battery::{self, BatteryState};
+use ruwm::command::CommandSource;
+use ruwm::emergency::{self, LockoutReason, LockoutState};
+use ruwm::keepalive::{self, RemainingTime};
+use ruwm::users::{self, LOCKOUT_DURATION, MAX_FAILED_ATTEMPTS};
+use ruwm::valve::{self, ValveState};
+use ruwm::web::{SessionToken, UserRole, WebEvent, WebRequest, WifiSettings};
+use ruwm::wm::{self, WaterMeterCommand, WaterMeterState};
+use ruwm::{mqtt, wifi, wm_stats};
+
+use harness::{Harness, WEB_CLIENTS};
+
+mod harness;
+
+const ADMIN: &str = "admin";
+const ADMIN_PASSWORD: &str = "secret";
+
+fn setup(harness: &Harness) -> SessionToken {
+    harness.web_request(WebRequest::SetupAdmin(
+        ADMIN.try_into().unwrap(),
+        ADMIN_PASSWORD.try_into().unwrap(),
+    ));
+
+    let (role, token) = role_state(&harness.web_events()).unwrap();
+    assert_eq!(role, Some(UserRole::Admin));
+
+    token.unwrap()
+}
+
+fn authenticate(harness: &Harness, username: &str, password: &str) -> Vec<WebEvent> {
+    authenticate_client(harness, 0, username, password)
+}
+
+fn authenticate_client(
+    harness: &Harness,
+    client: usize,
+    username: &str,
+    password: &str,
+) -> Vec<WebEvent> {
+    harness.web_request_from(
+        client,
+        WebRequest::Authenticate(username.try_into().unwrap(), password.try_into().unwrap()),
+    );
+
+    harness.web_events_of(client)
+}
+
+/// The last role reported to the client
+fn role_state(events: &[WebEvent]) -> Option<(Option<UserRole>, Option<SessionToken>)> {
+    events.iter().rev().find_map(|event| match event {
+        WebEvent::RoleState(role, token) => Some((*role, *token)),
+        _ => None,
+    })
+}
+
+fn is_failed(events: &[WebEvent]) -> bool {
+    events.contains(&WebEvent::AuthenticationFailed)
+}
+
+/// Changes a state, and checks that every client ends up with its current value
+fn assert_streamed(
+    harness: &Harness,
+    update: impl FnOnce() -> bool,
+    current: impl Fn() -> WebEvent,
+) {
+    assert!(update());
+
+    harness.run_until_stalled();
+
+    let current = current();
+
+    for client in 0..WEB_CLIENTS {
+        let events = harness.web_events_of(client);
+
+        let last = events
+            .iter()
+            .rev()
+            .find(|event| discriminant(*event) == discriminant(&current));
+
+        assert_eq!(last, Some(&current), "client {client}");
+    }
+}
+
+#[test]
+fn login_with_valid_credentials_only() {
+    let harness = Harness::new();
+
+    setup(&harness);
+
+    harness.web_request(WebRequest::Logout);
+    assert_eq!(role_state(&harness.web_events()), Some((None, None)));
+
+    let events = authenticate(&harness, ADMIN, "wrong");
+    assert!(is_failed(&events));
+    assert!(!events
+        .iter()
+        .any(|event| matches!(event, WebEvent::WaterMeterState(_))));
+
+    let events = authenticate(&harness, "nobody", ADMIN_PASSWORD);
+    assert!(is_failed(&events));
+
+    let events = authenticate(&harness, ADMIN, ADMIN_PASSWORD);
+    assert!(!is_failed(&events));
+
+    let (role, token) = role_state(&events).unwrap();
+    assert_eq!(role, Some(UserRole::Admin));
+    assert!(token.is_some());
+}
+
+#[test]
+fn roles_are_enforced() {
+    let harness = Harness::new();
+
+    setup(&harness);
+
+    assert!(users::add("viewer", "viewer", UserRole::Viewer));
+    assert!(users::add("user", "user", UserRole::User));
+
+    // Viewers see the state, but cannot change it
+    let events = authenticate(&harness, "viewer", "viewer");
+    assert_eq!(
+        role_state(&events).map(|(role, _)| role),
+        Some(Some(UserRole::Viewer))
+    );
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, WebEvent::WaterMeterState(_))));
+
+    harness.web_request(WebRequest::WaterMeterCommand(WaterMeterCommand::Arm));
+    harness.web_events();
+    assert!(!wm::STATE.get().armed);
+
+    harness.web_request(WebRequest::GetEvents);
+    assert!(harness
+        .web_events()
+        .iter()
+        .any(|event| matches!(event, WebEvent::AuditLog(_))));
+
+    // Users can, but cannot manage the accounts
+    authenticate(&harness, "user", "user");
+
+    harness.web_request(WebRequest::WaterMeterCommand(WaterMeterCommand::Arm));
+    harness.web_events();
+    assert!(wm::STATE.get().armed);
+
+    harness.web_request(WebRequest::RemoveUser("viewer".try_into().unwrap()));
+    harness.web_events();
+    assert!(users::STATE.get().get("viewer").is_some());
+
+    // Admins can
+    authenticate(&harness, ADMIN, ADMIN_PASSWORD);
+
+    harness.web_request(WebRequest::RemoveUser("viewer".try_into().unwrap()));
+    harness.web_events();
+    assert!(users::STATE.get().get("viewer").is_none());
+}
+
+#[test]
+fn lockout_is_per_username() {
+    let harness = Harness::new();
+
+    setup(&harness);
+
+    assert!(users::add("user", "user", UserRole::User));
+
+    for _ in 0..MAX_FAILED_ATTEMPTS {
+        assert!(is_failed(&authenticate(&harness, ADMIN, "wrong")));
+    }
+
+    // Locked out, even with the right password
+    assert!(is_failed(&authenticate(&harness, ADMIN, ADMIN_PASSWORD)));
+
+    // Other users are not
+    assert!(!is_failed(&authenticate(&harness, "user", "user")));
+
+    harness.advance(LOCKOUT_DURATION + Duration::from_secs(1));
+
+    assert!(!is_failed(&authenticate(&harness, ADMIN, ADMIN_PASSWORD)));
+}
+
+#[test]
+fn failed_logins_of_unknown_users_do_not_lock_out() {
+    let harness = Harness::new();
+
+    setup(&harness);
+
+    for index in 0..MAX_FAILED_ATTEMPTS as usize * 4 {
+        let username = format!("nobody{index}");
+
+        assert!(is_failed(&authenticate(&harness, &username, "wrong")));
+    }
+
+    assert!(!is_failed(&authenticate(&harness, ADMIN, ADMIN_PASSWORD)));
+}
+
+#[test]
+fn password_change_revokes_the_sessions() {
+    let harness = Harness::new();
+
+    let token = setup(&harness);
+
+    harness.web_request(WebRequest::ChangePassword(
+        "wrong".try_into().unwrap(),
+        "changed".try_into().unwrap(),
+    ));
+    assert!(harness.web_events().contains(&WebEvent::RequestFailed));
+
+    harness.web_request(WebRequest::ChangePassword(
+        ADMIN_PASSWORD.try_into().unwrap(),
+        "changed".try_into().unwrap(),
+    ));
+
+    let (role, new_token) = role_state(&harness.web_events()).unwrap();
+    assert_eq!(role, Some(UserRole::Admin));
+    assert_ne!(new_token, Some(token));
+
+    // The session started with the old password is gone, the one of this connection is not
+    assert!(users::resume_session(&token).is_none());
+    assert!(users::resume_session(&new_token.unwrap()).is_some());
+
+    harness.web_request(WebRequest::Logout);
+    harness.web_events();
+
+    assert!(is_failed(&authenticate(&harness, ADMIN, ADMIN_PASSWORD)));
+    assert!(!is_failed(&authenticate(&harness, ADMIN, "changed")));
+}
+
+#[test]
+fn state_changes_reach_every_client() {
+    let harness = Harness::new();
+
+    setup(&harness);
+
+    for client in 1..WEB_CLIENTS {
+        assert!(!is_failed(&authenticate_client(
+            &harness,
+            client,
+            ADMIN,
+            ADMIN_PASSWORD
+        )));
+    }
+
+    for client in 0..WEB_CLIENTS {
+        harness.web_events_of(client);
+    }
+
+    assert_streamed(
+        &harness,
+        || valve::STATE.update(Some(ValveState::Open)),
+        || WebEvent::ValveState(valve::STATE.get()),
+    );
+    assert_streamed(
+        &harness,
+        || valve::SOURCE_STATE.update(Some(CommandSource::Api)),
+        || WebEvent::ValveSourceState(valve::SOURCE_STATE.get()),
+    );
+    assert_streamed(
+        &harness,
+        || {
+            emergency::STATE.update(Some(LockoutState {
+                reason: LockoutReason::Leak,
+                condition_present: false,
+            }))
+        },
+        || WebEvent::LockoutState(emergency::STATE.get()),
+    );
+    assert_streamed(
+        &harness,
+        || {
+            wm::STATE.update(WaterMeterState {
+                edges_count: 42,
+                ..WaterMeterState::new()
+            })
+        },
+        || WebEvent::WaterMeterState(wm::STATE.get()),
+    );
+    assert_streamed(
+        &harness,
+        || {
+            wm_stats::STATE.update_with(|mut stats| {
+                stats.most_recent.edges_count += 1;
+                stats
+            })
+        },
+        || WebEvent::WaterMeterStatsState(wm_stats::STATE.get()),
+    );
+    assert_streamed(
+        &harness,
+        || {
+            battery::STATE.update(BatteryState {
+                voltage: Some(BatteryState::LOW_VOLTAGE + 100),
+                powered: Some(true),
+            })
+        },
+        || WebEvent::BatteryState(battery::STATE.get()),
+    );
+    assert_streamed(
+        &harness,
+        || keepalive::STATE.update(RemainingTime::Duration(Duration::from_secs(10))),
+        || WebEvent::RemainingTimeState(Some(10)),
+    );
+    assert_streamed(
+        &harness,
+        || mqtt::STATE.update(Some(true)),
+        || WebEvent::MqttState(mqtt::STATE.get()),
+    );
+    assert_streamed(
+        &harness,
+        || wifi::STATE.update(Some(true)),
+        || WebEvent::WifiState(wifi::STATE.get()),
+    );
+
+    let configuration = Configuration::Client(ClientConfiguration {
+        ssid: "home".try_into().unwrap(),
+        ..Default::default()
+    });
+
+    assert_streamed(
+        &harness,
+        || wifi::CONFIGURATION.update(Some(configuration.clone())),
+        || WebEvent::WifiSettingsState(WifiSettings::new(&configuration)),
+    );
+}